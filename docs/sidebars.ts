@@ -14,6 +14,7 @@ const sidebars: SidebarsConfig = {
         'gateway/overview',
         'gateway/routing',
         'gateway/rewrite-html',
+        'gateway/body-rewriting',
         'gateway/virtual-hosts',
       ],
     },
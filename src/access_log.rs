@@ -0,0 +1,417 @@
+//! `--access-log` and `--log-format`: where `serve` and `proxy` write their
+//! request log, and in what shape.
+//!
+//! `dev` is the compact, colored line trillium has always printed. `combined`
+//! is Apache's combined log format followed by two fields — the response time
+//! in seconds and the upstream URL a forwarded response came from (`-` when it
+//! was answered locally), as nginx configs commonly append — so tools that read
+//! the combined format keep working. `json` writes one object per line with the
+//! same information by name.
+//!
+//! For those two, the bytes are counted as the body is sent, after
+//! compression: they're what crossed the wire, not the file's length. Every
+//! line is written once its response has been sent.
+
+use clap::{Parser, ValueEnum};
+use futures_lite::io::AsyncRead;
+use serde_json::json;
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use trillium::{
+    Body, Conn, Handler, Info,
+    KnownHeaderName::{Referer, UserAgent},
+    Method, Status, Version,
+};
+use trillium_logger::{ColorMode, LogFormatter, Logger, dev_formatter};
+use trillium_proxy::{Url, upstream::UpstreamSelector};
+
+/// The `--access-log` and `--log-format` flags, flattened into `serve`'s and
+/// `proxy`'s args.
+#[derive(Parser, Debug, Clone)]
+pub struct AccessLog {
+    /// Append the request log to PATH instead of printing it
+    #[arg(long, env, value_name = "PATH", help_heading = "Logging")]
+    access_log: Option<PathBuf>,
+
+    /// Request log format
+    ///
+    /// `dev` is a short colored line for a terminal. `combined` is Apache's
+    /// combined format plus the response time in seconds and the upstream a
+    /// forwarded response came from. `json` is one object per line, with the
+    /// bytes sent, the duration and the upstream by name.
+    #[arg(
+        long,
+        env,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t,
+        help_heading = "Logging"
+    )]
+    log_format: LogFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq)]
+enum LogFormat {
+    #[default]
+    Dev,
+    Combined,
+    Json,
+}
+
+/// The upstream a forwarded response came from, left on the conn by
+/// [`Recorded`].
+#[derive(Debug, Clone)]
+pub struct Upstream(pub Url);
+
+/// An [`UpstreamSelector`] that records the upstream it picks, so the log can
+/// tell forwarded responses from local ones.
+#[derive(Debug)]
+pub struct Recorded<U>(pub U);
+
+impl<U: UpstreamSelector> UpstreamSelector for Recorded<U> {
+    fn determine_upstream(&self, conn: &mut Conn) -> Option<Url> {
+        let url = self.0.determine_upstream(conn)?;
+        conn.insert_state(Upstream(url.clone()));
+        Some(url)
+    }
+}
+
+impl AccessLog {
+    /// Open the log file, if there is one, exiting if it can't be written.
+    pub fn open(&self) -> RequestLog {
+        let sink = match &self.access_log {
+            None => Sink::Stdout,
+            Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Sink::File(Mutex::new(file)),
+                Err(e) => {
+                    eprintln!("could not open access log {}: {e}", path.display());
+                    std::process::exit(1);
+                }
+            },
+        };
+        RequestLog {
+            sink: Arc::new(sink),
+            format: self.log_format,
+        }
+    }
+}
+
+/// Where log lines go.
+#[derive(Debug)]
+enum Sink {
+    Stdout,
+    File(Mutex<File>),
+}
+
+impl Sink {
+    fn write(&self, line: &str) {
+        match self {
+            Sink::Stdout => println!("{line}"),
+            Sink::File(file) => {
+                let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                if let Err(e) = writeln!(file, "{line}") {
+                    log::error!("could not write to the access log: {e}");
+                }
+            }
+        }
+    }
+}
+
+/// An opened `--access-log`, ready to hand out handlers.
+#[derive(Debug)]
+pub struct RequestLog {
+    sink: Arc<Sink>,
+    format: LogFormat,
+}
+
+impl RequestLog {
+    fn is_file(&self) -> bool {
+        matches!(*self.sink, Sink::File(_))
+    }
+
+    fn target(&self) -> impl Fn(String) + Send + Sync + 'static {
+        let sink = Arc::clone(&self.sink);
+        move |line: String| sink.write(&line)
+    }
+
+    /// The request logger. `prefix` leads each `dev` line, as `proxy`'s `<- `
+    /// does.
+    pub fn handler(&self, prefix: &'static str) -> impl Handler {
+        let logger = Logger::new()
+            .with_formatter(Format {
+                format: self.format,
+                prefix,
+            })
+            .with_target(self.target())
+            .with_color_mode(if self.is_file() {
+                ColorMode::Off
+            } else {
+                ColorMode::Auto
+            });
+
+        // The startup banner belongs in a terminal, not in a log file or a
+        // stream of combined or json lines: it goes to stdout, or to stderr
+        // when stdout carries the log.
+        let to_stderr = !self.is_file();
+        let (banner, logger) = if self.is_file() || self.format != LogFormat::Dev {
+            let banner = Logger::new().with_target(move |banner: String| {
+                if to_stderr {
+                    eprintln!("{banner}");
+                } else {
+                    println!("{banner}");
+                }
+            });
+            (Some(Banner(banner)), logger.without_init_message())
+        } else {
+            (None, logger)
+        };
+
+        // After the logger, so its `before_send` runs first and the count is
+        // in place when the line is formatted.
+        (
+            banner,
+            logger,
+            (self.format != LogFormat::Dev).then_some(CountBytes),
+        )
+    }
+
+    /// `proxy`'s outbound `-> ` lines, which only the `dev` format has.
+    #[cfg(feature = "proxy")]
+    pub fn client_logger(
+        &self,
+    ) -> Option<
+        trillium_logger::client::ClientLogger<impl trillium_logger::client::ClientLogFormatter>,
+    > {
+        use trillium_logger::client::{ClientLogger, dev_formatter};
+        (self.format == LogFormat::Dev).then(|| {
+            ClientLogger::new()
+                .with_formatter(("-> ", dev_formatter))
+                .with_target(self.target())
+                .with_color_mode(if self.is_file() {
+                    ColorMode::Off
+                } else {
+                    ColorMode::Auto
+                })
+        })
+    }
+}
+
+/// Runs only a logger's `init`, which prints the startup banner to its
+/// target; never marks a conn, so never logs one.
+struct Banner<H>(H);
+
+impl<H: Handler> Handler for Banner<H> {
+    async fn init(&mut self, info: &mut Info) {
+        self.0.init(info).await;
+    }
+
+    async fn run(&self, conn: Conn) -> Conn {
+        conn
+    }
+}
+
+/// The bytes of a response body sent so far.
+#[derive(Debug, Clone, Default)]
+struct Sent(Arc<AtomicU64>);
+
+/// Counts the response body's bytes as they're sent.
+#[derive(Debug, Clone, Copy)]
+struct CountBytes;
+
+impl Handler for CountBytes {
+    async fn run(&self, conn: Conn) -> Conn {
+        conn
+    }
+
+    async fn before_send(&self, mut conn: Conn) -> Conn {
+        let sent = Sent::default();
+        if let Some(body) = conn.take_response_body() {
+            let len = body.len();
+            let reader = Counted {
+                inner: body.into_reader(),
+                sent: Arc::clone(&sent.0),
+            };
+            conn.set_body(Body::new_streaming(reader, len));
+        }
+        conn.with_state(sent)
+    }
+}
+
+struct Counted {
+    inner: Pin<Box<dyn AsyncRead + Send + Sync + 'static>>,
+    sent: Arc<AtomicU64>,
+}
+
+impl AsyncRead for Counted {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = self.inner.as_mut().poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = poll {
+            self.sent.fetch_add(read as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+struct Format {
+    format: LogFormat,
+    prefix: &'static str,
+}
+
+impl LogFormatter for Format {
+    type Output = Line;
+
+    fn format(&self, conn: &Conn, color: bool) -> Line {
+        match self.format {
+            LogFormat::Dev => Line::Dev(self.prefix, Box::new(dev_formatter(conn, color))),
+            format => Line::Entry(Box::new(Entry::new(conn, format))),
+        }
+    }
+}
+
+/// A log line, formatted once its response has been sent.
+enum Line {
+    Dev(&'static str, Box<dyn Display + Send + Sync>),
+    Entry(Box<Entry>),
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Dev(prefix, line) => write!(f, "{prefix}{line}"),
+            Line::Entry(entry) => entry.fmt(f),
+        }
+    }
+}
+
+/// What a `combined` or `json` line records, gathered before the response is
+/// sent; the duration and byte count are read when it's written.
+struct Entry {
+    format: LogFormat,
+    time: OffsetDateTime,
+    start: Instant,
+    ip: Option<IpAddr>,
+    method: Method,
+    target: String,
+    version: Version,
+    host: Option<String>,
+    status: Status,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    upstream: Option<String>,
+    sent: Option<Arc<AtomicU64>>,
+}
+
+impl Entry {
+    fn new(conn: &Conn, format: LogFormat) -> Self {
+        let header = |name| conn.request_headers().get_str(name).map(str::to_string);
+        Self {
+            format,
+            time: OffsetDateTime::now_utc(),
+            start: conn.start_time(),
+            ip: conn.peer_ip(),
+            method: conn.method(),
+            target: match conn.querystring() {
+                "" => conn.path().to_string(),
+                query => format!("{}?{query}", conn.path()),
+            },
+            version: conn.http_version(),
+            host: conn.host().map(str::to_string),
+            // A conn nothing answered is sent as a 404.
+            status: conn.status().unwrap_or(Status::NotFound),
+            referer: header(Referer),
+            user_agent: header(UserAgent),
+            upstream: conn
+                .state::<Upstream>()
+                .map(|upstream| upstream.0.to_string()),
+            sent: conn.state::<Sent>().map(|sent| Arc::clone(&sent.0)),
+        }
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let duration = self.start.elapsed().as_secs_f64();
+        let bytes = self
+            .sent
+            .as_ref()
+            .map_or(0, |sent| sent.load(Ordering::Relaxed));
+
+        if self.format == LogFormat::Json {
+            let line = json!({
+                "time": self.time.format(&Rfc3339).ok(),
+                "remote_addr": self.ip.map(|ip| ip.to_string()),
+                "method": self.method.to_string(),
+                "url": self.target,
+                "protocol": self.version.to_string(),
+                "host": self.host,
+                "status": self.status as u16,
+                "bytes": bytes,
+                "duration_ms": (duration * 1e6).round() / 1e3,
+                "referer": self.referer,
+                "user_agent": self.user_agent,
+                "forwarded": self.upstream.is_some(),
+                "upstream": self.upstream,
+            });
+            return write!(f, "{line}");
+        }
+
+        let ip = self.ip.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+        write!(
+            f,
+            "{ip} - - [{}] \"{} {} {}\" {} {bytes} \"{}\" \"{}\" {duration:.3} {}",
+            apache_time(self.time),
+            self.method,
+            quoted(&self.target),
+            self.version,
+            self.status as u16,
+            self.referer.as_deref().map_or("-".into(), quoted),
+            self.user_agent.as_deref().map_or("-".into(), quoted),
+            self.upstream.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+/// `10/Oct/2000:13:55:36 +0000`, as Apache writes it.
+fn apache_time(time: OffsetDateTime) -> String {
+    let month = time.month().to_string();
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        time.day(),
+        &month[..3],
+        time.year(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// Escape a value for a double-quoted combined log field, as Apache does.
+fn quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -10,7 +10,10 @@
 //! Two things need it, independently: the directory listing
 //! ([`crate::directory_listing`], via `serve --directory-listing` or the
 //! gateway's `directory-listing` flag) links [`BASE_CSS`] + [`LISTING_CSS`], and
-//! `serve --render`'s pages link [`BASE_CSS`] (+ [`MARKDOWN_CSS`] for markdown).
+//! `serve --render`'s pages link [`BASE_CSS`] (+ [`CODE_CSS`] for source,
+//! [`MARKDOWN_CSS`] for markdown,
+//! [`DATA_CSS`] for CSV, JSON/YAML/TOML and notebooks, [`MEDIA_CSS`] for
+//! images, audio, video and PDFs, [`SEARCH_CSS`] for search results).
 //! It is mounted whenever either is on, and both go through the same handler.
 //!
 //! Place it ahead of the file handler: a hit serves-and-halts, a miss falls
@@ -27,10 +30,29 @@ pub const BASE_CSS: &str = "/_css/base.css";
 /// The directory listing's table styling, layered over [`BASE_CSS`].
 pub const LISTING_CSS: &str = "/_css/listing.css";
 
+/// The numbered source view on `?render` pages — line numbers, highlighted
+/// lines, the wrap toggle — layered over [`BASE_CSS`].
+#[cfg(feature = "serve-render")]
+pub const CODE_CSS: &str = "/_css/code.css";
+
 /// Typography for rendered markdown, layered over [`BASE_CSS`].
 #[cfg(feature = "serve-render")]
 pub const MARKDOWN_CSS: &str = "/_css/markdown.css";
 
+/// The table, tree and notebook views on `?render` pages for data files,
+/// layered over [`BASE_CSS`].
+#[cfg(feature = "serve-render")]
+pub const DATA_CSS: &str = "/_css/data.css";
+
+/// The `/_serve_search` results page, layered over [`BASE_CSS`].
+#[cfg(feature = "serve-render")]
+pub const SEARCH_CSS: &str = "/_css/search.css";
+
+/// The image viewer, media players and PDF frame on `?render` pages, layered
+/// over [`BASE_CSS`].
+#[cfg(feature = "serve-render")]
+pub const MEDIA_CSS: &str = "/_css/media.css";
+
 /// Inline `<head>` script that applies a saved light/dark choice before first
 /// paint, so an explicit override never flashes the system theme. Goes ahead of
 /// the stylesheet link in every generated page; a no-op if the visitor never
@@ -40,14 +62,20 @@ pub const THEME_HEAD: &str = "<script>(function(){try{var \
                               light\")document.documentElement.setAttribute(\"data-theme\",t);\
                               }catch(e){}})();</script>";
 
+/// The search box in the corner of `serve --render`'s pages and listings, next
+/// to [`THEME_TOGGLE`]. Submits to `/_serve_search`; styled by [`BASE_CSS`].
+pub const SEARCH_FORM: &str = "<form class=\"site-search\" method=\"get\" \
+                               action=\"/_serve_search\" role=\"search\"><input \
+                               type=\"search\" name=\"q\" placeholder=\"search files\" \
+                               aria-label=\"search files\"></form>";
+
 /// The corner theme toggle dropped into every generated page. It cycles three
 /// states — auto (follow the OS), light, dark — and remembers the choice. The
 /// icon reflects the chosen *state* (half-filled circle for auto, sun for light,
 /// moon for dark), driven by the `data-theme` attribute in [`BASE_CSS`]; auto is
 /// the absence of the attribute, so the OS preference drives the palette. The
 /// handler stores `"system"`/`"light"`/`"dark"`; [`THEME_HEAD`] reapplies it.
-pub const THEME_TOGGLE: &str =
-    "<button class=\"theme-toggle\" type=\"button\" aria-label=\"Switch theme: auto, light, or \
+pub const THEME_TOGGLE: &str = "<button class=\"theme-toggle\" type=\"button\" aria-label=\"Switch theme: auto, light, or \
      dark\" title=\"Switch theme: auto, light, dark\"><svg class=\"auto\" viewBox=\"0 0 16 16\" \
      aria-hidden=\"true\"><circle cx=\"8\" cy=\"8\" r=\"5.4\" fill=\"none\" \
      stroke=\"currentColor\" stroke-width=\"1.4\"/><path d=\"M8 2.6a5.4 5.4 0 0 1 0 \
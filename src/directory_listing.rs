@@ -9,19 +9,49 @@
 //! directory; otherwise it leaves the conn untouched so the normal 404 path
 //! applies.
 //!
+//! Besides the HTML page, a listing answers `?format=json` (or `Accept:
+//! application/json`) with its entries' names, types, sizes and mtimes, and
+//! `?format=plain` (or `Accept: text/plain`) with one name per line. `?q=`
+//! filters entries by name — a substring, or a glob when it has `*` or `?` —
+//! and `?depth=` lists that many levels of subdirectories as well. Dotfiles
+//! are left out unless [`DirectoryListing::with_hidden_files`], and
+//! `.gitignore`d entries are too under [`DirectoryListing::with_gitignore`].
+//! `?view=grid` lays the entries out as tiles instead of a table, with a
+//! thumbnail for each image under [`DirectoryListing::with_thumbnails`].
+//!
+//! `serve` also lists directories that aren't one directory on disk — a
+//! directory inside an archive root, or one several overlaid roots share. It
+//! leaves a [`VirtualDirectory`] in conn state instead, and the listing reads
+//! its entries from that.
+//!
 //! The page is built as a plain `String` — no template engine, no network
 //! requests. Its one dependency is [`crate::assets`], which must be mounted
 //! ahead of the file handler to serve the [`LISTING_CSS`] stylesheet the page
 //! links.
 
-use crate::assets::{BASE_CSS, LISTING_CSS, THEME_HEAD, THEME_TOGGLE};
+use crate::assets::{BASE_CSS, LISTING_CSS, SEARCH_FORM, THEME_HEAD, THEME_TOGGLE};
+use ignore::{Match, gitignore::Gitignore};
 use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
 use querystrong::QueryStrong;
+use serde_json::json;
 use size::Size;
-use std::{cmp::Ordering, fmt::Write, path::Path, time::SystemTime};
-use trillium::{Conn, Handler, KnownHeaderName::ContentType};
+#[cfg(feature = "serve")]
+use std::sync::Arc;
+use std::{
+    cmp::Ordering,
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use trillium::{
+    Conn, Handler,
+    KnownHeaderName::{Accept, ContentType, Vary},
+};
 use trillium_static::StaticConnExt;
 
+/// The deepest `?depth=` honored, so one request can't walk an entire disk.
+const MAX_DEPTH: usize = 8;
+
 /// Which column the listing is sorted by, chosen via the `sort` query param.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum SortKey {
@@ -86,26 +116,159 @@ struct Sort {
     order: Order,
 }
 
-impl Sort {
-    fn from_query(querystring: &str) -> Self {
+/// What the listing is rendered as, from `?format=` or else the `Accept`
+/// header. A browser's `Accept` always includes `text/html`, so it gets the
+/// page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Html,
+    Json,
+    Plain,
+}
+
+impl Format {
+    fn negotiate(format: Option<&str>, accept: Option<&str>) -> Self {
+        match format {
+            Some("json") => return Format::Json,
+            Some("plain" | "txt" | "text") => return Format::Plain,
+            Some("html") => return Format::Html,
+            _ => {}
+        }
+        match accept {
+            Some(accept) if accept.contains("text/html") => Format::Html,
+            Some(accept) if accept.contains("application/json") => Format::Json,
+            Some(accept) if accept.contains("text/plain") => Format::Plain,
+            _ => Format::Html,
+        }
+    }
+}
+
+/// Everything one request asks of the listing, parsed from its query string.
+#[derive(Debug, Clone)]
+struct Query {
+    sort: Sort,
+    format: Format,
+    /// `?q=`, lower-cased; empty when absent.
+    filter: String,
+    /// `?depth=`: 1 lists just the directory, 2 its subdirectories too, and so
+    /// on up to [`MAX_DEPTH`].
+    depth: usize,
+    /// `?view=grid`: tiles rather than a table.
+    grid: bool,
+}
+
+impl Query {
+    fn parse(querystring: &str, accept: Option<&str>) -> Self {
         let qs = QueryStrong::parse(querystring);
         Self {
-            key: SortKey::parse(qs.get_str("sort")),
-            order: Order::parse(qs.get_str("order")),
+            sort: Sort {
+                key: SortKey::parse(qs.get_str("sort")),
+                order: Order::parse(qs.get_str("order")),
+            },
+            format: Format::negotiate(qs.get_str("format"), accept),
+            filter: qs.get_str("q").unwrap_or_default().trim().to_lowercase(),
+            depth: qs
+                .get_str("depth")
+                .and_then(|depth| depth.parse().ok())
+                .unwrap_or(1)
+                .clamp(1, MAX_DEPTH),
+            grid: qs.get_str("view") == Some("grid"),
+        }
+    }
+
+    /// Whether an entry's file name passes the `?q=` filter: a
+    /// case-insensitive substring match, or a whole-name glob match when the
+    /// filter has a `*` or `?`.
+    fn matches(&self, file_name: &str) -> bool {
+        let file_name = file_name.to_lowercase();
+        if self.filter.contains(['*', '?']) {
+            glob_match(self.filter.as_bytes(), file_name.as_bytes())
+        } else {
+            file_name.contains(&self.filter)
+        }
+    }
+
+    /// The `q`/`depth`/`view` params to carry along on a sort link, each
+    /// prefixed with an (escaped) `&`.
+    fn carried_params(&self) -> String {
+        let mut params = self.filter_params();
+        if self.grid {
+            params.push_str("&amp;view=grid");
+        }
+        params
+    }
+
+    /// The `q`/`depth` params, each prefixed with an (escaped) `&` — what a
+    /// list/grid toggle link carries, along with the sort.
+    fn filter_params(&self) -> String {
+        let mut params = String::new();
+        if !self.filter.is_empty() {
+            let _ = write!(
+                params,
+                "&amp;q={}",
+                utf8_percent_encode(&self.filter, QUERY_VALUE)
+            );
         }
+        if self.depth > 1 {
+            let _ = write!(params, "&amp;depth={}", self.depth);
+        }
+        params
     }
 }
 
+/// Match `name` against a glob `pattern` where `*` is any run of characters and
+/// `?` any single byte.
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of `name` it has swallowed so far,
+    // to backtrack to on a mismatch.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
 /// Renders an HTML directory index when the static file handler resolved a
 /// directory it could not serve an index from.
 ///
 /// `renderable` is an optional predicate (keyed on a lower-cased file
 /// extension): when set, matching file rows get a `?render` link. `serve`
 /// supplies it under `--render`; `gateway`, which has no render support, leaves
-/// it `None`.
-#[derive(Debug, Clone, Copy, Default)]
+/// it `None`. `upload` adds a form that posts files into the directory, for
+/// `serve --allow-upload`, and `archives` links `?archive=` downloads of it,
+/// for `serve --archives`. `thumbnails`, like `renderable`, is keyed on an
+/// extension: matching files get a `?thumbnail` image in the grid view, and
+/// the page links the list/grid toggle. `search` puts the `/_serve_search`
+/// box in the corner. `show_hidden` lists dotfiles, and
+/// `gitignore_roots` leaves out what the `.gitignore` files between the root
+/// a listed directory is beneath and that directory ignore.
+#[derive(Debug, Clone, Default)]
 pub struct DirectoryListing {
     renderable: Option<fn(&str) -> bool>,
+    thumbnails: Option<fn(&str) -> bool>,
+    search: bool,
+    upload: bool,
+    archives: bool,
+    show_hidden: bool,
+    gitignore_roots: Vec<PathBuf>,
 }
 
 impl DirectoryListing {
@@ -119,41 +282,113 @@ impl DirectoryListing {
     pub fn with_renderable(renderable: fn(&str) -> bool) -> Self {
         Self {
             renderable: Some(renderable),
+            ..Self::default()
+        }
+    }
+
+    /// Show a `?thumbnail` image for files `has_thumbnail` accepts in the grid
+    /// view, and link the list/grid toggle. `serve --render` answers
+    /// `?thumbnail`.
+    #[cfg(feature = "serve-render")]
+    pub fn with_thumbnails(self, has_thumbnail: fn(&str) -> bool) -> Self {
+        Self {
+            thumbnails: Some(has_thumbnail),
+            ..self
+        }
+    }
+
+    /// Put the search box in the corner, for `serve --render`'s
+    /// `/_serve_search`.
+    #[cfg(feature = "serve-render")]
+    pub fn with_search(self) -> Self {
+        Self {
+            search: true,
+            ..self
+        }
+    }
+
+    /// Add an upload form below the table. It posts `multipart/form-data` to
+    /// the directory's own url, which `serve --allow-upload` accepts.
+    #[cfg(feature = "serve")]
+    pub fn with_upload_form(self) -> Self {
+        Self {
+            upload: true,
+            ..self
         }
     }
+
+    /// Link `.zip` and `.tar.gz` downloads of the directory under the heading,
+    /// which `serve --archives` answers.
+    #[cfg(feature = "serve")]
+    pub fn with_archive_links(self) -> Self {
+        Self {
+            archives: true,
+            ..self
+        }
+    }
+
+    /// List dotfiles, which are left out by default.
+    pub fn with_hidden_files(self) -> Self {
+        Self {
+            show_hidden: true,
+            ..self
+        }
+    }
+
+    /// Leave out entries ignored by `.gitignore` files in `root` (a served
+    /// root) or any directory between it and the one listed. Call it once for
+    /// each root. The root is canonicalized to match the directories the file
+    /// handler resolves, which are.
+    #[cfg(feature = "serve")]
+    pub fn with_gitignore(mut self, root: PathBuf) -> Self {
+        self.gitignore_roots
+            .push(root.canonicalize().unwrap_or(root));
+        self
+    }
 }
 
 impl Handler for DirectoryListing {
     async fn run(&self, conn: Conn) -> Conn {
         // Pull owned copies so the immutable borrows of `conn` end before we
         // build the response.
-        let Some((fs_path, url_path, prefix)) = conn.resolved_directory().map(|dir| {
-            (
-                dir.path().to_path_buf(),
-                request_path(&conn).to_string(),
-                mount_prefix(&conn).to_string(),
-            )
-        }) else {
-            return conn;
+        let source = match conn.resolved_directory() {
+            Some(dir) => Source::Disk(dir.path().to_path_buf()),
+            #[cfg(feature = "serve")]
+            None => match conn.state::<VirtualDirectory>() {
+                Some(dir) => Source::Virtual(dir.clone()),
+                None => return conn,
+            },
+            #[cfg(not(feature = "serve"))]
+            None => return conn,
         };
-        let sort = Sort::from_query(conn.querystring());
+        let url_path = request_path(&conn).to_string();
+        let prefix = mount_prefix(&conn).to_string();
+        let query = Query::parse(conn.querystring(), conn.request_headers().get_str(Accept));
 
         // `read_dir` + per-entry `metadata` are blocking syscalls; keep them off
         // the async executor.
-        let entries = match blocking::unblock(move || read_entries(&fs_path)).await {
-            Ok(mut entries) => {
-                sort_entries(&mut entries, sort);
-                entries
-            }
-            Err(error) => {
-                log::warn!("could not list {url_path}: {error}");
-                return conn; // fall through to 404
-            }
-        };
+        let listing = self.clone();
+        let walk_query = query.clone();
+        let entries =
+            match blocking::unblock(move || listing.list_entries(&source, &walk_query)).await {
+                Ok(entries) => entries,
+                Err(error) => {
+                    log::warn!("could not list {url_path}: {error}");
+                    return conn; // fall through to 404
+                }
+            };
 
-        let body = render(&url_path, &prefix, &entries, sort, self.renderable);
+        let (content_type, body) = match query.format {
+            Format::Html => (
+                "text/html; charset=utf-8",
+                render(self, &url_path, &prefix, &entries, &query),
+            ),
+            Format::Json => ("application/json", render_json(&url_path, &entries)),
+            Format::Plain => ("text/plain; charset=utf-8", render_plain(&entries)),
+        };
 
-        conn.with_response_header(ContentType, "text/html; charset=utf-8")
+        conn.with_response_header(ContentType, content_type)
+            .with_response_header(Vary, "Accept")
             .ok(body)
             .halt()
     }
@@ -189,32 +424,228 @@ fn mount_prefix(conn: &Conn) -> &str {
 }
 
 /// One row in the listing.
-struct Entry {
-    name: String,
-    is_dir: bool,
+pub struct Entry {
+    /// The file name, or under `?depth=` the `/`-separated path relative to
+    /// the listed directory.
+    pub name: String,
+    pub is_dir: bool,
     /// `None` for directories and for entries we could not stat.
-    len: Option<u64>,
-    modified: Option<SystemTime>,
-}
-
-/// Read the directory's entries, statting each for type, size, and mtime.
-fn read_entries(dir: &Path) -> std::io::Result<Vec<Entry>> {
-    let mut entries = Vec::new();
-    for entry in std::fs::read_dir(dir)? {
-        let entry = entry?;
-        let meta = entry.metadata().ok();
-        let is_dir = meta.as_ref().is_some_and(std::fs::Metadata::is_dir);
-        entries.push(Entry {
-            name: entry.file_name().to_string_lossy().into_owned(),
-            is_dir,
-            len: meta
-                .as_ref()
-                .filter(|_| !is_dir)
-                .map(std::fs::Metadata::len),
-            modified: meta.as_ref().and_then(|m| m.modified().ok()),
-        });
-    }
-    Ok(entries)
+    pub len: Option<u64>,
+    pub modified: Option<SystemTime>,
+}
+
+/// Where a [`VirtualDirectory`]'s entries come from.
+#[cfg(feature = "serve")]
+pub trait ListDirectory: Send + Sync {
+    /// The entries directly in `relative`, a `/`-separated path below the
+    /// listed directory (empty for the directory itself), unsorted and with
+    /// dotfiles included.
+    fn read_dir(&self, relative: &str) -> std::io::Result<Vec<Entry>>;
+}
+
+/// A directory to list that isn't one directory on disk, left in conn state
+/// where the file handler would leave a
+/// [`ResolvedDirectory`][trillium_static::ResolvedDirectory].
+#[cfg(feature = "serve")]
+#[derive(Clone)]
+pub struct VirtualDirectory(pub Arc<dyn ListDirectory>);
+
+/// The directory being listed.
+enum Source {
+    Disk(PathBuf),
+    #[cfg(feature = "serve")]
+    Virtual(VirtualDirectory),
+}
+
+impl DirectoryListing {
+    /// The entries to list for `source`, filtered, sorted, and — under
+    /// `?depth=` — each directory followed by its own entries. An error reading
+    /// the directory itself is returned; an unreadable subdirectory is just
+    /// left empty.
+    fn list_entries(&self, source: &Source, query: &Query) -> std::io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        match source {
+            Source::Disk(dir) => {
+                let mut ignores = match self
+                    .gitignore_roots
+                    .iter()
+                    .find(|root| dir.starts_with(root))
+                {
+                    Some(root) => ancestor_gitignores(root, dir),
+                    None => Vec::new(),
+                };
+                self.read_entries(dir, "", 1, query, &mut ignores, &mut entries)?;
+            }
+            #[cfg(feature = "serve")]
+            Source::Virtual(dir) => {
+                self.read_virtual_entries(&*dir.0, "", 1, query, &mut entries)?
+            }
+        }
+        Ok(entries)
+    }
+
+    /// [`Self::read_entries`] for a [`VirtualDirectory`], whose entries
+    /// already carry their type, size and mtime. `prefix` is both the path
+    /// below the listed directory and the prefix of each entry's name.
+    #[cfg(feature = "serve")]
+    fn read_virtual_entries(
+        &self,
+        dir: &dyn ListDirectory,
+        prefix: &str,
+        level: usize,
+        query: &Query,
+        out: &mut Vec<Entry>,
+    ) -> std::io::Result<()> {
+        let mut entries = dir.read_dir(prefix.trim_end_matches('/'))?;
+        entries.retain(|entry| self.show_hidden || !entry.name.starts_with('.'));
+        sort_entries(&mut entries, query.sort);
+
+        for entry in entries {
+            let descend = entry.is_dir && level < query.depth;
+            let name = format!("{prefix}{}", entry.name);
+            if query.matches(&entry.name) {
+                out.push(Entry {
+                    name: name.clone(),
+                    ..entry
+                });
+            }
+            if descend
+                && let Err(error) =
+                    self.read_virtual_entries(dir, &format!("{name}/"), level + 1, query, out)
+            {
+                log::debug!("could not list {name}: {error}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Read one directory's entries, statting each for type, size, and mtime,
+    /// into `out`, recursing into subdirectories while `level` is under the
+    /// query's depth. `ignores` holds the `.gitignore` of every directory from
+    /// the root down to this one.
+    fn read_entries(
+        &self,
+        dir: &Path,
+        prefix: &str,
+        level: usize,
+        query: &Query,
+        ignores: &mut Vec<Gitignore>,
+        out: &mut Vec<Entry>,
+    ) -> std::io::Result<()> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') && !self.show_hidden {
+                continue;
+            }
+            let meta = entry.metadata().ok();
+            let is_dir = meta.as_ref().is_some_and(std::fs::Metadata::is_dir);
+            if is_ignored(ignores, &entry.path(), is_dir) {
+                continue;
+            }
+            entries.push(Entry {
+                name,
+                is_dir,
+                len: meta
+                    .as_ref()
+                    .filter(|_| !is_dir)
+                    .map(std::fs::Metadata::len),
+                modified: meta.as_ref().and_then(|m| m.modified().ok()),
+            });
+        }
+        sort_entries(&mut entries, query.sort);
+
+        for entry in entries {
+            // `DirEntry::metadata` doesn't follow symlinks, so a symlinked
+            // directory is never descended into and can't loop.
+            let descend = entry.is_dir && level < query.depth;
+            let child = dir.join(&entry.name);
+            let name = format!("{prefix}{}", entry.name);
+            if query.matches(&entry.name) {
+                out.push(Entry {
+                    name: name.clone(),
+                    ..entry
+                });
+            }
+            if descend {
+                let pushed = !self.gitignore_roots.is_empty() && push_gitignore(ignores, &child);
+                if let Err(error) =
+                    self.read_entries(&child, &format!("{name}/"), level + 1, query, ignores, out)
+                {
+                    log::debug!("could not list {}: {error}", child.display());
+                }
+                if pushed {
+                    ignores.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `.gitignore` files of `root` and every directory from it down to `dir`,
+/// outermost first. Empty if `dir` isn't beneath `root`.
+pub(crate) fn ancestor_gitignores(root: &Path, dir: &Path) -> Vec<Gitignore> {
+    let mut ignores = Vec::new();
+    let Ok(relative) = dir.strip_prefix(root) else {
+        return ignores;
+    };
+    let mut ancestor = root.to_path_buf();
+    push_gitignore(&mut ignores, &ancestor);
+    for component in relative.components() {
+        ancestor.push(component);
+        push_gitignore(&mut ignores, &ancestor);
+    }
+    ignores
+}
+
+/// Push `dir`'s `.gitignore`, if it has one. Returns whether it did.
+pub(crate) fn push_gitignore(ignores: &mut Vec<Gitignore>, dir: &Path) -> bool {
+    let path = dir.join(".gitignore");
+    if !path.is_file() {
+        return false;
+    }
+    let (gitignore, error) = Gitignore::new(&path);
+    if let Some(error) = error {
+        log::debug!("{}: {error}", path.display());
+    }
+    ignores.push(gitignore);
+    true
+}
+
+/// Whether the innermost `.gitignore` with an opinion on `path` ignores it; a
+/// `!pattern` in a deeper file overrides an ignore in a shallower one, as in
+/// git.
+pub(crate) fn is_ignored(ignores: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|gitignore| match gitignore.matched(path, is_dir) {
+            Match::None => None,
+            Match::Ignore(_) => Some(true),
+            Match::Whitelist(_) => Some(false),
+        })
+        .unwrap_or(false)
+}
+
+/// Whether a `.gitignore` between `root` and `path` ignores it or one of its
+/// parents: [`is_ignored`] for a single path, rather than entries met walking
+/// down. `false` if `path` isn't beneath `root`.
+#[cfg(feature = "serve-render")]
+pub(crate) fn is_gitignored(root: &Path, path: &Path) -> bool {
+    let parent = path.parent().unwrap_or(root);
+    ancestor_gitignores(root, parent)
+        .iter()
+        .rev()
+        .find_map(
+            |gitignore| match gitignore.matched_path_or_any_parents(path, path.is_dir()) {
+                Match::None => None,
+                Match::Ignore(_) => Some(true),
+                Match::Whitelist(_) => Some(false),
+            },
+        )
+        .unwrap_or(false)
 }
 
 /// Sort entries for display. Directories always group before files (they're
@@ -262,6 +693,10 @@ const SEGMENT: &AsciiSet = &CONTROLS
     .add(b'{')
     .add(b'}');
 
+/// Characters to percent-encode in a query param value: [`SEGMENT`], plus `+`,
+/// which a query string decodes as a space.
+const QUERY_VALUE: &AsciiSet = &SEGMENT.add(b'+');
+
 fn encode_segment(segment: &str) -> impl std::fmt::Display + '_ {
     utf8_percent_encode(segment, SEGMENT)
 }
@@ -304,10 +739,13 @@ fn format_modified(modified: Option<SystemTime>) -> String {
     }
 }
 
-/// Build a sortable `<th>` whose link sorts by `key`. Clicking the already-active
-/// column flips direction; an arrow marks the active column. The href is a
-/// query-only relative link, so it preserves the current path.
-fn header_cell(label: &str, key: SortKey, sort: Sort, extra_class: &str) -> String {
+/// Build a link that sorts by `key`. Clicking the already-active key flips
+/// direction; an arrow marks it. The href is a query-only relative link, so it
+/// preserves the current path; it carries the query's filter, depth and view
+/// along. Returns whether `key` is the active one, its `aria-sort` attribute,
+/// and the link.
+fn sort_link(label: &str, key: SortKey, query: &Query) -> (bool, &'static str, String) {
+    let sort = query.sort;
     let active = sort.key == key;
     let order = if active {
         sort.order.flipped()
@@ -321,28 +759,54 @@ fn header_cell(label: &str, key: SortKey, sort: Sort, extra_class: &str) -> Stri
         (true, Order::Desc) => (" aria-sort=\"descending\"", " \u{2191}"),
         (false, _) => ("", ""),
     };
+    let link = format!(
+        "<a href=\"?sort={}&amp;order={}{}\">{label}{arrow}</a>",
+        key.param(),
+        order.param(),
+        query.carried_params(),
+    );
+    (active, aria, link)
+}
+
+/// Build a sortable `<th>` around a [`sort_link`].
+fn header_cell(label: &str, key: SortKey, query: &Query, extra_class: &str) -> String {
+    let (active, aria, link) = sort_link(label, key, query);
     let class = if active {
         format!("sortable active{extra_class}")
     } else {
         format!("sortable{extra_class}")
     };
-    format!(
-        "<th class=\"{class}\"{aria}><a href=\"?sort={}&amp;order={}\">{label}{arrow}</a></th>",
-        key.param(),
-        order.param(),
-    )
+    format!("<th class=\"{class}\"{aria}>{link}</th>")
+}
+
+/// The grid view's stand-in for the table's header row: the same sort links in
+/// a line above the tiles.
+fn sort_bar(query: &Query) -> String {
+    let mut bar = String::from("<p class=\"sort\">sort by");
+    for (label, key) in [
+        ("name", SortKey::Name),
+        ("size", SortKey::Size),
+        ("modified", SortKey::Modified),
+    ] {
+        let (active, _, link) = sort_link(label, key, query);
+        let class = if active { " class=\"active\"" } else { "" };
+        let _ = write!(bar, " <span{class}>{link}</span>");
+    }
+    bar.push_str("</p>\n");
+    bar
 }
 
 /// Build the full HTML page for `url_path` (the full request path) and its
 /// entries. `prefix` is the router-stripped mount prefix, used to reach the
-/// stylesheet. `renderable`, when set, decides which file rows get a `?render`
-/// link.
+/// stylesheet. The listing's `renderable`, when set, decides which file rows
+/// get a `?render` link; `thumbnails` which grid tiles get an image; `upload`
+/// adds the upload form and `archives` the download links.
 fn render(
+    listing: &DirectoryListing,
     url_path: &str,
     prefix: &str,
     entries: &[Entry],
-    sort: Sort,
-    renderable: Option<fn(&str) -> bool>,
+    query: &Query,
 ) -> String {
     // Absolute base for hrefs, always trailing-slashed so it works whether or
     // not the request path had a trailing slash.
@@ -355,11 +819,12 @@ fn render(
     let title = format!("Index of {}", escape(url_path));
     let head = format!(
         "{}{}{}",
-        header_cell("Name", SortKey::Name, sort, ""),
-        header_cell("Size", SortKey::Size, sort, " size"),
-        header_cell("Last modified", SortKey::Modified, sort, " modified"),
+        header_cell("Name", SortKey::Name, query, ""),
+        header_cell("Size", SortKey::Size, query, " size"),
+        header_cell("Last modified", SortKey::Modified, query, " modified"),
     );
     let mut rows = String::new();
+    let mut tiles = String::new();
 
     // Parent link, unless we're already at the root of what's mounted here —
     // under a router prefix that's `/docs/`, not `/`, and linking above it would
@@ -372,6 +837,11 @@ fn render(
              href=\"{parent}\">{FOLDER_ICON}<span>../</span></a></td><td class=\"size\"></td><td \
              class=\"modified\"></td></tr>"
         );
+        let _ = write!(
+            tiles,
+            "<li><a href=\"{parent}?view=grid\"><span \
+             class=\"thumb\">{FOLDER_ICON}</span><span>../</span></a></li>"
+        );
     }
 
     for entry in entries {
@@ -386,7 +856,7 @@ fn render(
         // the rendered view (that's what a reader almost always wants) and a
         // "view raw" link exposes the untransformed bytes. Everything else links
         // straight to the file, with no extra link.
-        let renders = matches!(renderable, Some(is_renderable)
+        let renders = matches!(listing.renderable, Some(is_renderable)
             if !entry.is_dir && is_renderable(&extension(&entry.name)));
         let (name_href, raw_link) = if renders {
             (
@@ -394,18 +864,116 @@ fn render(
                 format!("<a class=\"raw\" href=\"{href}\" title=\"raw\">view raw</a>"),
             )
         } else {
-            (href, String::new())
+            (href.clone(), String::new())
         };
+        let name = escape(&entry.name);
         let _ = write!(
             rows,
             "<tr><td class=\"name\"><a \
              href=\"{name_href}\">{icon}<span>{name}{slash}</span></a>{raw_link}</td><td \
              class=\"size\">{size}</td><td class=\"modified\">{modified}</td></tr>",
-            name = escape(&entry.name),
             modified = format_modified(entry.modified),
         );
+
+        // A directory tile stays in the grid view; an image tile shows its
+        // thumbnail, lazily so a big directory doesn't fetch them all at once.
+        if query.grid {
+            let has_thumbnail = matches!(listing.thumbnails, Some(has_thumbnail)
+                if !entry.is_dir && has_thumbnail(&extension(&entry.name)));
+            let thumb = if has_thumbnail {
+                format!("<img src=\"{href}?thumbnail\" alt=\"\" loading=\"lazy\">")
+            } else {
+                icon.to_string()
+            };
+            let tile_href = if entry.is_dir {
+                format!("{href}?view=grid")
+            } else {
+                name_href
+            };
+            let _ = write!(
+                tiles,
+                "<li><a href=\"{tile_href}\" title=\"{name}{slash} \u{b7} {size}\"><span \
+                 class=\"thumb\">{thumb}</span><span>{name}{slash}</span></a></li>"
+            );
+        }
     }
 
+    let entries_html = if query.grid {
+        format!("{}<ul class=\"grid\">\n{tiles}\n</ul>\n", sort_bar(query))
+    } else {
+        format!("<table>\n<thead><tr>{head}</tr></thead>\n<tbody>\n{rows}</tbody>\n</table>\n")
+    };
+
+    // The list/grid toggle, keeping the sort, filter and depth. Only linked
+    // where there are thumbnails to make the grid worth it.
+    let view_links = if listing.thumbnails.is_some() {
+        let params = format!(
+            "sort={}&amp;order={}{}",
+            query.sort.key.param(),
+            query.sort.order.param(),
+            query.filter_params()
+        );
+        let (list_class, grid_class) = if query.grid {
+            ("", " class=\"active\"")
+        } else {
+            (" class=\"active\"", "")
+        };
+        format!(
+            "<p class=\"views\"><a{list_class} href=\"?{params}\">list</a> <a{grid_class} \
+             href=\"?{params}&amp;view=grid\">grid</a></p>\n"
+        )
+    } else {
+        String::new()
+    };
+
+    // Posts to the directory's own url (a relative `action` would drop a
+    // trailing-slash-less last segment).
+    let upload_form = if listing.upload {
+        format!(
+            "<form class=\"upload\" method=\"post\" enctype=\"multipart/form-data\" \
+             action=\"{}\"><input type=\"file\" name=\"file\" multiple required><button \
+             type=\"submit\">Upload</button></form>\n",
+            escape(&base)
+        )
+    } else {
+        String::new()
+    };
+
+    // Query-only, like the sort links, so they keep the current path.
+    let archive_links = if listing.archives {
+        "<p class=\"archives\">download as <a href=\"?archive=zip\" download>.zip</a> <a \
+         href=\"?archive=tar.gz\" download>.tar.gz</a></p>\n"
+    } else {
+        ""
+    };
+
+    let search_form = if listing.search { SEARCH_FORM } else { "" };
+
+    // A GET form, so submitting reloads this listing with `?q=`. The sort,
+    // depth and view ride along as hidden fields.
+    let mut carried = format!(
+        "<input type=\"hidden\" name=\"sort\" value=\"{}\"><input type=\"hidden\" \
+         name=\"order\" value=\"{}\">",
+        query.sort.key.param(),
+        query.sort.order.param(),
+    );
+    if query.depth > 1 {
+        let _ = write!(
+            carried,
+            "<input type=\"hidden\" name=\"depth\" value=\"{}\">",
+            query.depth
+        );
+    }
+    if query.grid {
+        carried.push_str("<input type=\"hidden\" name=\"view\" value=\"grid\">");
+    }
+    let filter_form = format!(
+        "<form class=\"filter\" method=\"get\"><input type=\"search\" name=\"q\" \
+         value=\"{}\" placeholder=\"filter by name or *.glob\" \
+         aria-label=\"filter\">{carried}</form>\n",
+        escape(&query.filter)
+    );
+
     format!(
         "<!DOCTYPE html>\n\
 <html lang=\"en\">\n\
@@ -419,12 +987,14 @@ fn render(
 </head>\n\
 <body>\n\
 {THEME_TOGGLE}\n\
+{search_form}\
 <main>\n\
 <h1>{title}</h1>\n\
-<table>\n\
-<thead><tr>{head}</tr></thead>\n\
-<tbody>\n{rows}</tbody>\n\
-</table>\n\
+{archive_links}\
+{view_links}\
+{filter_form}\
+{entries_html}\
+{upload_form}\
 <footer>served by <a href=\"https://trillium.rs\">trillium</a></footer>\n\
 </main>\n\
 </body>\n\
@@ -432,6 +1002,35 @@ fn render(
     )
 }
 
+/// The listing as JSON: the request path and, per entry, its name, type, size
+/// (`null` for directories) and RFC 3339 mtime (`null` if unknown).
+fn render_json(url_path: &str, entries: &[Entry]) -> String {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "name": entry.name,
+                "type": if entry.is_dir { "directory" } else { "file" },
+                "size": entry.len,
+                "modified": entry
+                    .modified
+                    .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "path": url_path, "entries": entries }).to_string()
+}
+
+/// The listing as plain text: one name per line, directories `/`-suffixed.
+fn render_plain(entries: &[Entry]) -> String {
+    let mut body = String::new();
+    for entry in entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        let _ = writeln!(body, "{}{slash}", entry.name);
+    }
+    body
+}
+
 /// Given a trailing-slashed absolute base like `/a/b/`, return its parent `/a/`.
 fn parent_path(base: &str) -> String {
     let trimmed = base.trim_end_matches('/');
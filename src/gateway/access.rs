@@ -0,0 +1,172 @@
+//! The `access` directive: client-ip allow/deny rules.
+//!
+//! Rules are checked in order and the first whose networks contain the client
+//! decides; a request no rule matches is allowed, so a default-deny list ends
+//! with `deny "all"`. The client is `conn.peer_ip()`, which `trusted-proxies`
+//! and PROXY protocol have already resolved by the time this runs — the same
+//! address `rate-limit` keys on.
+//!
+//! A `deny-file` is read at startup and watched: each change re-reads it and
+//! swaps the new list in atomically. A change that doesn't parse (or a file
+//! that vanishes mid-deploy) is logged and the previous list stays in force.
+
+use super::{
+    config::{AccessNode, AccessRule},
+    networks::Networks,
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock, mpsc},
+    thread,
+    time::Duration,
+};
+use trillium::{Conn, Handler, KnownHeaderName, Status};
+
+/// Client-ip rules for one binding, host, or route.
+#[derive(Debug, Clone)]
+pub struct Access {
+    rules: Vec<(Verdict, Source)>,
+    body: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Allow,
+    Deny,
+}
+
+/// Where a rule's networks come from.
+#[derive(Debug, Clone)]
+enum Source {
+    Inline(Networks),
+    File(Arc<RwLock<Networks>>),
+}
+
+impl Source {
+    fn matches(&self, ip: Option<IpAddr>) -> bool {
+        match self {
+            Self::Inline(networks) => networks.matches(ip),
+            Self::File(networks) => networks
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .matches(ip),
+        }
+    }
+}
+
+impl Access {
+    pub fn new(node: &AccessNode) -> Self {
+        let inline = |networks: &[String]| {
+            Source::Inline(
+                Networks::parse(networks.iter().map(String::as_str))
+                    .expect("access networks validated at load"),
+            )
+        };
+        let rules = node
+            .rules
+            .iter()
+            .map(|rule| match rule {
+                AccessRule::Allow(networks) => (Verdict::Allow, inline(networks)),
+                AccessRule::Deny(networks) => (Verdict::Deny, inline(networks)),
+                AccessRule::DenyFile(path) => (Verdict::Deny, Source::File(watched(path))),
+            })
+            .collect();
+        Self {
+            rules,
+            body: node.body.clone().unwrap_or_else(|| "Forbidden".to_string()),
+        }
+    }
+
+    fn allows(&self, ip: Option<IpAddr>) -> bool {
+        self.rules
+            .iter()
+            .find(|(_, source)| source.matches(ip))
+            .is_none_or(|(verdict, _)| *verdict == Verdict::Allow)
+    }
+}
+
+impl Handler for Access {
+    async fn run(&self, conn: Conn) -> Conn {
+        if self.allows(conn.peer_ip()) {
+            return conn;
+        }
+        conn.with_response_header(KnownHeaderName::ContentType, "text/plain; charset=utf-8")
+            .with_status(Status::Forbidden)
+            .with_body(self.body.clone())
+            .halt()
+    }
+}
+
+/// Read a deny-file and keep it current. A file that can't be read or parsed
+/// at startup is a config error, like a missing TLS cert.
+fn watched(path: &Path) -> Arc<RwLock<Networks>> {
+    let networks = read(path).unwrap_or_else(|e| panic!("{e}"));
+    let networks = Arc::new(RwLock::new(networks));
+    let shared = Arc::clone(&networks);
+    let path = path.to_path_buf();
+    thread::spawn(move || watch(path, shared));
+    networks
+}
+
+fn read(path: &Path) -> Result<Networks, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read deny-file {}: {e}", path.display()))?;
+    Networks::parse_file(&contents).map_err(|e| format!("deny-file {}: {e}", path.display()))
+}
+
+/// Watch the file's directory rather than the file itself, so a deploy that
+/// replaces it by rename (as editors and config management do) is still seen.
+fn watch(path: PathBuf, networks: Arc<RwLock<Networks>>) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = path.file_name().map(ToOwned::to_owned);
+
+    let (events_tx, events_rx) = mpsc::channel();
+    let mut watcher = match RecommendedWatcher::new(events_tx, notify::Config::default()) {
+        Ok(watcher) => watcher,
+        Err(error) => {
+            log::warn!("deny-file: could not start file watcher: {error}");
+            return;
+        }
+    };
+    if let Err(error) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+        log::warn!("deny-file: could not watch {}: {error}", dir.display());
+        return;
+    }
+
+    let concerns_file = |event: &notify::Result<notify::Event>| {
+        let Ok(event) = event else { return false };
+        (event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove())
+            && event
+                .paths
+                .iter()
+                .any(|changed| changed.file_name() == file_name.as_deref())
+    };
+
+    loop {
+        let Ok(first) = events_rx.recv() else {
+            return;
+        };
+        // Coalesce a burst (truncate + write, or write + rename) into one reload.
+        let mut relevant = concerns_file(&first);
+        while let Ok(event) = events_rx.recv_timeout(Duration::from_millis(100)) {
+            relevant |= concerns_file(&event);
+        }
+        if !relevant {
+            continue;
+        }
+        match read(&path) {
+            Ok(reloaded) => {
+                log::info!("reloaded deny-file {}", path.display());
+                *networks
+                    .write()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) = reloaded;
+            }
+            Err(error) => log::error!("{error}; keeping the previous list"),
+        }
+    }
+}
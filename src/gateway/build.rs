@@ -9,11 +9,19 @@
 //! and the matched prefix is stripped for the inner handlers.
 
 use super::{
+    access::Access,
     config::{
         Binding, CacheNode, Config, Directive, ElementOp, FilesDirective, HeaderOp,
-        HeadersDirective, HttpConfigNode, ProxyDirective, RedirectDirective, RewriteHtmlDirective,
-        Route, SelectBlock,
+        HeadersDirective, HttpConfigNode, ProxyDirective, RewriteHtmlDirective, Route, SelectBlock,
     },
+    forwarding::ClientIp,
+    https::{Hsts, HttpsRedirect},
+    networks::Networks,
+    proxy_protocol::ProxyProtocolServer,
+    redirect::Redirect,
+    rewrite_json::RewriteJson,
+    sockets::Sockets,
+    substitute::Substitute,
     upstream,
 };
 use crate::{
@@ -23,16 +31,17 @@ use crate::{
     tls::Tls,
 };
 use std::{io, path::PathBuf, time::Duration};
-use trillium::{BoxedHandler, Conn, Handler, HttpConfig, KnownHeaderName, Method, Status};
+use trillium::{BoxedHandler, Conn, Handler, HttpConfig, Method};
 use trillium_client::Client;
 use trillium_html_rewriter::{
     HtmlRewriter, Settings,
     html::{element, html_content::ContentType},
 };
-use trillium_logger::Logger;
+use trillium_logger::{Logger, dev_formatter, formatters::ip};
 use trillium_proxy::Proxy;
 use trillium_router::Router;
-use trillium_server_common::{ServerHandle, Swansong};
+use trillium_server_common::{Server, ServerHandle, Swansong};
+use trillium_smol::{SmolRuntime, SmolUdpSocket, async_net::TcpListener};
 use trillium_static::StaticFileHandler;
 
 /// Default cache knobs. Memory/max-body match `trillium proxy`; the on-disk
@@ -128,7 +137,24 @@ pub fn print_startup(config: &Config) {
         } else {
             "http"
         };
-        println!("{}", format!("{scheme}://{host}:{port}").bold().green());
+        let url = format!("{scheme}://{host}:{port}").bold().green();
+        let mut notes = Vec::new();
+        match (binding.fd, &binding.fd_name) {
+            (Some(fd), _) => notes.push(format!("fd {fd}")),
+            (None, Some(name)) => notes.push(format!("fd {name:?}")),
+            (None, None) => {}
+        }
+        if binding.proxy_protocol.unwrap_or(false) {
+            notes.push("proxy protocol".to_string());
+        }
+        if binding.https_redirect.unwrap_or(false) {
+            notes.push("→ https".to_string());
+        }
+        if notes.is_empty() {
+            println!("{url}");
+        } else {
+            println!("{url} {}", format!("({})", notes.join(", ")).dimmed());
+        }
 
         for hostblock in &binding.hosts {
             println!("  {}", hostblock.patterns.join(" ").yellow());
@@ -181,56 +207,116 @@ fn describe_directive(directive: &Directive) -> String {
         Directive::Redirect(r) => format!("redirect {}", r.to),
         Directive::Headers(_) => "headers".to_string(),
         Directive::RewriteHtml(r) => format!("rewrite-html ({} selectors)", r.selects.len()),
+        Directive::Substitute(s) => format!("substitute ({} replacements)", s.replacements.len()),
+        Directive::RewriteJson(r) => format!("rewrite-json ({} operations)", r.ops.len()),
+        Directive::Access(a) => format!("access ({} rules)", a.rules.len()),
     }
 }
 
 /// Build one binding's listeners (shared swansong, per-binding `HttpConfig`, TLS)
-/// and spawn them, returning its [`ServerHandle`]. Each address is claimed
-/// eagerly via the [`ListenerConfig`] builder, so a bind failure (port in use,
-/// unresolvable host) surfaces here as an `Err` — fail-fast — instead of as a
+/// and spawn them, returning its [`ServerHandle`]. The TCP listener is claimed
+/// eagerly through [`Sockets`] — handed down by an upgrade, inherited via socket
+/// activation, or freshly bound — so a bind failure (port in use, unresolvable
+/// host, missing fd) surfaces here as an `Err` — fail-fast — instead of as a
 /// silently dead listener after the server task spawns.
-///
-/// [`ListenerConfig`]: trillium_server_common::ListenerConfig
 pub fn spawn_binding(
     binding: &Binding,
     config: &Config,
     swansong: &Swansong,
     client: &Client,
+    sockets: &mut Sockets,
 ) -> io::Result<ServerHandle> {
     let (host, port) = parse_listen(&binding.listen);
     let addr = (host.as_str(), port);
+    let handler = binding_handler(binding, config, client);
+    let listener = TcpListener::try_from(sockets.listener(binding, addr)?)?;
+
+    if binding.proxy_protocol.unwrap_or(false) {
+        let trusted = config.trusted_proxies.as_ref().map(|node| {
+            Networks::parse(node.networks.iter().map(String::as_str))
+                .expect("trusted-proxies validated at load")
+        });
+        let server = ProxyProtocolServer::new(listener, trusted);
+        let server_config = trillium_server_common::Config::<ProxyProtocolServer, ()>::new();
+        listen(
+            binding,
+            server_config,
+            server,
+            addr,
+            swansong,
+            sockets,
+            handler,
+        )
+    } else {
+        let server_config = trillium_smol::config();
+        listen(
+            binding,
+            server_config,
+            listener,
+            addr,
+            swansong,
+            sockets,
+            handler,
+        )
+    }
+}
 
-    let mut server = trillium_smol::config()
+/// Apply the binding's server settings to `server_config` and spawn its
+/// listeners over the claimed `server`. Generic over the server type so a
+/// `proxy-protocol` binding (on [`ProxyProtocolServer`]) and a plain one share
+/// everything above the accept loop.
+#[cfg_attr(not(feature = "h3"), allow(unused_variables))]
+fn listen<S>(
+    binding: &Binding,
+    server_config: trillium_server_common::Config<S, ()>,
+    server: impl Into<S>,
+    addr: (&str, u16),
+    swansong: &Swansong,
+    sockets: &Sockets,
+    handler: impl Handler,
+) -> io::Result<ServerHandle>
+where
+    S: Server<Runtime = SmolRuntime, UdpTransport = SmolUdpSocket>,
+{
+    let mut server_config = server_config
         .with_nodelay()
         .with_swansong(swansong.clone())
         .without_signals();
 
     if let Some(http) = &binding.http {
-        server = server.with_http_config(http_config(http));
+        server_config = server_config.with_http_config(http_config(http));
         if let Some(max) = http.max_connections {
-            server = server.with_max_connections(Some(max));
+            server_config = server_config.with_max_connections(Some(max));
         }
     }
 
-    let handler = binding_handler(binding, config, client);
-
     // The global server config (swansong, HTTP config, …) carries over to the
     // multi-listener builder; we add the binding's listener topology to it. TLS
     // (with per-host SNI cert selection) is built from the binding's and its
     // hosts' cert configs; `gateway` currently implies `rustls`, so the `tls{}`
     // block is always actionable.
-    let listeners = server.listeners();
+    let listeners = server_config.listeners();
     let listeners = match super::sni::build(binding) {
         Some(tls) => {
-            let listeners = listeners.bind_tls(addr, tls.acceptor)?;
+            let listeners = listeners.bind_server_tls(server, tls.acceptor);
             // On h3 builds, a QUIC listener shares the binding's port and is
             // advertised to clients via an `alt-svc` header on the TLS listener.
+            // UDP sockets aren't handed down by an upgrade, so while the old
+            // process still holds the port this one serves without h3.
             #[cfg(feature = "h3")]
-            let listeners = listeners.bind_quic(addr, tls.quic)?;
+            let listeners = if sockets.is_upgrade() && std::net::UdpSocket::bind(addr).is_err() {
+                log::warn!(
+                    "{}: QUIC port still held by the previous process; serving without h3",
+                    binding.listen
+                );
+                listeners
+            } else {
+                listeners.bind_quic(addr, tls.quic)?
+            };
 
             listeners
         }
-        None => listeners.bind_tcp(addr)?,
+        None => listeners.bind_server(server),
     };
 
     Ok(listeners.spawn(handler))
@@ -256,7 +342,7 @@ fn parse_size(s: &str) -> u64 {
 }
 
 /// Parse a human-readable duration like `5m` or `1h`.
-fn parse_duration(s: &str) -> Duration {
+pub fn parse_duration(s: &str) -> Duration {
     humantime::parse_duration(s).unwrap_or_else(|e| panic!("invalid duration {s:?}: {e}"))
 }
 
@@ -267,15 +353,29 @@ pub fn binding_handler(binding: &Binding, config: &Config, client: &Client) -> i
     // behavior). Otherwise a host pre-router dispatches by Host header, with the
     // binding's direct routes as the default vhost. `BoxedHandler` unifies the
     // two shapes into one handler type.
+    // Each host's router carries its `access` rules and its `hsts` (or the
+    // binding's, inherited).
+    let binding_hsts = binding.hsts.as_ref().map(Hsts::new);
     let dispatcher = if binding.hosts.is_empty() {
-        BoxedHandler::new(build_router(&binding.routes, client))
+        BoxedHandler::new((binding_hsts, build_router(&binding.routes, client)))
     } else {
         let hosts = binding
             .hosts
             .iter()
-            .map(|h| (h.patterns.clone(), build_router(&h.routes, client)))
+            .map(|h| {
+                let hsts = h
+                    .hsts
+                    .as_ref()
+                    .map(Hsts::new)
+                    .or_else(|| binding_hsts.clone());
+                let access = h.access.as_ref().map(Access::new);
+                let router = BoxedHandler::new((access, hsts, build_router(&h.routes, client)));
+                (h.patterns.clone(), router)
+            })
             .collect();
-        let default = (!binding.routes.is_empty()).then(|| build_router(&binding.routes, client));
+        let default = (!binding.routes.is_empty()).then(|| {
+            BoxedHandler::new((binding_hsts.clone(), build_router(&binding.routes, client)))
+        });
         BoxedHandler::new(super::host::HostRouter::new(hosts, default))
     };
 
@@ -295,11 +395,25 @@ pub fn binding_handler(binding: &Binding, config: &Config, client: &Client) -> i
         .is_some()
         .then(trillium_caching_headers::caching_headers);
 
+    // Ahead of everything else, so the logger and rate limiter key on the
+    // resolved client rather than the load balancer.
+    let client_ip = config.trusted_proxies.as_ref().map(ClientIp::new);
+    let access = binding.access.as_ref().map(Access::new);
+    let https_redirect = binding
+        .https_redirect
+        .unwrap_or(false)
+        .then(|| HttpsRedirect::new(config));
+
     (
+        client_ip,
         // Suppress the per-binding "Trillium started …" banner; our own
         // `print_startup` summary covers all bindings once, up front.
-        Logger::new().without_init_message(),
+        Logger::new()
+            .with_formatter((ip, " ", dev_formatter))
+            .without_init_message(),
+        access,
         rate_limit,
+        https_redirect,
         caching_headers,
         compression,
         dispatcher,
@@ -336,6 +450,11 @@ fn push_directive(stack: &mut Vec<BoxedHandler>, directive: &Directive, client:
         Directive::Redirect(redirect) => stack.push(BoxedHandler::new(Redirect::new(redirect))),
         Directive::Headers(headers) => stack.push(BoxedHandler::new(Headers::new(headers))),
         Directive::RewriteHtml(rewrite) => push_rewrite_html(stack, rewrite),
+        Directive::Substitute(substitute) => {
+            stack.push(BoxedHandler::new(Substitute::new(substitute)));
+        }
+        Directive::RewriteJson(rewrite) => stack.push(BoxedHandler::new(RewriteJson::new(rewrite))),
+        Directive::Access(access) => stack.push(BoxedHandler::new(Access::new(access))),
     }
 }
 
@@ -395,12 +514,18 @@ fn push_files(stack: &mut Vec<BoxedHandler>, files: &FilesDirective) {
     if let Some(index) = &files.index {
         handler = handler.with_index_file(index);
     }
+    if files.precompressed.unwrap_or(false) {
+        handler = handler.with_precompressed();
+    }
     stack.push(BoxedHandler::new(handler));
 
     if directory_listing {
         // Runs only when the file handler resolved a directory it had no index
-        // for; otherwise leaves the conn untouched. Same pattern as `serve`.
-        stack.push(BoxedHandler::new(DirectoryListing::new()));
+        // for; otherwise leaves the conn untouched. Same pattern as `serve`,
+        // though gateway listings keep showing dotfiles.
+        stack.push(BoxedHandler::new(
+            DirectoryListing::new().with_hidden_files(),
+        ));
     }
 }
 
@@ -432,36 +557,6 @@ pub fn parse_listen(listen: &str) -> (String, u16) {
     (host, port)
 }
 
-/// `redirect "url" status=NNN` — respond with a `Location` redirect and halt.
-#[derive(Debug, Clone)]
-struct Redirect {
-    to: String,
-    status: Status,
-}
-
-impl Redirect {
-    fn new(redirect: &RedirectDirective) -> Self {
-        let status = match redirect.status {
-            Some(code) => {
-                Status::try_from(code).unwrap_or_else(|_| panic!("invalid redirect status {code}"))
-            }
-            None => Status::Found,
-        };
-        Self {
-            to: redirect.to.clone(),
-            status,
-        }
-    }
-}
-
-impl Handler for Redirect {
-    async fn run(&self, conn: Conn) -> Conn {
-        conn.with_response_header(KnownHeaderName::Location, self.to.clone())
-            .with_status(self.status)
-            .halt()
-    }
-}
-
 /// `headers { add/set/remove ... }` — mutate response headers. Applied in
 /// `before_send` so it overrides headers set by the terminal handler (and can
 /// remove headers added late, like `Server`).
@@ -13,8 +13,9 @@
 //! compression true                  // optional cross-cutting defaults
 //! rate-limit "100/min" burst=200
 //! dns "1.1.1.1"                      // encrypted DNS for proxied upstreams
+//! trusted-proxies "10.0.0.0/8"       // believe forwarding headers from these
 //!
-//! binding ":8080" {
+//! binding ":8080" {                   // or `binding ":8080" fd=0` under socket activation
 //!     tls cert="./cert.pem" key="./key.pem"
 //!     http { received-body-max-len "10MiB" }
 //!     route "/api/*" {
@@ -55,6 +56,13 @@ pub struct Config {
     #[knus(child, unwrap(argument))]
     pub dns: Option<String>,
 
+    /// Networks whose forwarding headers (`Forwarded`, `X-Forwarded-*`) and
+    /// PROXY protocol headers are believed when resolving the client ip.
+    /// Absent → the socket peer is the client and incoming forwarding headers
+    /// pass through untouched.
+    #[knus(child)]
+    pub trusted_proxies: Option<TrustedProxiesNode>,
+
     /// One or more listeners.
     #[knus(children(name = "binding"))]
     pub bindings: Vec<Binding>,
@@ -128,6 +136,18 @@ pub struct RateLimitNode {
     pub burst: Option<u64>,
 }
 
+/// `trusted-proxies "10.0.0.0/8" "fd00::/8" strip-untrusted=true` — CIDRs or
+/// bare addresses, validated at load.
+#[derive(knus::Decode, Debug, Clone)]
+pub struct TrustedProxiesNode {
+    #[knus(arguments)]
+    pub networks: Vec<String>,
+    /// Drop forwarding headers from peers outside `networks` rather than pass
+    /// them upstream. Defaults to false.
+    #[knus(property)]
+    pub strip_untrusted: Option<bool>,
+}
+
 /// A single listener: a socket address plus everything served on it.
 #[derive(knus::Decode, Debug)]
 pub struct Binding {
@@ -135,10 +155,42 @@ pub struct Binding {
     #[knus(argument)]
     pub listen: String,
 
+    /// Adopt the socket-activated listener at this `LISTEN_FDS` index instead
+    /// of binding `listen` (which then only labels the binding and, on h3
+    /// builds, gives the QUIC address).
+    #[knus(property)]
+    pub fd: Option<usize>,
+
+    /// Adopt the socket-activated listener with this `LISTEN_FDNAMES` name
+    /// (systemd's `FileDescriptorName=`) instead of binding `listen`.
+    #[knus(property)]
+    pub fd_name: Option<String>,
+
+    /// Redirect every request on this plaintext binding to the TLS binding that
+    /// serves its host, keeping path and query. ACME HTTP-01 challenge paths
+    /// are exempt. Defaults to false.
+    #[knus(property)]
+    pub https_redirect: Option<bool>,
+
+    /// Every connection opens with a PROXY protocol (v1 or v2) header from an
+    /// L4 load balancer, naming the real client. Defaults to false.
+    #[knus(property)]
+    pub proxy_protocol: Option<bool>,
+
     /// TLS for this binding. Absent → plaintext.
     #[knus(child)]
     pub tls: Option<TlsNode>,
 
+    /// `Strict-Transport-Security` for secure responses on this binding; the
+    /// default for `host` blocks that don't set their own.
+    #[knus(child)]
+    pub hsts: Option<HstsNode>,
+
+    /// Client-ip allow/deny rules checked for every request on this binding,
+    /// before any `host` or `route` rules.
+    #[knus(child)]
+    pub access: Option<AccessNode>,
+
     /// Per-binding `trillium_http::HttpConfig` overrides. Absent → defaults.
     #[knus(child)]
     pub http: Option<HttpConfigNode>,
@@ -170,6 +222,15 @@ pub struct HostBlock {
     #[knus(child)]
     pub tls: Option<TlsNode>,
 
+    /// `Strict-Transport-Security` for this host's secure responses, replacing
+    /// the binding's.
+    #[knus(child)]
+    pub hsts: Option<HstsNode>,
+
+    /// Client-ip allow/deny rules for this host, checked after the binding's.
+    #[knus(child)]
+    pub access: Option<AccessNode>,
+
     /// Ordered path routes for this virtual host.
     #[knus(children(name = "route"))]
     pub routes: Vec<Route>,
@@ -184,6 +245,18 @@ pub struct TlsNode {
     pub key: PathBuf,
 }
 
+/// `hsts max-age="1y" include-subdomains=true preload=true`. `max-age` is a
+/// duration (default one year), parsed in the build step.
+#[derive(knus::Decode, Debug, Clone, Default)]
+pub struct HstsNode {
+    #[knus(property)]
+    pub max_age: Option<String>,
+    #[knus(property)]
+    pub include_subdomains: Option<bool>,
+    #[knus(property)]
+    pub preload: Option<bool>,
+}
+
 /// Per-binding subset of [`trillium_http::HttpConfig`]. All optional; only the
 /// keys present are applied over the defaults. Size-valued fields are strings
 /// (`"10MiB"`) parsed in the build step. Expanded toward full `HttpConfig`
@@ -210,7 +283,7 @@ pub struct Route {
 }
 
 /// One directive within a route. The enum variant name is the KDL node name
-/// (`files`, `proxy`, `redirect`, `headers`); document order is preserved, which
+/// (`files`, `proxy`, `redirect`, `headers`, …); document order is preserved, which
 /// is how the directive stack stays ordered.
 #[derive(knus::Decode, Debug)]
 pub enum Directive {
@@ -219,9 +292,38 @@ pub enum Directive {
     Redirect(RedirectDirective),
     Headers(HeadersDirective),
     RewriteHtml(RewriteHtmlDirective),
+    Substitute(SubstituteDirective),
+    RewriteJson(RewriteJsonDirective),
+    Access(AccessNode),
+}
+
+/// `access body="No." { allow "10.0.0.0/8"; deny "all" }` — client-ip rules,
+/// first match wins; a request no rule matches is allowed. Valid on a binding,
+/// a `host`, or as a route directive. The client ip is the one `trusted-proxies`
+/// and PROXY protocol resolve, as for `rate-limit`.
+#[derive(knus::Decode, Debug, Clone)]
+pub struct AccessNode {
+    /// Response body for denied requests (`403 Forbidden`); defaults to
+    /// `Forbidden`.
+    #[knus(property)]
+    pub body: Option<String>,
+    #[knus(children)]
+    pub rules: Vec<AccessRule>,
 }
 
-/// `files root="/srv/www" index="index.html" directory-listing=true`.
+/// One `access` rule. Networks are CIDRs, bare addresses, or `all`.
+#[derive(knus::Decode, Debug, Clone)]
+pub enum AccessRule {
+    /// `allow "10.0.0.0/8" "192.168.0.0/16"`.
+    Allow(#[knus(arguments)] Vec<String>),
+    /// `deny "0.0.0.0/0"`.
+    Deny(#[knus(arguments)] Vec<String>),
+    /// `deny-file "/etc/trillium/blocklist"` — one network per line (`#`
+    /// comments allowed), re-read whenever the file changes.
+    DenyFile(#[knus(argument)] PathBuf),
+}
+
+/// `files root="/srv/www" index="index.html" directory-listing=true precompressed=true`.
 #[derive(knus::Decode, Debug)]
 pub struct FilesDirective {
     #[knus(property)]
@@ -230,6 +332,9 @@ pub struct FilesDirective {
     pub index: Option<String>,
     #[knus(property)]
     pub directory_listing: Option<bool>,
+    /// Serve `.br`/`.zst`/`.gz` siblings to clients that accept them.
+    #[knus(property)]
+    pub precompressed: Option<bool>,
 }
 
 /// `proxy strategy="round-robin" { upstream "..." }`.
@@ -256,7 +361,8 @@ pub struct UpstreamNode {
     pub url: String,
 }
 
-/// `redirect "https://example.com/new" status=308`.
+/// `redirect "https://{host}{path}{query}" status=308`. The target may use the
+/// `{host}`, `{path}` and `{query}` placeholders (see [`super::redirect`]).
 #[derive(knus::Decode, Debug)]
 pub struct RedirectDirective {
     #[knus(argument)]
@@ -343,6 +449,67 @@ pub enum ElementOp {
     Unwrap,
 }
 
+/// `substitute { types "text/*" "application/javascript"; replace "from" "to" }`
+/// — literal (or `regex=true`) replacements over text response bodies, applied
+/// a line at a time as the body streams. Place it after the body-producing
+/// directive, like `rewrite-html`.
+#[derive(knus::Decode, Debug)]
+pub struct SubstituteDirective {
+    /// Content types to substitute (`type/subtype` or `type/*`); defaults to
+    /// `text/*`, JavaScript, JSON and XML.
+    #[knus(child, unwrap(arguments))]
+    pub types: Option<Vec<String>>,
+    /// Replacements, applied in order to each line.
+    #[knus(children(name = "replace"))]
+    pub replacements: Vec<ReplaceNode>,
+}
+
+/// `replace "http://internal:8080" "https://example.com"`, or
+/// `replace "v(\\d+)" "version $1" regex=true`. Regex patterns are validated at
+/// load time.
+#[derive(knus::Decode, Debug, Clone)]
+pub struct ReplaceNode {
+    #[knus(argument)]
+    pub from: String,
+    #[knus(argument)]
+    pub to: String,
+    #[knus(property)]
+    pub regex: Option<bool>,
+}
+
+/// `rewrite-json { replace "/debug" "false"; remove "/internal" }` — an RFC
+/// 6902 JSON Patch applied to `application/json` and `+json` responses. Values
+/// are JSON text. The patch applies atomically: if any operation fails, the
+/// response is sent unchanged.
+#[derive(knus::Decode, Debug)]
+pub struct RewriteJsonDirective {
+    #[knus(children)]
+    pub ops: Vec<JsonPatchOp>,
+}
+
+/// One JSON Patch operation. Paths are JSON pointers (RFC 6901).
+#[derive(knus::Decode, Debug, Clone)]
+pub enum JsonPatchOp {
+    /// `add "/path" "<json>"` — insert into an object or array (`/-` appends).
+    Add(#[knus(argument)] String, #[knus(argument)] String),
+    /// `remove "/path"`.
+    Remove(#[knus(argument)] String),
+    /// `replace "/path" "<json>"` — the path must exist.
+    Replace(#[knus(argument)] String, #[knus(argument)] String),
+    /// `move "/to" from="/from"`.
+    Move(
+        #[knus(argument)] String,
+        #[knus(property(name = "from"))] String,
+    ),
+    /// `copy "/to" from="/from"`.
+    Copy(
+        #[knus(argument)] String,
+        #[knus(property(name = "from"))] String,
+    ),
+    /// `test "/path" "<json>"` — abandon the patch unless the value matches.
+    Test(#[knus(argument)] String, #[knus(argument)] String),
+}
+
 impl Config {
     /// Parse a KDL config file, reporting errors with `miette` source spans.
     pub fn load(path: &std::path::Path) -> miette::Result<Self> {
@@ -352,9 +519,163 @@ impl Config {
         let config: Self = knus::parse(&filename, &text)?;
         config.validate_selectors(&filename, &text)?;
         config.validate_dns(&filename, &text)?;
+        config.validate_networks(&filename, &text)?;
+        config.validate_redirects(&filename, &text)?;
+        config.validate_transforms(&filename, &text)?;
         Ok(config)
     }
 
+    /// Check `substitute` regexes and `rewrite-json` pointers and values at
+    /// load, rather than failing (or never matching) once serving. A route that
+    /// rewrites bodies can't also serve precompressed files, whose bodies it
+    /// would have to pass through untouched.
+    fn validate_transforms(&self, filename: &str, src: &str) -> miette::Result<()> {
+        let error = |needle: &str, label: &str, message: String| {
+            let labels = locate(src, needle)
+                .map(|span| vec![miette::LabeledSpan::at(span, label.to_string())])
+                .unwrap_or_default();
+            miette::miette!(labels = labels, "{message}")
+                .with_source_code(miette::NamedSource::new(filename, src.to_string()))
+        };
+
+        let routes = self
+            .bindings
+            .iter()
+            .flat_map(|b| b.hosts.iter().flat_map(|h| &h.routes).chain(&b.routes));
+        for route in routes {
+            let rewrites_body = route.directives.iter().any(|directive| {
+                matches!(
+                    directive,
+                    Directive::RewriteHtml(_)
+                        | Directive::Substitute(_)
+                        | Directive::RewriteJson(_)
+                )
+            });
+            for directive in &route.directives {
+                match directive {
+                    Directive::Files(files)
+                        if rewrites_body && files.precompressed == Some(true) =>
+                    {
+                        return Err(error(
+                            &files.root.display().to_string(),
+                            "precompressed",
+                            "precompressed files can't be rewritten; drop `precompressed=true` or \
+                             the rewriting directive"
+                                .to_string(),
+                        ));
+                    }
+                    Directive::Substitute(substitute) => {
+                        let regexes = substitute
+                            .replacements
+                            .iter()
+                            .filter(|replace| replace.regex.unwrap_or(false));
+                        for replace in regexes {
+                            if let Err(e) = regex::bytes::Regex::new(&replace.from) {
+                                return Err(error(
+                                    &replace.from,
+                                    "invalid regex",
+                                    format!("invalid substitute regex {:?}: {e}", replace.from),
+                                ));
+                            }
+                        }
+                    }
+                    Directive::RewriteJson(rewrite) => {
+                        for op in &rewrite.ops {
+                            if let Err(e) = super::rewrite_json::validate(op) {
+                                let needle = match op {
+                                    JsonPatchOp::Add(path, _)
+                                    | JsonPatchOp::Remove(path)
+                                    | JsonPatchOp::Replace(path, _)
+                                    | JsonPatchOp::Move(path, _)
+                                    | JsonPatchOp::Copy(path, _)
+                                    | JsonPatchOp::Test(path, _) => path,
+                                };
+                                return Err(error(needle, "invalid operation", e));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check `https-redirect` bindings at load: one on a binding that is
+    /// itself TLS, or one with no TLS binding to send anyone to.
+    fn validate_redirects(&self, filename: &str, src: &str) -> miette::Result<()> {
+        let error = |needle: &str, label: &str, message: String| {
+            let labels = locate(src, needle)
+                .map(|span| vec![miette::LabeledSpan::at(span, label.to_string())])
+                .unwrap_or_default();
+            miette::miette!(labels = labels, "{message}")
+                .with_source_code(miette::NamedSource::new(filename, src.to_string()))
+        };
+
+        let has_tls = |b: &Binding| b.tls.is_some() || b.hosts.iter().any(|h| h.tls.is_some());
+        for binding in &self.bindings {
+            if !binding.https_redirect.unwrap_or(false) {
+                continue;
+            }
+            if has_tls(binding) {
+                return Err(error(
+                    &binding.listen,
+                    "TLS binding",
+                    "https-redirect belongs on a plaintext binding".to_string(),
+                ));
+            }
+            if !self.bindings.iter().any(has_tls) {
+                return Err(error(
+                    &binding.listen,
+                    "nothing to redirect to",
+                    "https-redirect needs a TLS binding to redirect to".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate every network in `trusted-proxies` and in `access` rules at
+    /// load, so a typo'd CIDR fails with a span instead of silently trusting
+    /// (or blocking) nothing. `deny-file` contents are checked when read.
+    fn validate_networks(&self, filename: &str, src: &str) -> miette::Result<()> {
+        let trusted = self.trusted_proxies.iter().flat_map(|t| &t.networks);
+        let access = self
+            .bindings
+            .iter()
+            .flat_map(|b| {
+                let routes = b.hosts.iter().flat_map(|h| &h.routes).chain(&b.routes);
+                let directives = routes.flat_map(|r| &r.directives).filter_map(|d| match d {
+                    Directive::Access(access) => Some(access),
+                    _ => None,
+                });
+                b.access
+                    .iter()
+                    .chain(b.hosts.iter().filter_map(|h| h.access.as_ref()))
+                    .chain(directives)
+            })
+            .flat_map(|access| &access.rules)
+            .flat_map(|rule| match rule {
+                AccessRule::Allow(networks) | AccessRule::Deny(networks) => networks.as_slice(),
+                AccessRule::DenyFile(_) => &[],
+            });
+
+        for network in trusted.chain(access) {
+            if let Err(e) = super::networks::Networks::parse([network.as_str()]) {
+                let labels = locate(src, network)
+                    .map(|span| vec![miette::LabeledSpan::at(span, "invalid network")])
+                    .unwrap_or_default();
+                return Err(miette::miette!(
+                    labels = labels,
+                    help = "use a CIDR like \"10.0.0.0/8\", a single address, or \"all\"",
+                    "{e}",
+                )
+                .with_source_code(miette::NamedSource::new(filename, src.to_string())));
+            }
+        }
+        Ok(())
+    }
+
     /// Validate the `dns` resolver string with the shared [`crate::dns::parse_dns`]
     /// parser at load time, so a bad scheme or empty host fails with a `miette`
     /// span pointing at the offending string rather than exiting once the proxy
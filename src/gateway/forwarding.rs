@@ -0,0 +1,124 @@
+//! Client-IP resolution behind trusted proxies, and the forwarding headers the
+//! gateway accepts and emits.
+//!
+//! With `trusted-proxies` configured, a request whose socket peer is inside one
+//! of the listed networks has its `Forwarded` (RFC 7239) or `X-Forwarded-*`
+//! headers believed: the chain of `for=` hops is walked right to left, skipping
+//! hops that are themselves trusted, and the first untrusted hop becomes the
+//! conn's peer ip. Everything downstream — the logger, the `rate-limit` network
+//! key, and later handlers — then sees the real client rather than the load
+//! balancer. A trusted `proto=https` likewise marks the conn secure.
+//!
+//! The incoming headers are then normalized so `proxy` routes emit exactly one
+//! consistent `Forwarded` header upstream: the `X-Forwarded-*` variants are
+//! removed and `Forwarded` is reduced to the resolved `proto`, onto which
+//! trillium-proxy appends `for=<client>` and `host=`. With `strip-untrusted`,
+//! forwarding headers from an untrusted peer are dropped instead of being passed
+//! along for the upstream to (mis)trust.
+
+use super::{config::TrustedProxiesNode, networks::Networks};
+use std::net::{IpAddr, SocketAddr};
+use trillium::{Conn, Handler, Headers, KnownHeaderName, Transport};
+use trillium_forwarding::Forwarded;
+
+/// Every request header that carries forwarding information.
+const FORWARDING_HEADERS: &[KnownHeaderName] = &[
+    KnownHeaderName::Forwarded,
+    KnownHeaderName::XforwardedFor,
+    KnownHeaderName::XforwardedProto,
+    KnownHeaderName::XforwardedHost,
+    KnownHeaderName::XforwardedBy,
+    KnownHeaderName::XforwardedSsl,
+];
+
+/// Resolves the client ip from trusted forwarding headers and normalizes those
+/// headers for upstreams. First in every binding's handler tuple, so the rest
+/// of the stack sees the resolved peer.
+#[derive(Debug, Clone)]
+pub struct ClientIp {
+    trusted: Networks,
+    strip_untrusted: bool,
+}
+
+impl ClientIp {
+    pub fn new(node: &TrustedProxiesNode) -> Self {
+        Self {
+            trusted: Networks::parse(node.networks.iter().map(String::as_str))
+                .expect("trusted-proxies validated at load"),
+            strip_untrusted: node.strip_untrusted.unwrap_or(false),
+        }
+    }
+
+    /// Walk the `for=` chain from the nearest hop outward: the first hop not
+    /// itself a trusted proxy is the client. A hop we can't read (`unknown`, an
+    /// obfuscated identifier) ends the walk, since nothing beyond it can be
+    /// vouched for. If every hop is trusted, the outermost one is the client.
+    fn resolve(&self, forwarded: &Forwarded<'_>) -> Option<IpAddr> {
+        let hops = forwarded.forwarded_for();
+        for hop in hops.iter().rev() {
+            match parse_node(hop) {
+                Some(ip) if self.trusted.contains(ip) => {}
+                Some(ip) => return Some(ip),
+                None => return None,
+            }
+        }
+        hops.first().and_then(|hop| parse_node(hop))
+    }
+}
+
+impl Handler for ClientIp {
+    async fn run(&self, mut conn: Conn) -> Conn {
+        if !conn.peer_ip().is_some_and(|ip| self.trusted.contains(ip)) {
+            if self.strip_untrusted {
+                strip(conn.request_headers_mut());
+            }
+            return conn;
+        }
+
+        let forwarded = match Forwarded::from_headers(conn.request_headers()) {
+            Ok(forwarded) => forwarded.map(Forwarded::into_owned),
+            Err(error) => {
+                log::debug!("ignoring unparseable forwarding headers: {error}");
+                None
+            }
+        };
+        strip(conn.request_headers_mut());
+        let Some(forwarded) = forwarded else {
+            return conn;
+        };
+
+        let proto = forwarded.proto().map(str::to_ascii_lowercase);
+        let client = self.resolve(&forwarded);
+        let inner: &mut trillium_http::Conn<Box<dyn Transport>> = conn.as_mut();
+        if let Some(client) = client {
+            inner.set_peer_ip(Some(client));
+        }
+        if let Some(proto) = &proto {
+            inner.set_secure(proto == "https");
+        }
+
+        // trillium-proxy builds the upstream header from this one plus the
+        // (now resolved) peer ip and the Host.
+        if let Some(proto) = proto {
+            conn.request_headers_mut()
+                .insert(KnownHeaderName::Forwarded, format!("proto={proto}"));
+        }
+        conn
+    }
+}
+
+fn strip(headers: &mut Headers) {
+    for name in FORWARDING_HEADERS {
+        headers.remove(*name);
+    }
+}
+
+/// An RFC 7239 node (`192.0.2.43`, `"[2001:db8::17]:4711"`, `unknown`) as an
+/// ip, if it is one.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim_matches('"');
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| node.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
@@ -1,6 +1,6 @@
 //! Host-header virtual hosting — the gateway pre-router.
 //!
-//! A [`HostRouter`] sits in front of the per-host [`Router`]s on a single
+//! A [`HostRouter`] sits in front of the per-host routers on a single
 //! binding and dispatches each request to the one whose Host pattern matches,
 //! falling back to an optional default (the binding's direct routes, which also
 //! catches requests with no Host header, e.g. HTTP/1.0).
@@ -11,8 +11,7 @@
 //! state. This is what makes directives such as `headers` (which act in
 //! `before_send`) keep working behind the pre-router.
 
-use trillium::{Conn, Handler, Upgrade};
-use trillium_router::Router;
+use trillium::{BoxedHandler, Conn, Handler, Upgrade};
 
 /// Matches a request Host (or TLS SNI) against one configured pattern. Shared
 /// by request routing and per-host TLS cert selection so both agree on what a
@@ -47,11 +46,12 @@ impl HostMatcher {
     }
 }
 
-/// One virtual host: its patterns and the router for its routes.
+/// One virtual host: its patterns and the handler for its routes (its router,
+/// plus any per-host response handling such as `hsts`).
 #[derive(Debug)]
 struct HostScope {
     matchers: Vec<HostMatcher>,
-    router: Router,
+    router: BoxedHandler,
 }
 
 impl HostScope {
@@ -60,16 +60,16 @@ impl HostScope {
     }
 }
 
-/// Dispatches by Host header to a per-host [`Router`].
+/// Dispatches by Host header to a per-host router.
 #[derive(Debug)]
 pub struct HostRouter {
     hosts: Vec<HostScope>,
-    default: Option<Router>,
+    default: Option<BoxedHandler>,
 }
 
 impl HostRouter {
     /// Build from `(patterns, router)` pairs and an optional default router.
-    pub fn new(hosts: Vec<(Vec<String>, Router)>, default: Option<Router>) -> Self {
+    pub fn new(hosts: Vec<(Vec<String>, BoxedHandler)>, default: Option<BoxedHandler>) -> Self {
         let hosts = hosts
             .into_iter()
             .map(|(patterns, router)| HostScope {
@@ -80,7 +80,7 @@ impl HostRouter {
         Self { hosts, default }
     }
 
-    fn select(&self, host: Option<&str>) -> Option<&Router> {
+    fn select(&self, host: Option<&str>) -> Option<&BoxedHandler> {
         self.hosts
             .iter()
             .find(|scope| scope.matches(host))
@@ -98,7 +98,9 @@ fn normalize(host: Option<&str>) -> Option<String> {
     })
 }
 
-fn host(conn: &Conn) -> Option<String> {
+/// The request's normalized hostname, from the Host header (or the HTTP/2+
+/// `:authority`).
+pub(crate) fn host(conn: &Conn) -> Option<String> {
     let conn: &trillium_http::Conn<_> = conn.as_ref();
     let host = conn.host().or(conn.authority());
     normalize(host)
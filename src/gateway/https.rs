@@ -0,0 +1,130 @@
+//! Moving clients onto https: the `https-redirect` binding option and `hsts`.
+//!
+//! `https-redirect=true` on a plaintext binding answers every request with a
+//! `308` to the same host, path and query on whichever TLS binding in the
+//! config serves that host — except ACME HTTP-01 challenges, which must be
+//! answered over plain http and fall through to the binding's routes.
+//!
+//! `hsts` adds `Strict-Transport-Security` to responses on secure conns only;
+//! browsers ignore the header over http, and sending it there would be wrong.
+
+use super::{
+    config::{Config, HstsNode},
+    host::HostMatcher,
+};
+use std::time::Duration;
+use trillium::{Conn, Handler, KnownHeaderName, Status};
+
+/// ACME HTTP-01 challenges are fetched over port 80 and must not be redirected.
+const ACME_CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// One year, the HSTS preload list's minimum and our default `max-age`.
+const ONE_YEAR: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Redirects plaintext requests to the TLS binding serving their host.
+#[derive(Debug)]
+pub struct HttpsRedirect {
+    /// `(host patterns, port)` for every TLS binding, in config order.
+    targets: Vec<(Vec<HostMatcher>, u16)>,
+}
+
+impl HttpsRedirect {
+    pub fn new(config: &Config) -> Self {
+        let mut targets = Vec::new();
+        for binding in &config.bindings {
+            let (_, port) = super::build::parse_listen(&binding.listen);
+            // A binding-level cert serves any host on that binding; otherwise
+            // only the hosts carrying their own cert are reachable over TLS.
+            if binding.tls.is_some() {
+                targets.push((vec![HostMatcher::Any], port));
+            } else {
+                let matchers = binding
+                    .hosts
+                    .iter()
+                    .filter(|host| host.tls.is_some())
+                    .flat_map(|host| host.patterns.iter().map(|p| HostMatcher::parse(p)))
+                    .collect::<Vec<_>>();
+                if !matchers.is_empty() {
+                    targets.push((matchers, port));
+                }
+            }
+        }
+        Self { targets }
+    }
+
+    fn port_for(&self, host: &str) -> Option<u16> {
+        self.targets
+            .iter()
+            .find(|(matchers, _)| matchers.iter().any(|m| m.matches(Some(host))))
+            .map(|(_, port)| *port)
+    }
+}
+
+impl Handler for HttpsRedirect {
+    async fn run(&self, conn: Conn) -> Conn {
+        // Already https as far as we know: a trusted proxy terminated TLS.
+        if conn.is_secure() || conn.path().starts_with(ACME_CHALLENGE_PREFIX) {
+            return conn;
+        }
+        let Some(host) = super::host::host(&conn) else {
+            return conn;
+        };
+        let Some(port) = self.port_for(&host) else {
+            return conn;
+        };
+
+        let mut location = format!("https://{host}");
+        if port != 443 {
+            location.push_str(&format!(":{port}"));
+        }
+        location.push_str(conn.path_and_query());
+
+        conn.with_response_header(KnownHeaderName::Location, location)
+            .with_status(Status::PermanentRedirect)
+            .halt()
+    }
+}
+
+/// `hsts max-age="1y" include-subdomains=true preload=true`.
+#[derive(Debug, Clone)]
+pub struct Hsts(String);
+
+impl Hsts {
+    pub fn new(node: &HstsNode) -> Self {
+        let max_age = node
+            .max_age
+            .as_deref()
+            .map_or(ONE_YEAR, super::build::parse_duration);
+        let include_subdomains = node.include_subdomains.unwrap_or(false);
+        let preload = node.preload.unwrap_or(false);
+        if preload && (!include_subdomains || max_age < ONE_YEAR) {
+            log::warn!(
+                "hsts preload requires include-subdomains=true and a max-age of at least one \
+                 year; preload list submission will be rejected"
+            );
+        }
+
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if preload {
+            value.push_str("; preload");
+        }
+        Self(value)
+    }
+}
+
+impl Handler for Hsts {
+    async fn run(&self, conn: Conn) -> Conn {
+        conn
+    }
+
+    async fn before_send(&self, mut conn: Conn) -> Conn {
+        if conn.is_secure() {
+            conn.response_headers_mut()
+                .insert(KnownHeaderName::StrictTransportSecurity, self.0.clone());
+        }
+        conn
+    }
+}
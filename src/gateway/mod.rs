@@ -5,14 +5,24 @@
 //! normal trillium app, the handler graph is built at runtime from the config
 //! rather than composed at compile time.
 
+mod access;
 mod build;
 mod config;
+mod forwarding;
 mod host;
+mod https;
+mod networks;
+mod proxy_protocol;
+mod redirect;
+mod rewrite_json;
 mod sni;
+mod sockets;
+mod substitute;
 mod upstream;
 use clap::Parser;
 use clap_verbosity_flag::Verbosity;
 use config::Config;
+use sockets::Sockets;
 use std::{fmt::Debug, path::PathBuf};
 use trillium_server_common::Swansong;
 
@@ -64,11 +74,15 @@ impl GatewayCli {
         // (`without_signals`) so we register once, on the main thread, below.
         //
         // One client (cache + connection pool) shared by every proxy directive.
+        //
+        // Listening sockets come from `Sockets`, which also remembers them so a
+        // SIGUSR2 upgrade can hand them down to the next process.
         let client = build::build_client(&config);
         let swansong = Swansong::new();
+        let mut sockets = Sockets::from_env();
         let mut handles = Vec::with_capacity(config.bindings.len());
         for binding in &config.bindings {
-            match build::spawn_binding(binding, &config, &swansong, &client) {
+            match build::spawn_binding(binding, &config, &swansong, &client, &mut sockets) {
                 Ok(handle) => handles.push(handle),
                 // A bind failed (port in use, unresolvable host). Drain the
                 // bindings that did come up, then exit so the operator sees the
@@ -81,32 +95,50 @@ impl GatewayCli {
             }
         }
 
+        sockets.release_unclaimed();
+
         // Announce only once every listener is actually bound, so the green
         // banner never advertises a binding that failed to come up.
         build::print_startup(&config);
 
-        wait_for_shutdown_signal();
+        // If an upgrade started us, every socket is now being served here; the
+        // old process can stop accepting and drain.
+        #[cfg(unix)]
+        sockets.finish_upgrade();
+
+        wait_for_shutdown_signal(&sockets);
         log::info!("shutting down {} binding(s)", handles.len());
         swansong.shut_down();
         swansong.block_on_shutdown_completion();
     }
 }
 
-/// Block the main thread until a shutdown signal arrives.
+/// Block the main thread until a shutdown signal arrives. `SIGUSR2` starts a
+/// binary upgrade instead: the new process inherits every listening socket and
+/// sends us `SIGQUIT` once it has taken over, so we drain without the ports
+/// ever closing.
 #[cfg(unix)]
-fn wait_for_shutdown_signal() {
+fn wait_for_shutdown_signal(sockets: &Sockets) {
     use signal_hook::{
-        consts::signal::{SIGINT, SIGQUIT, SIGTERM},
+        consts::signal::{SIGINT, SIGQUIT, SIGTERM, SIGUSR2},
         iterator::Signals,
     };
-    let mut signals = Signals::new([SIGINT, SIGTERM, SIGQUIT]).expect("registering signals");
-    signals.forever().next();
+    let mut signals =
+        Signals::new([SIGINT, SIGTERM, SIGQUIT, SIGUSR2]).expect("registering signals");
+    for signal in signals.forever() {
+        if signal != SIGUSR2 {
+            return;
+        }
+        if let Err(error) = sockets.upgrade() {
+            log::error!("upgrade failed: {error}; still serving");
+        }
+    }
 }
 
 /// Non-unix fallback: park until the process is terminated. Graceful
-/// signal-driven shutdown on Windows is a follow-up.
+/// signal-driven shutdown (and binary upgrade) on Windows is a follow-up.
 #[cfg(not(unix))]
-fn wait_for_shutdown_signal() {
+fn wait_for_shutdown_signal(_sockets: &Sockets) {
     loop {
         std::thread::park();
     }
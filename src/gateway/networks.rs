@@ -0,0 +1,58 @@
+//! IP network lists, shared by `trusted-proxies`, PROXY protocol bindings and
+//! `access` rules.
+
+use cidr::AnyIpCidr;
+use std::net::IpAddr;
+
+/// A list of networks: CIDRs (`10.0.0.0/8`), bare addresses (`192.0.2.7`), or
+/// `all`.
+#[derive(Debug, Clone, Default)]
+pub struct Networks(Vec<AnyIpCidr>);
+
+impl Networks {
+    /// Parse a list of networks. The config validates these at load, so the
+    /// error is only for that check.
+    pub fn parse<'a>(networks: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        networks
+            .into_iter()
+            .map(|network| match network {
+                "all" => Ok(AnyIpCidr::Any),
+                network => network
+                    .parse::<AnyIpCidr>()
+                    .map_err(|e| format!("invalid network {network:?}: {e}")),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// Parse a network list file: one network per line, blank lines and `#`
+    /// comments ignored.
+    pub fn parse_file(contents: &str) -> Result<Self, String> {
+        Self::parse(
+            contents
+                .lines()
+                .map(|line| {
+                    line.split_once('#')
+                        .map_or(line, |(network, _)| network)
+                        .trim()
+                })
+                .filter(|line| !line.is_empty()),
+        )
+    }
+
+    /// Whether `ip` is inside one of the networks. IPv4-mapped IPv6 addresses
+    /// (a dual-stack socket's view of an IPv4 peer) match their IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0.iter().any(|network| network.contains(&ip))
+    }
+
+    /// [`Networks::contains`] for a peer that may be unknown (a unix socket),
+    /// which only `all` matches.
+    pub fn matches(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => self.contains(ip),
+            None => self.0.iter().any(AnyIpCidr::is_any),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! PROXY protocol (v1 text and v2 binary) on gateway bindings.
+//!
+//! An L4 load balancer that terminates nothing still hides the client: every
+//! TCP connection arrives from the balancer's address. With `proxy-protocol=true`
+//! on a binding, each connection must open with the balancer's PROXY header,
+//! which names the original source; that address becomes the connection's peer
+//! for everything above it (TLS, the logger, `rate-limit`, `trusted-proxies`).
+//!
+//! The header has to be consumed before the connection reaches trillium, because
+//! the server reads a connection's peer address as soon as it is accepted. So a
+//! proxy-protocol binding runs on [`ProxyProtocolServer`], which accepts on the
+//! binding's listener in a background task, reads each header concurrently (a
+//! slow or silent balancer connection can't stall the others), and hands the
+//! server a [`ProxiedStream`] whose `peer_addr` is the source from the header.
+
+use super::networks::Networks;
+use async_channel::{Receiver, Sender};
+use futures_lite::{AsyncRead, AsyncReadExt, AsyncWrite, FutureExt};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use trillium::{Info, Transport};
+use trillium_server_common::Server;
+use trillium_smol::{
+    SmolRuntime, SmolUdpSocket,
+    async_io::Timer,
+    async_net::{TcpListener, TcpStream},
+};
+
+/// How long a new connection has to deliver its PROXY header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The twelve bytes every v2 header starts with.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The longest v1 header the spec allows, including the trailing CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// A [`Server`] over a TCP listener whose connections each begin with a PROXY
+/// protocol header.
+#[derive(Debug)]
+pub struct ProxyProtocolServer {
+    incoming: Receiver<ProxiedStream>,
+    local_addr: Option<SocketAddr>,
+}
+
+impl ProxyProtocolServer {
+    /// Start accepting on `listener`. With `trusted` set, a connection from
+    /// outside those networks is closed before its header is believed.
+    pub fn new(listener: TcpListener, trusted: Option<Networks>) -> Self {
+        let local_addr = listener.local_addr().ok();
+        let (sender, incoming) = async_channel::unbounded();
+        async_global_executor::spawn(accept_loop(listener, trusted, sender)).detach();
+        Self {
+            incoming,
+            local_addr,
+        }
+    }
+}
+
+impl Server for ProxyProtocolServer {
+    type Runtime = SmolRuntime;
+    type Transport = ProxiedStream;
+    type UdpTransport = SmolUdpSocket;
+
+    fn runtime() -> Self::Runtime {
+        SmolRuntime::default()
+    }
+
+    async fn accept(&mut self) -> io::Result<Self::Transport> {
+        self.incoming
+            .recv()
+            .await
+            .map_err(|_| io::Error::other("proxy protocol accept loop stopped"))
+    }
+
+    fn init(&self, info: &mut Info) {
+        if let Some(local_addr) = self.local_addr {
+            info.insert_shared_state(local_addr);
+        }
+    }
+}
+
+/// Accept until the server is dropped (at shutdown, which closes the channel),
+/// reading each connection's header on its own task.
+async fn accept_loop(
+    listener: TcpListener,
+    trusted: Option<Networks>,
+    sender: Sender<ProxiedStream>,
+) {
+    loop {
+        let accepted = async { Some(listener.accept().await) }
+            .or(async {
+                sender.closed().await;
+                None
+            })
+            .await;
+
+        let (stream, peer) = match accepted {
+            Some(Ok(accepted)) => accepted,
+            Some(Err(error)) => {
+                log::error!("tcp error: {error}");
+                continue;
+            }
+            None => return,
+        };
+
+        if let Some(trusted) = &trusted
+            && !trusted.contains(peer.ip())
+        {
+            log::warn!("closing PROXY protocol connection from untrusted {peer}");
+            continue;
+        }
+
+        let sender = sender.clone();
+        async_global_executor::spawn(async move {
+            match handshake(stream).await {
+                Ok(stream) => {
+                    let _ = sender.send(stream).await;
+                }
+                Err(error) => log::warn!("PROXY protocol header from {peer}: {error}"),
+            }
+        })
+        .detach();
+    }
+}
+
+async fn handshake(mut stream: TcpStream) -> io::Result<ProxiedStream> {
+    let source = read_header(&mut stream)
+        .or(async {
+            Timer::after(HEADER_TIMEOUT).await;
+            Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+        })
+        .await?;
+    Ok(ProxiedStream { stream, source })
+}
+
+/// Read exactly one PROXY header (v1 or v2) off the front of the stream, and
+/// nothing after it. Returns the original source address, or `None` for a
+/// header that doesn't carry one (v1 `UNKNOWN`, v2 `LOCAL` health checks, and
+/// non-IP families), in which case the socket's own peer stands.
+async fn read_header(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+async fn read_v1(stream: &mut TcpStream, start: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await?;
+        line.push(byte[0]);
+    }
+
+    let line = std::str::from_utf8(&line).map_err(|_| invalid("PROXY v1 header is not ascii"))?;
+    let mut fields = line.trim_end().split(' ').skip(1);
+    match fields.next() {
+        Some("UNKNOWN") => Ok(None),
+        Some("TCP4" | "TCP6") => {
+            let source = fields.next().and_then(|ip| ip.parse::<IpAddr>().ok());
+            let _destination = fields.next();
+            let port = fields.next().and_then(|port| port.parse::<u16>().ok());
+            match (source, port) {
+                (Some(ip), Some(port)) => Ok(Some(SocketAddr::new(ip, port))),
+                _ => Err(invalid("malformed PROXY v1 address")),
+            }
+        }
+        _ => Err(invalid("unsupported PROXY v1 protocol")),
+    }
+}
+
+/// The binary header: version/command, family, a big-endian length, then the
+/// addresses (and any TLVs, which are skipped).
+async fn read_v2(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut head = [0; 4];
+    stream.read_exact(&mut head).await?;
+    let [version_command, family, len_high, len_low] = head;
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    let mut body = vec![0; usize::from(u16::from_be_bytes([len_high, len_low]))];
+    stream.read_exact(&mut body).await?;
+
+    match (version_command & 0x0f, family >> 4) {
+        // LOCAL: the balancer's own connection (a health check); no client.
+        (0, _) => Ok(None),
+        (1, 1) if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        (1, 2) if body.len() >= 36 => {
+            let octets: [u8; 16] = body[..16].try_into().expect("sliced to 16 bytes");
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        // PROXY over AF_UNSPEC or AF_UNIX: nothing we can use as a peer ip.
+        (1, 0 | 3) => Ok(None),
+        (1, _) => Err(invalid("truncated PROXY v2 address block")),
+        _ => Err(invalid("unsupported PROXY v2 command")),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// A TCP stream past its PROXY header, reporting the header's source address
+/// as its peer.
+#[derive(Debug)]
+pub struct ProxiedStream {
+    stream: TcpStream,
+    source: Option<SocketAddr>,
+}
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+impl Transport for ProxiedStream {
+    fn peer_addr(&self) -> io::Result<Option<SocketAddr>> {
+        match self.source {
+            Some(source) => Ok(Some(source)),
+            None => self.stream.peer_addr().map(Some),
+        }
+    }
+
+    fn set_ip_ttl(&mut self, ttl: u32) -> io::Result<()> {
+        self.stream.set_ttl(ttl)
+    }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> io::Result<()> {
+        self.stream.set_nodelay(nodelay)
+    }
+}
@@ -0,0 +1,129 @@
+//! The `redirect` directive and its target templates.
+//!
+//! A target is a URL with optional placeholders filled from the request:
+//! `{host}` (the Host header's hostname, without a port), `{path}` (the full
+//! request path, before any route prefix is stripped), and `{query}` (`?` plus
+//! the query string, or nothing when there is none). So
+//! `redirect "https://{host}{path}{query}"` sends a request to the same URL
+//! over https. Any other braces are part of the URL.
+
+use super::config::RedirectDirective;
+use trillium::{Conn, Handler, KnownHeaderName, Status};
+
+/// A parsed redirect target.
+#[derive(Debug, Clone)]
+pub struct Template(Vec<Part>);
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Host,
+    Path,
+    Query,
+}
+
+impl Template {
+    /// Parse a target. Braces that aren't one of the placeholders — JSON in
+    /// a query string, say — are kept as written.
+    pub fn parse(target: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = target;
+        while let Some(open) = rest.find('{') {
+            literal.push_str(&rest[..open]);
+            rest = &rest[open..];
+            let placeholder = [
+                ("{host}", Part::Host),
+                ("{path}", Part::Path),
+                ("{query}", Part::Query),
+            ]
+            .into_iter()
+            .find(|(name, _)| rest.starts_with(name));
+            match placeholder {
+                Some((name, part)) => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                    rest = &rest[name.len()..];
+                }
+                None => {
+                    literal.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Self(parts)
+    }
+
+    fn uses_host(&self) -> bool {
+        self.0.iter().any(|part| matches!(part, Part::Host))
+    }
+
+    /// Fill the placeholders from `conn`. `None` when the template needs a
+    /// host and the request has none (HTTP/1.0 without a Host header).
+    fn render(&self, conn: &Conn) -> Option<String> {
+        let host = if self.uses_host() {
+            Some(super::host::host(conn)?)
+        } else {
+            None
+        };
+        // The unrouted path: a route's own prefix has been stripped from
+        // `conn.path()` by the time the directive runs.
+        let inner: &trillium_http::Conn<_> = conn.as_ref();
+        let mut url = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Literal(literal) => url.push_str(literal),
+                Part::Host => url.push_str(host.as_deref().unwrap_or_default()),
+                Part::Path => url.push_str(inner.path()),
+                Part::Query => {
+                    let query = inner.querystring();
+                    if !query.is_empty() {
+                        url.push('?');
+                        url.push_str(query);
+                    }
+                }
+            }
+        }
+        Some(url)
+    }
+}
+
+/// `redirect "url" status=NNN` — respond with a `Location` redirect and halt.
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    to: Template,
+    status: Status,
+}
+
+impl Redirect {
+    pub fn new(redirect: &RedirectDirective) -> Self {
+        let status = match redirect.status {
+            Some(code) => {
+                Status::try_from(code).unwrap_or_else(|_| panic!("invalid redirect status {code}"))
+            }
+            None => Status::Found,
+        };
+        Self {
+            to: Template::parse(&redirect.to),
+            status,
+        }
+    }
+}
+
+impl Handler for Redirect {
+    async fn run(&self, conn: Conn) -> Conn {
+        match self.to.render(&conn) {
+            Some(location) => conn
+                .with_response_header(KnownHeaderName::Location, location)
+                .with_status(self.status)
+                .halt(),
+            None => conn.with_status(Status::BadRequest).halt(),
+        }
+    }
+}
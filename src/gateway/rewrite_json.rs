@@ -0,0 +1,258 @@
+//! The `rewrite-json` directive: RFC 6902 JSON Patch over JSON responses.
+//!
+//! Unlike `substitute`, a patch needs the whole document, so the body is
+//! buffered (up to [`MAX_BODY`]), parsed, patched and re-serialized. A patch
+//! applies atomically, as RFC 6902 requires: if any operation fails — a path
+//! that doesn't exist, a `test` that doesn't hold — or the body isn't valid
+//! JSON, the response goes out exactly as the upstream sent it.
+
+use super::config::{JsonPatchOp, RewriteJsonDirective};
+use futures_lite::{AsyncReadExt, io::Cursor};
+use serde_json::Value;
+use trillium::{
+    Body, Conn, Handler,
+    KnownHeaderName::{AcceptEncoding, ContentEncoding, ContentLength, ContentType, Etag},
+    Status,
+};
+
+/// The largest body buffered for patching; larger responses stream through
+/// unpatched.
+const MAX_BODY: u64 = 16 * 1024 * 1024;
+
+/// One parsed patch operation.
+#[derive(Debug, Clone)]
+enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+impl Operation {
+    /// Parse a config op. Values and pointers were validated at load.
+    fn new(op: &JsonPatchOp) -> Self {
+        Self::parse(op).expect("rewrite-json validated at load")
+    }
+
+    /// Parse a config op, checking its pointers and JSON values.
+    fn parse(op: &JsonPatchOp) -> Result<Self, String> {
+        let pointer = |pointer: &str| {
+            if pointer.is_empty() || pointer.starts_with('/') {
+                Ok(pointer.to_string())
+            } else {
+                Err(format!(
+                    "JSON pointer {pointer:?} must be empty or start with `/`"
+                ))
+            }
+        };
+        let value = |json: &str| {
+            serde_json::from_str::<Value>(json).map_err(|e| format!("invalid JSON {json:?}: {e}"))
+        };
+        Ok(match op {
+            JsonPatchOp::Add(path, json) => Self::Add {
+                path: pointer(path)?,
+                value: value(json)?,
+            },
+            JsonPatchOp::Remove(path) => Self::Remove {
+                path: pointer(path)?,
+            },
+            JsonPatchOp::Replace(path, json) => Self::Replace {
+                path: pointer(path)?,
+                value: value(json)?,
+            },
+            JsonPatchOp::Move(path, from) => Self::Move {
+                from: pointer(from)?,
+                path: pointer(path)?,
+            },
+            JsonPatchOp::Copy(path, from) => Self::Copy {
+                from: pointer(from)?,
+                path: pointer(path)?,
+            },
+            JsonPatchOp::Test(path, json) => Self::Test {
+                path: pointer(path)?,
+                value: value(json)?,
+            },
+        })
+    }
+
+    fn apply(&self, doc: &mut Value) -> Result<(), String> {
+        match self {
+            Self::Add { path, value } => add(doc, path, value.clone()),
+            Self::Remove { path } => remove(doc, path).map(drop),
+            Self::Replace { path, value } => {
+                *doc.pointer_mut(path)
+                    .ok_or_else(|| format!("replace: no value at {path:?}"))? = value.clone();
+                Ok(())
+            }
+            Self::Move { from, path } => {
+                if path.starts_with(&format!("{from}/")) {
+                    return Err(format!("move: {from:?} is a prefix of {path:?}"));
+                }
+                let value = remove(doc, from)?;
+                add(doc, path, value)
+            }
+            Self::Copy { from, path } => {
+                let value = doc
+                    .pointer(from)
+                    .ok_or_else(|| format!("copy: no value at {from:?}"))?
+                    .clone();
+                add(doc, path, value)
+            }
+            Self::Test { path, value } => match doc.pointer(path) {
+                Some(actual) if actual == value => Ok(()),
+                _ => Err(format!("test failed at {path:?}")),
+            },
+        }
+    }
+}
+
+/// Check one configured operation; for load-time validation.
+pub fn validate(op: &JsonPatchOp) -> Result<(), String> {
+    Operation::parse(op).map(drop)
+}
+
+/// Split a non-root pointer into its parent pointer and unescaped last token.
+fn split(path: &str) -> (&str, String) {
+    let (parent, token) = path.rsplit_once('/').unwrap_or(("", path));
+    (parent, token.replace("~1", "/").replace("~0", "~"))
+}
+
+/// An array index token: digits without a leading zero.
+fn index(token: &str) -> Option<usize> {
+    if token.len() > 1 && token.starts_with('0') {
+        return None;
+    }
+    token.parse().ok()
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let (parent, token) = split(path);
+    match doc.pointer_mut(parent) {
+        Some(Value::Object(object)) => {
+            object.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(array)) if token == "-" => {
+            array.push(value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => match index(&token) {
+            Some(index) if index <= array.len() => {
+                array.insert(index, value);
+                Ok(())
+            }
+            _ => Err(format!("add: bad array index in {path:?}")),
+        },
+        _ => Err(format!("add: no container at {parent:?}")),
+    }
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value, String> {
+    if path.is_empty() {
+        return Err("remove: can't remove the whole document".to_string());
+    }
+    let (parent, token) = split(path);
+    match doc.pointer_mut(parent) {
+        Some(Value::Object(object)) => object
+            .remove(&token)
+            .ok_or_else(|| format!("remove: no value at {path:?}")),
+        Some(Value::Array(array)) => match index(&token) {
+            Some(index) if index < array.len() => Ok(array.remove(index)),
+            _ => Err(format!("remove: bad array index in {path:?}")),
+        },
+        _ => Err(format!("remove: no value at {path:?}")),
+    }
+}
+
+/// `rewrite-json { add "/path" "<json>"; remove "/path"; … }`.
+#[derive(Debug, Clone)]
+pub struct RewriteJson {
+    operations: Vec<Operation>,
+}
+
+impl RewriteJson {
+    pub fn new(directive: &RewriteJsonDirective) -> Self {
+        Self {
+            operations: directive.ops.iter().map(Operation::new).collect(),
+        }
+    }
+
+    fn patch(&self, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let mut doc: Value =
+            serde_json::from_slice(bytes).map_err(|e| format!("not valid JSON: {e}"))?;
+        for operation in &self.operations {
+            operation.apply(&mut doc)?;
+        }
+        serde_json::to_vec(&doc).map_err(|e| e.to_string())
+    }
+}
+
+/// `application/json`, or any `+json` structured syntax type
+/// (`application/problem+json`, …).
+fn is_json(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence == "application/json" || essence.ends_with("+json")
+}
+
+impl Handler for RewriteJson {
+    async fn run(&self, mut conn: Conn) -> Conn {
+        // Ask the upstream for an unencoded body we can parse; `compression`
+        // re-encodes the result.
+        conn.request_headers_mut().remove(AcceptEncoding);
+        conn
+    }
+
+    async fn before_send(&self, mut conn: Conn) -> Conn {
+        let headers = conn.response_headers();
+        let encoded = headers
+            .get_str(ContentEncoding)
+            .is_some_and(|encoding| !encoding.eq_ignore_ascii_case("identity"));
+        if encoded || !headers.get_str(ContentType).is_some_and(is_json) {
+            return conn;
+        }
+        let Some(body) = conn.take_response_body() else {
+            return conn;
+        };
+
+        let mut reader = body.into_reader();
+        let mut bytes = Vec::new();
+        if let Err(error) = (&mut reader)
+            .take(MAX_BODY + 1)
+            .read_to_end(&mut bytes)
+            .await
+        {
+            log::warn!("rewrite-json: could not read response body: {error}");
+            return conn.with_status(Status::BadGateway).with_body("");
+        }
+
+        if bytes.len() as u64 > MAX_BODY {
+            log::debug!("rewrite-json: body over {MAX_BODY} bytes; passing it through");
+            conn.response_headers_mut().remove(ContentLength);
+            return conn.with_body(Body::new_streaming(Cursor::new(bytes).chain(reader), None));
+        }
+
+        match self.patch(&bytes) {
+            Ok(patched) => {
+                let headers = conn.response_headers_mut();
+                headers.remove(ContentLength);
+                headers.remove(Etag);
+                conn.with_body(patched)
+            }
+            Err(error) => {
+                log::debug!("rewrite-json: not applied: {error}");
+                conn.with_body(bytes)
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Listening sockets for gateway bindings: fresh binds, systemd-style socket
+//! activation, and the SIGUSR2 binary upgrade.
+//!
+//! Every binding's TCP listener is claimed through [`Sockets::listener`] rather
+//! than by address inside the listener builder, so the gateway holds a clone of
+//! each socket it serves on. That is what makes a zero-downtime upgrade
+//! possible: on SIGUSR2, [`Sockets::upgrade`] re-executes the binary with those
+//! sockets inherited via the `LISTEN_FDS` protocol, the new process adopts them
+//! by listen address instead of binding afresh (which would fail with the port
+//! in use), and once it is serving it signals the old process to drain. At no
+//! point is the port closed, so no connection is refused.
+//!
+//! The same `LISTEN_FDS` protocol is how systemd (or systemfd) hands a process
+//! pre-bound sockets; a binding opts into one with `fd=N` (by index) or
+//! `fd-name="…"` (by `FileDescriptorName=`, via `LISTEN_FDNAMES`).
+
+use super::config::Binding;
+use std::{env, io, net::TcpListener};
+
+/// Set on a process started by [`Sockets::upgrade`]: the old process's pid,
+/// which is signalled to drain once every binding is up.
+const UPGRADE_PARENT: &str = "TRILLIUM_GATEWAY_UPGRADE_PARENT";
+
+/// Set alongside [`UPGRADE_PARENT`]: the `listen` string of each inherited
+/// socket, newline-separated, in `LISTEN_FDS` order. Listen strings contain `:`,
+/// so they can't travel in the colon-separated `LISTEN_FDNAMES`.
+const UPGRADE_LISTEN: &str = "TRILLIUM_GATEWAY_UPGRADE_LISTEN";
+
+/// The sockets inherited from the environment, and a clone of every socket the
+/// gateway is serving on.
+#[derive(Debug, Default)]
+pub struct Sockets {
+    /// Inherited TCP listeners, by `LISTEN_FDS` index. Taken as bindings claim
+    /// them.
+    inherited: Vec<Option<TcpListener>>,
+    /// The name of each inherited listener: `LISTEN_FDNAMES` under socket
+    /// activation, or the binding's `listen` string after an upgrade.
+    names: Vec<String>,
+    /// The old process's pid, when this process was started by an upgrade.
+    upgrade_parent: Option<u32>,
+    /// `(listen, socket)` for every binding in service, handed down on upgrade.
+    bound: Vec<(String, TcpListener)>,
+}
+
+impl Sockets {
+    /// Collect the sockets inherited through `LISTEN_FDS`, if any.
+    pub fn from_env() -> Self {
+        let upgrade_parent = env::var(UPGRADE_PARENT)
+            .ok()
+            .and_then(|pid| pid.parse().ok());
+        let names = match upgrade_parent {
+            Some(_) => env::var(UPGRADE_LISTEN)
+                .map(|listen| listen.lines().map(String::from).collect())
+                .unwrap_or_default(),
+            None => env::var("LISTEN_FDNAMES")
+                .map(|names| names.split(':').map(String::from).collect())
+                .unwrap_or_default(),
+        };
+
+        let mut fds = listenfd::ListenFd::from_env();
+        let inherited = (0..fds.len())
+            .map(|index| match fds.take_tcp_listener(index) {
+                Ok(listener) => listener,
+                Err(error) => {
+                    log::warn!("ignoring inherited fd at index {index}: {error}");
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            inherited,
+            names,
+            upgrade_parent,
+            bound: Vec::new(),
+        }
+    }
+
+    /// Whether this process was started by [`Sockets::upgrade`], so the previous
+    /// process may still hold any port that wasn't handed down (QUIC).
+    pub fn is_upgrade(&self) -> bool {
+        self.upgrade_parent.is_some()
+    }
+
+    /// Claim the TCP listener for `binding`: the socket handed down by an
+    /// upgrade, else the inherited one it names with `fd`/`fd-name`, else a
+    /// fresh bind of its `listen` address. A named fd that isn't there is an
+    /// error rather than a silent fresh bind — under socket activation the
+    /// supervisor owns the port, so binding it ourselves would fail anyway.
+    pub fn listener(&mut self, binding: &Binding, addr: (&str, u16)) -> io::Result<TcpListener> {
+        let handed_down = if self.is_upgrade() {
+            self.take_named(&binding.listen)
+        } else {
+            None
+        };
+        let listener = if let Some(listener) = handed_down {
+            listener
+        } else if self.is_upgrade() {
+            // A binding the old process didn't have (the config changed under
+            // the upgrade): its systemd fd indices no longer apply.
+            TcpListener::bind(addr)?
+        } else if let Some(index) = binding.fd {
+            self.take(index).ok_or_else(|| {
+                not_inherited(format!("no inherited listener at fd index {index}"))
+            })?
+        } else if let Some(name) = &binding.fd_name {
+            self.take_named(name)
+                .ok_or_else(|| not_inherited(format!("no inherited listener named {name:?}")))?
+        } else {
+            TcpListener::bind(addr)?
+        };
+
+        self.bound
+            .push((binding.listen.clone(), listener.try_clone()?));
+        Ok(listener)
+    }
+
+    /// Close any inherited socket no binding claimed, so a port dropped from the
+    /// config (or an extra systemd socket) isn't held open for nothing.
+    pub fn release_unclaimed(&mut self) {
+        for (index, listener) in self.inherited.iter_mut().enumerate() {
+            if listener.take().is_some() {
+                let name = self.names.get(index).map_or("", String::as_str);
+                log::warn!("closing unclaimed inherited listener {index} {name}");
+            }
+        }
+    }
+
+    fn take(&mut self, index: usize) -> Option<TcpListener> {
+        self.inherited.get_mut(index)?.take()
+    }
+
+    fn take_named(&mut self, name: &str) -> Option<TcpListener> {
+        let index = self.names.iter().position(|n| n == name)?;
+        self.take(index)
+    }
+
+    /// Tell the process that started us (via [`Sockets::upgrade`]) that every
+    /// binding is up, so it can stop accepting and drain. A no-op outside an
+    /// upgrade.
+    #[cfg(unix)]
+    pub fn finish_upgrade(&self) {
+        use nix::{
+            sys::signal::{Signal, kill},
+            unistd::Pid,
+        };
+        let Some(parent) = self.upgrade_parent else {
+            return;
+        };
+        log::info!("upgrade complete; asking pid {parent} to drain");
+        if let Err(error) = kill(Pid::from_raw(parent as i32), Signal::SIGQUIT) {
+            log::warn!("could not signal pid {parent} to drain: {error}");
+        }
+    }
+
+    /// Re-execute the running binary with the same arguments, handing down every
+    /// listening socket. The new process signals us (`SIGQUIT`) once it has
+    /// taken over; if it fails to start instead, we simply keep serving.
+    ///
+    /// `LISTEN_FDS` requires the sockets at consecutive descriptors, so each is
+    /// duplicated into a free run starting at `LISTEN_FDS_FIRST_FD`. `F_DUPFD`
+    /// leaves close-on-exec clear, so the duplicates (and only they) survive the
+    /// exec; our copies are closed again once the child is spawned.
+    #[cfg(unix)]
+    pub fn upgrade(&self) -> io::Result<()> {
+        use std::{os::fd::RawFd, process::Command, thread};
+
+        let fds = self.consecutive_duplicates()?;
+        let first: RawFd = fds.first().copied().unwrap_or(3);
+        let listen = self
+            .bound
+            .iter()
+            .map(|(listen, _)| listen.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // argv[0] rather than `current_exe`: after a deploy replaced the binary,
+        // `/proc/self/exe` still names the old (deleted) file.
+        let mut args = env::args_os();
+        let program = args.next().unwrap_or_else(|| "trillium".into());
+        let spawned = Command::new(program)
+            .args(args)
+            .env("LISTEN_FDS", fds.len().to_string())
+            .env("LISTEN_FDS_FIRST_FD", first.to_string())
+            .env(UPGRADE_PARENT, std::process::id().to_string())
+            .env(UPGRADE_LISTEN, listen)
+            .env_remove("LISTEN_PID")
+            .env_remove("LISTEN_FDNAMES")
+            .spawn();
+
+        for fd in fds {
+            let _ = nix::unistd::close(fd);
+        }
+        let mut child = spawned?;
+        log::info!("upgrading: started pid {}", child.id());
+
+        // Reap the child so a failed upgrade doesn't linger as a zombie, and say
+        // why it failed; a successful one outlives us.
+        thread::spawn(move || match child.wait() {
+            Ok(status) if !status.success() => {
+                log::error!("upgrade failed: new process exited with {status}; still serving");
+            }
+            Ok(_) => {}
+            Err(error) => log::warn!("could not wait on upgraded process: {error}"),
+        });
+        Ok(())
+    }
+
+    /// Duplicate every bound socket into consecutive descriptors, retrying past
+    /// any descriptor already in use. Returns the raw duplicates, which the
+    /// caller must close.
+    #[cfg(unix)]
+    fn consecutive_duplicates(&self) -> io::Result<Vec<std::os::fd::RawFd>> {
+        use nix::fcntl::{FcntlArg, fcntl};
+
+        let mut first = 3;
+        'attempt: loop {
+            let mut fds = Vec::with_capacity(self.bound.len());
+            for (offset, (_, listener)) in (0..).zip(&self.bound) {
+                let fd = match fcntl(listener, FcntlArg::F_DUPFD(first + offset)) {
+                    Ok(fd) => fd,
+                    Err(error) => {
+                        close_all(fds);
+                        return Err(error.into());
+                    }
+                };
+                fds.push(fd);
+                // `F_DUPFD` picks the lowest free descriptor at or above the
+                // hint, so a gap means the run was interrupted; start over past
+                // it.
+                if fd != first + offset {
+                    close_all(fds);
+                    first = fd + 1;
+                    continue 'attempt;
+                }
+            }
+            return Ok(fds);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn close_all(fds: Vec<std::os::fd::RawFd>) {
+    for fd in fds {
+        let _ = nix::unistd::close(fd);
+    }
+}
+
+fn not_inherited(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, message)
+}
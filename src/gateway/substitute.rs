@@ -0,0 +1,244 @@
+//! The `substitute` directive: literal or regex replacements over text bodies.
+//!
+//! Meant for fronting apps that embed their internal hostname in JS, CSS, JSON
+//! or plain text, where `rewrite-html` can't reach. Replacement streams: the
+//! body is processed a line at a time as it arrives, so a match can't span a
+//! newline and `^`/`$` anchor to the line, without its terminator (the same
+//! contract as Apache's `mod_substitute`). A single line longer than
+//! [`MAX_LINE`] — minified bundles — is processed in pieces of that size, and
+//! a match straddling a piece boundary is missed.
+//!
+//! The upstream's `Accept-Encoding` is dropped on the way in, so the body
+//! arrives uncompressed; the binding's own `compression` re-encodes it on the
+//! way out. A response that is encoded anyway passes through untouched.
+
+use super::config::{ReplaceNode, SubstituteDirective};
+use futures_lite::AsyncRead;
+use regex::bytes::{NoExpand, Regex};
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use trillium::{
+    Body, Conn, Handler,
+    KnownHeaderName::{AcceptEncoding, ContentEncoding, ContentLength, ContentType, Etag},
+};
+
+/// Content types substituted when the directive doesn't list its own.
+const DEFAULT_TYPES: &[&str] = &[
+    "text/*",
+    "application/javascript",
+    "application/json",
+    "application/xml",
+];
+
+/// The longest run of bytes held back waiting for a newline.
+const MAX_LINE: usize = 1024 * 1024;
+
+/// How much of the upstream body to read at a time.
+const CHUNK: usize = 16 * 1024;
+
+/// One replacement: a compiled pattern, and whether `$1`-style references in
+/// the replacement are expanded (regex) or taken literally.
+#[derive(Debug)]
+struct Replacement {
+    pattern: Regex,
+    with: Vec<u8>,
+    expand: bool,
+}
+
+impl Replacement {
+    fn new(node: &ReplaceNode) -> Self {
+        let regex = node.regex.unwrap_or(false);
+        let pattern = if regex {
+            node.from.clone()
+        } else {
+            regex::escape(&node.from)
+        };
+        Self {
+            pattern: Regex::new(&pattern).expect("substitute patterns validated at load"),
+            with: node.to.clone().into_bytes(),
+            expand: regex,
+        }
+    }
+
+    fn apply(&self, text: Vec<u8>) -> Vec<u8> {
+        let replaced = if self.expand {
+            self.pattern.replace_all(&text, self.with.as_slice())
+        } else {
+            self.pattern.replace_all(&text, NoExpand(&self.with))
+        };
+        replaced.into_owned()
+    }
+}
+
+/// `substitute { types ...; replace "from" "to" regex=true }`.
+#[derive(Debug, Clone)]
+pub struct Substitute {
+    replacements: Arc<[Replacement]>,
+    types: Vec<String>,
+}
+
+impl Substitute {
+    pub fn new(directive: &SubstituteDirective) -> Self {
+        let types = match &directive.types {
+            Some(types) => types.clone(),
+            None => DEFAULT_TYPES.iter().map(ToString::to_string).collect(),
+        };
+        Self {
+            replacements: directive
+                .replacements
+                .iter()
+                .map(Replacement::new)
+                .collect(),
+            types,
+        }
+    }
+}
+
+impl Handler for Substitute {
+    async fn run(&self, mut conn: Conn) -> Conn {
+        conn.request_headers_mut().remove(AcceptEncoding);
+        conn
+    }
+
+    async fn before_send(&self, mut conn: Conn) -> Conn {
+        let headers = conn.response_headers();
+        let encoded = headers
+            .get_str(ContentEncoding)
+            .is_some_and(|encoding| !encoding.eq_ignore_ascii_case("identity"));
+        let matches = headers
+            .get_str(ContentType)
+            .is_some_and(|content_type| content_type_matches(content_type, &self.types));
+        if encoded || !matches {
+            return conn;
+        }
+        let Some(body) = conn.take_response_body() else {
+            return conn;
+        };
+
+        let reader = Substituting {
+            inner: body,
+            replacements: Arc::clone(&self.replacements),
+            pending: Vec::new(),
+            output: Vec::new(),
+            position: 0,
+            done: false,
+        };
+        let headers = conn.response_headers_mut();
+        headers.remove(ContentLength);
+        headers.remove(Etag);
+        conn.with_body(Body::new_streaming(reader, None))
+    }
+}
+
+/// Whether a `Content-Type` header matches any of `patterns`: an exact
+/// `type/subtype`, or `type/*`. Parameters (`; charset=…`) are ignored.
+fn content_type_matches(content_type: &str, patterns: &[String]) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_suffix("/*") {
+            Some(kind) => essence
+                .split_once('/')
+                .is_some_and(|(essence_kind, _)| essence_kind == kind),
+            None => essence == pattern,
+        }
+    })
+}
+
+/// The streaming body: reads the upstream body, holds back any trailing
+/// partial line, and serves the substituted complete lines.
+struct Substituting {
+    inner: Body,
+    replacements: Arc<[Replacement]>,
+    /// Input not yet substituted: at most one partial line.
+    pending: Vec<u8>,
+    /// Substituted output not yet read, from `position`.
+    output: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl Substituting {
+    /// Apply the replacements to each line of `text` on its own, leaving its
+    /// `\n` or `\r\n` terminator out of what they see.
+    fn substitute(&self, text: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(text.len());
+        for line in text.split_inclusive(|&byte| byte == b'\n') {
+            let body = line
+                .strip_suffix(b"\r\n")
+                .or_else(|| line.strip_suffix(b"\n"))
+                .unwrap_or(line);
+            let replaced = self
+                .replacements
+                .iter()
+                .fold(body.to_vec(), |text, replacement| replacement.apply(text));
+            output.extend_from_slice(&replaced);
+            output.extend_from_slice(&line[body.len()..]);
+        }
+        output
+    }
+
+    /// Move everything up to the last complete line (or everything, at the end
+    /// of the body or past [`MAX_LINE`]) from `pending` into `output`.
+    fn process(&mut self) {
+        let split = if self.done || self.pending.len() > MAX_LINE {
+            self.pending.len()
+        } else {
+            match self.pending.iter().rposition(|&byte| byte == b'\n') {
+                Some(newline) => newline + 1,
+                None => return,
+            }
+        };
+        let rest = self.pending.split_off(split);
+        let ready = std::mem::replace(&mut self.pending, rest);
+        self.output = self.substitute(&ready);
+        self.position = 0;
+    }
+}
+
+impl AsyncRead for Substituting {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if this.position < this.output.len() {
+                let n = buf.len().min(this.output.len() - this.position);
+                buf[..n].copy_from_slice(&this.output[this.position..this.position + n]);
+                this.position += n;
+                return Poll::Ready(Ok(n));
+            }
+            if this.done {
+                return Poll::Ready(Ok(0));
+            }
+
+            let start = this.pending.len();
+            this.pending.resize(start + CHUNK, 0);
+            let read = match Pin::new(&mut this.inner).poll_read(cx, &mut this.pending[start..]) {
+                Poll::Ready(Ok(read)) => read,
+                Poll::Ready(Err(error)) => {
+                    this.pending.truncate(start);
+                    return Poll::Ready(Err(error));
+                }
+                Poll::Pending => {
+                    this.pending.truncate(start);
+                    return Poll::Pending;
+                }
+            };
+            this.pending.truncate(start + read);
+            this.done = read == 0;
+            this.process();
+        }
+    }
+}
@@ -58,7 +58,7 @@ pub fn main() {
 pub enum Cli {
     #[cfg(feature = "serve")]
     /// Static file server and reverse proxy
-    Serve(serve::StaticCli),
+    Serve(Box<serve::StaticCli>),
 
     #[cfg(all(unix, feature = "dev-server"))]
     /// Development server for trillium applications
@@ -90,7 +90,7 @@ impl Cli {
         use Cli::*;
         match self {
             #[cfg(feature = "serve")]
-            Serve(s) => s.run(),
+            Serve(s) => (*s).run(),
             #[cfg(all(unix, feature = "dev-server"))]
             DevServer(d) => d.run(),
             #[cfg(feature = "client")]
@@ -107,6 +107,10 @@ impl Cli {
     }
 }
 
+// `--access-log` and `--log-format`, shared by the `serve` and `proxy` request
+// logs.
+#[cfg(any(feature = "proxy", feature = "serve"))]
+mod access_log;
 #[cfg(any(feature = "proxy", feature = "serve", feature = "gateway"))]
 mod ratelimit;
 // `gateway` has its own TLS path (`gateway::sni`) and never touches `ServerTls`,
@@ -1,4 +1,5 @@
 use crate::{
+    access_log::{AccessLog, Recorded},
     cache::{self, CacheSpec},
     ratelimit::RateLimit,
     server_tls::ServerTls,
@@ -8,11 +9,6 @@ use clap::{Parser, ValueEnum};
 use std::{fmt::Debug, path::PathBuf, time::Duration};
 use trillium::{Conn, Method, Status};
 use trillium_client::Client;
-use trillium_logger::{
-    Logger,
-    client::{ClientLogger, dev_formatter as client_dev_formatter},
-    dev_formatter,
-};
 use trillium_proxy::{
     ForwardProxyConnect, Proxy, Url,
     upstream::{
@@ -158,6 +154,9 @@ pub struct ProxyCli {
     #[command(flatten)]
     rate_limit: RateLimit,
 
+    #[command(flatten)]
+    access_log: AccessLog,
+
     #[command(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
 }
@@ -221,11 +220,14 @@ impl ProxyCli {
         client
     }
 
-    pub fn run(self) {
+    pub fn run(mut self) {
         env_logger::Builder::new()
             .filter_level(self.verbose.log_level_filter())
             .init();
 
+        let host = self.host.clone();
+        self.server_tls.prepare_self_signed(&host);
+
         // Resolve the cache flags into a primitive spec, then let the shared
         // `cache` module select the storage backend. `--no-cache` (or a spec
         // with no tiers) leaves the client uncached. `--cache-memory-capacity 0`
@@ -247,8 +249,12 @@ impl ProxyCli {
         #[cfg(not(unix))]
         let unix_socket: Option<PathBuf> = None;
 
-        let client = crate::tls::build_client(self.client_tls, self.insecure, unix_socket)
-            .with_handler(ClientLogger::new().with_formatter(("-> ", client_dev_formatter)));
+        let request_log = self.access_log.open();
+        let client = crate::tls::build_client(self.client_tls, self.insecure, unix_socket);
+        let client = match request_log.client_logger() {
+            Some(client_logger) => client.with_handler(client_logger),
+            None => client,
+        };
         let client = match cache_spec {
             Some(spec) => cache::attach(client, spec),
             None => client,
@@ -258,7 +264,7 @@ impl ProxyCli {
         let client = self.apply_dns(client);
 
         let server = (
-            Logger::new().with_formatter(("<- ", dev_formatter)),
+            request_log.handler("<- "),
             self.rate_limit.limiter(),
             // `Option<Handler>` is a `Handler`, so `None` skips compression entirely.
             (!self.no_compress).then(trillium_compression::compression),
@@ -277,7 +283,7 @@ impl ProxyCli {
             } else {
                 None
             },
-            Proxy::new(client, self.build_upstream())
+            Proxy::new(client, Recorded(self.build_upstream()))
                 .with_via_pseudonym("trillium-proxy")
                 .with_websocket_upgrades()
                 .proxy_not_found(),
@@ -1,32 +1,55 @@
-use crate::{
-    assets,
-    ratelimit::RateLimit,
-    server_tls::ServerTls,
-    tls::{Tls, parse_url},
-};
+use crate::{access_log::AccessLog, assets, ratelimit::RateLimit, server_tls::ServerTls};
 use clap::Parser;
 use clap_verbosity_flag::Verbosity;
 use colored::Colorize;
-use std::{fmt::Debug, io::Write};
-use trillium_logger::Logger;
-use trillium_proxy::{Client, Proxy, Url};
+use std::{fmt::Debug, io::Write, path::PathBuf};
 use trillium_static::StaticFileHandler;
 
+mod archive;
+mod archive_root;
+mod auth;
+mod content;
+mod forward;
+mod headers;
+#[cfg(feature = "serve-lan")]
+mod lan;
 #[cfg(feature = "serve-render")]
 mod live;
 #[cfg(feature = "serve-render")]
 mod render;
 mod root_path;
+mod roots;
+#[cfg(feature = "serve-render")]
+mod search;
+mod spa;
+#[cfg(feature = "serve-render")]
+mod thumbnail;
+mod write;
 use crate::directory_listing::DirectoryListing;
+use archive::Archives;
+use auth::Access;
+use content::ContentOptions;
+use forward::Forwards;
+use headers::ResponseHeaders;
+#[cfg(feature = "serve-lan")]
+use lan::Lan;
 use root_path::RootPath;
+use roots::{Layers, Root};
+use spa::Spa;
+#[cfg(feature = "serve-render")]
+use two_face::theme::EmbeddedThemeName;
+use write::WriteAccess;
 
 #[derive(Parser, Debug)]
 pub struct StaticCli {
-    /// Filesystem path to serve
+    /// Filesystem paths to serve: directories, files or archives
     ///
-    /// Defaults to the current working directory
-    #[arg(default_value_t)]
-    root: RootPath,
+    /// Defaults to the current working directory. A `.zip`, `.tar`, `.tar.gz`
+    /// or `.tgz` is served from inside, without extracting it. Given several
+    /// roots, each request is answered from the first that has the path, and
+    /// a directory listing shows them merged.
+    #[arg(value_name = "ROOT")]
+    roots: Vec<RootPath>,
 
     /// Local host or ip to listen on
     #[arg(short = 'o', long, env, default_value = "localhost")]
@@ -36,30 +59,51 @@ pub struct StaticCli {
     #[arg(short, long, env, default_value = "8080")]
     port: u16,
 
+    #[cfg(feature = "serve-lan")]
+    #[command(flatten)]
+    lan: Lan,
+
     #[command(flatten)]
     server_tls: ServerTls,
 
-    /// Host to forward (reverse proxy) not-found requests to
-    ///
-    /// This forwards any request that would otherwise be a 404 Not
-    /// Found to the specified listener spec.
-    ///
-    /// Examples:
-    ///    `--forward localhost:8081`
-    ///    `--forward http://localhost:8081`
-    ///    `--forward https://localhost:8081`
-    ///
-    /// Note: http+unix:// schemes are not yet supported
-    #[arg(short, long, env = "FORWARD", value_parser = parse_url)]
-    forward: Option<Url>,
+    #[command(flatten)]
+    forwards: Forwards,
 
     #[arg(short, long, env)]
     index: Option<String>,
 
+    #[command(flatten)]
+    content: ContentOptions,
+
+    /// Serve a single-page app: unknown routes get FALLBACK with a 200
+    ///
+    /// A browser navigation (a GET accepting `text/html`) to a path with no
+    /// file extension that matches no file is answered with FALLBACK, relative
+    /// to the root (default `index.html`), so client-side routing works.
+    /// Missing assets like `/app.js` still 404.
+    #[arg(
+        long,
+        env,
+        value_name = "FALLBACK",
+        num_args = 0..=1,
+        default_missing_value = "index.html"
+    )]
+    spa: Option<String>,
+
     /// disable response compression (gzip/brotli/zstd)
     #[arg(long)]
     no_compress: bool,
 
+    /// serve precompressed siblings (`app.js.br`, `.zst`, `.gz`) when accepted
+    ///
+    /// A request for `app.js` from a client whose Accept-Encoding allows brotli
+    /// gets `app.js.br` if it exists, with `Content-Encoding: br`, `Vary:
+    /// Accept-Encoding`, and the original file's content type; runtime
+    /// compression passes it through. Ignored with `--render` and
+    /// `--live-reload`, which rewrite response bodies.
+    #[arg(long, env)]
+    precompressed: bool,
+
     /// serve an HTML directory listing for directories without an index file
     ///
     /// When enabled, a request that resolves to a directory with no index file
@@ -68,29 +112,115 @@ pub struct StaticCli {
     #[arg(short = 'l', long, env)]
     directory_listing: bool,
 
+    /// include dotfiles in directory listings
+    ///
+    /// Listings leave out names starting with `.` by default. The files are
+    /// still served to anyone who knows the path.
+    #[arg(long, env)]
+    show_hidden: bool,
+
+    /// leave files ignored by `.gitignore` out of directory listings
+    ///
+    /// Reads the `.gitignore` in each root and in every directory down to the
+    /// one listed. As with --show-hidden, ignored files are still served.
+    #[arg(long, env)]
+    respect_gitignore: bool,
+
+    /// download directories as `?archive=zip` or `?archive=tar.gz`
+    ///
+    /// Archives stream as they're built, skip unreadable entries and what the
+    /// listing hides, and stay within the root. With --directory-listing, each
+    /// listing links both.
+    #[arg(long, env)]
+    archives: bool,
+
     /// Render recognized files in the browser and live-reload on change
     ///
     /// Adds a `?render` query param to any served file: source files are shown
-    /// as a syntax-highlighted HTML page, markdown is rendered to HTML, and
-    /// `?render=json` returns a JSON envelope for anything else. Directory
-    /// listings link to `?render` for recognized types.
+    /// as a syntax-highlighted HTML page with linkable line numbers (`#L10-L20`,
+    /// or `?render&lines=10-20` for just those lines), markdown is rendered to
+    /// HTML, and `?render=json` returns a JSON envelope for anything else. CSV
+    /// and TSV become a sortable, paged table, JSON, YAML and TOML a collapsible
+    /// tree, and Jupyter notebooks a page of cells and outputs. Images get a
+    /// viewer with their dimensions and EXIF summary, audio and video a player,
+    /// and PDFs the browser's viewer. Directory listings link to `?render` for
+    /// recognized types and offer a `?view=grid` of image thumbnails.
     ///
-    /// Also injects a small live-reload script into HTML responses and watches
-    /// the served directory, refreshing the browser whenever a file changes or
-    /// is added (so directory listings stay current too).
+    /// Markdown pages read as a docs site: a sidebar of the root's markdown
+    /// files (or its SUMMARY.md), a table of contents, and `.md` links that stay
+    /// rendered.
+    ///
+    /// `/_serve_search?q=` searches the text of every served file, from a box
+    /// on each rendered page and listing; `&format=json` returns the results as
+    /// JSON.
+    ///
+    /// Implies --live-reload.
     #[cfg(feature = "serve-render")]
     #[arg(short = 'r', long, env)]
     render: bool,
 
+    /// Syntax highlighting theme for light pages under --render
+    ///
+    /// Any theme bundled with two-face, e.g. "GitHub", "OneHalfLight" or
+    /// "Solarized (light)"; case doesn't matter.
+    #[cfg(feature = "serve-render")]
+    #[arg(
+        long,
+        env,
+        value_name = "NAME",
+        default_value_t = render::DEFAULT_LIGHT_THEME,
+        value_parser = render::parse_theme
+    )]
+    theme_light: EmbeddedThemeName,
+
+    /// Syntax highlighting theme for dark pages under --render
+    ///
+    /// Any theme bundled with two-face, e.g. "Dracula", "Nord" or "Solarized
+    /// (dark)"; case doesn't matter.
+    #[cfg(feature = "serve-render")]
+    #[arg(
+        long,
+        env,
+        value_name = "NAME",
+        default_value_t = render::DEFAULT_DARK_THEME,
+        value_parser = render::parse_theme
+    )]
+    theme_dark: EmbeddedThemeName,
+
+    /// Draw mermaid diagrams and math on rendered markdown pages with scripts
+    /// from cdn.jsdelivr.net
+    ///
+    /// Off by default, so rendered pages make no network requests; diagrams and
+    /// math then show as their source.
+    #[cfg(feature = "serve-render")]
+    #[arg(long, env)]
+    cdn_scripts: bool,
+
+    #[cfg(feature = "serve-render")]
+    #[command(flatten)]
+    live_reload: live::LiveReload,
+
+    #[command(flatten)]
+    response_headers: ResponseHeaders,
+
+    #[command(flatten)]
+    write_access: WriteAccess,
+
+    #[command(flatten)]
+    access: Access,
+
     #[command(flatten)]
     rate_limit: RateLimit,
 
+    #[command(flatten)]
+    access_log: AccessLog,
+
     #[command(flatten)]
     verbose: Verbosity,
 }
 
 impl StaticCli {
-    pub fn run(self) {
+    pub fn run(mut self) {
         env_logger::Builder::new()
             .parse_filters(&format!(
                 "{},quinn=off,quinn_proto=off",
@@ -106,20 +236,70 @@ impl StaticCli {
             })
             .init();
 
-        let path = self.root.clone();
-        #[cfg(feature = "serve-render")]
-        let root_dir = std::path::PathBuf::from(self.root.clone());
-        let mut static_file_handler = StaticFileHandler::new(path);
-        if let Some(index) = &self.index {
-            static_file_handler = static_file_handler.with_index_file(index);
-        }
+        let request_log = self.access_log.open();
+
+        #[cfg(feature = "serve-lan")]
+        let host = self.lan.host().unwrap_or(&self.host).to_string();
+        #[cfg(not(feature = "serve-lan"))]
+        let host = self.host.clone();
+        self.server_tls.prepare_self_signed(&host);
 
+        let roots = if self.roots.is_empty() {
+            vec![Root::open(RootPath::default())]
+        } else {
+            self.roots.drain(..).map(Root::open).collect()
+        };
+        // Uploads, `?archive=` downloads, thumbnails, search, the markdown
+        // sidebar and live reload work on the first root, which has to be on
+        // disk for them.
+        let primary = roots[0].disk().map(PathBuf::from);
+        if primary.is_none() && (self.write_access.is_enabled() || self.archives) {
+            eprintln!(
+                "--allow-upload, --allow-delete, --webdav and --archives need the first root to \
+                 be a directory, not the archive {}",
+                roots[0].path().display()
+            );
+            std::process::exit(1);
+        }
+        let spa = self
+            .spa
+            .as_deref()
+            .map(|fallback| Spa::new(&roots, fallback));
         // Without the feature there is no `--render` flag, so nothing renders.
         // Hoisting it to a plain `bool` keeps the rest of `run` cfg-free.
         #[cfg(feature = "serve-render")]
         let render = self.render;
         #[cfg(not(feature = "serve-render"))]
         let render = false;
+        #[cfg(feature = "serve-render")]
+        let live_reload = self.live_reload.is_enabled(render);
+        #[cfg(not(feature = "serve-render"))]
+        let live_reload = false;
+
+        // The `?render` pages and the live-reload injection read the body the
+        // file handler produced, which a compressed sibling would garble.
+        if self.precompressed && live_reload {
+            log::warn!(
+                "--precompressed is ignored with --render and --live-reload, which rewrite \
+                 response bodies"
+            );
+        }
+        let layers = Layers::new(&roots, self.index.clone(), |root| {
+            let mut static_file_handler = StaticFileHandler::new(root);
+            if let Some(index) = &self.index {
+                static_file_handler = static_file_handler.with_index_file(index);
+            }
+            if self.precompressed && !live_reload {
+                static_file_handler = static_file_handler.with_precompressed();
+            }
+            static_file_handler
+        });
+        let layers = if self.respect_gitignore {
+            layers.with_gitignore()
+        } else {
+            layers
+        };
+        let static_file_handler = self.content.wrap(layers, self.index.clone());
 
         // The `?render` handler, enabled by `--render`. It transforms the served
         // body in `before_send` (the static handler halts, so a later `run`
@@ -127,29 +307,133 @@ impl StaticCli {
         // that handler produced. `Option<()>` is a no-op `Handler` when the
         // feature is compiled out.
         #[cfg(feature = "serve-render")]
-        let render_handler = render.then_some(render::Render);
+        let render_handler = render.then(|| {
+            match (&primary, roots.len()) {
+                (None, _) => log::info!(
+                    "no markdown sidebar: the first root, {}, is an archive",
+                    roots[0].path().display()
+                ),
+                (Some(primary), 2..) => log::info!(
+                    "the markdown sidebar lists the pages in {} only",
+                    primary.display()
+                ),
+                _ => {}
+            }
+            let render =
+                render::Render::new(primary.clone()).with_themes(self.theme_light, self.theme_dark);
+            if self.cdn_scripts {
+                render.with_cdn_scripts()
+            } else {
+                render
+            }
+        });
         #[cfg(not(feature = "serve-render"))]
         let render_handler = (); // `()` is a no-op `Handler`
 
         // The directory listing links `?render` for recognized files only when
         // `--render` is on.
         #[cfg(feature = "serve-render")]
-        let directory_listing = if render {
-            DirectoryListing::with_renderable(render::is_renderable)
-        } else {
-            DirectoryListing::new()
+        let directory_listing = match (render, &primary) {
+            (true, Some(_)) => DirectoryListing::with_renderable(render::is_renderable)
+                .with_thumbnails(thumbnail::has_thumbnail)
+                .with_search(),
+            (true, None) => DirectoryListing::with_renderable(render::is_renderable),
+            (false, _) => DirectoryListing::new(),
         };
         #[cfg(not(feature = "serve-render"))]
         let directory_listing = DirectoryListing::new();
+        let directory_listing = if self.write_access.allows_upload() {
+            directory_listing.with_upload_form()
+        } else {
+            directory_listing
+        };
+        let directory_listing = if self.archives {
+            directory_listing.with_archive_links()
+        } else {
+            directory_listing
+        };
+        let directory_listing = if self.show_hidden {
+            directory_listing.with_hidden_files()
+        } else {
+            directory_listing
+        };
+        let directory_listing = if self.respect_gitignore {
+            roots
+                .iter()
+                .filter_map(Root::disk)
+                .fold(directory_listing, |listing, root| {
+                    listing.with_gitignore(root.to_path_buf())
+                })
+        } else {
+            directory_listing
+        };
+
+        // `--allow-upload`/`--allow-delete`/`--webdav`. Ahead of `--forward` so
+        // writes land on local disk instead of being proxied (and their request
+        // bodies consumed) upstream.
+        let writes = primary
+            .clone()
+            .and_then(|root| self.write_access.handler(root));
+
+        // `--archives`: `?archive=` downloads of a directory, leaving out what
+        // the listing hides. Ahead of the file handler, which would otherwise
+        // serve a directory's index file.
+        let archives = primary.clone().filter(|_| self.archives).map(|root| {
+            let archives = Archives::new(root);
+            let archives = if self.show_hidden {
+                archives.with_hidden_files()
+            } else {
+                archives
+            };
+            if self.respect_gitignore {
+                archives.with_gitignore()
+            } else {
+                archives
+            }
+        });
+
+        // `?thumbnail`, for the listing's grid view under `--render`. Ahead of the
+        // file handler, which would otherwise serve the full-size image.
+        #[cfg(feature = "serve-render")]
+        let thumbnails = primary
+            .clone()
+            .filter(|_| render)
+            .map(thumbnail::Thumbnails::new);
+        #[cfg(not(feature = "serve-render"))]
+        let thumbnails = ();
 
-        // Live reload, enabled by `--render`: injects the reload script into HTML
-        // responses and serves the `/_serve_live.*` routes. Placed after
-        // compression (so the rewriter runs on the uncompressed body) and ahead
-        // of the file handler (so its routes win). `()` is a no-op when disabled.
+        // Live reload, enabled by `--live-reload` or `--render`: injects the
+        // reload script into HTML responses and serves the `/_serve_live.*`
+        // routes. Placed after compression (so the rewriter runs on the
+        // uncompressed body) and ahead of the file handler (so its routes win).
+        // `()` is a no-op when disabled.
+        //
+        // The full-text index behind `/_serve_search`, also under `--render`. It
+        // builds in the background at startup; live reload's watcher hands it
+        // each batch of changed paths so results stay current, and has the
+        // markdown sidebar rebuilt.
+        #[cfg(feature = "serve-render")]
+        let search = primary.clone().filter(|_| render).map(search::Index::new);
         #[cfg(feature = "serve-render")]
-        let live = render.then(|| live::handler(root_dir));
+        let live = primary.clone().and_then(|root| {
+            let search = search.clone();
+            let rendered = render_handler.clone();
+            self.live_reload
+                .handler(root, render, move |paths: &[PathBuf]| {
+                    if let Some(index) = &search {
+                        index.update(paths);
+                    }
+                    if let Some(rendered) = &rendered {
+                        rendered.files_changed();
+                    }
+                })
+        });
+        #[cfg(feature = "serve-render")]
+        let search = search.map(search::handler);
         #[cfg(not(feature = "serve-render"))]
         let live = ();
+        #[cfg(not(feature = "serve-render"))]
+        let search = ();
 
         // Embedded stylesheets (`/_css/`) and the fonts they reference
         // (`/_fonts/`). Both the rendered pages and the directory listing link a
@@ -157,28 +441,101 @@ impl StaticCli {
         // a miss falls through to the user's files.
         let assets = (render || self.directory_listing).then(assets::handler);
 
+        // `--auth`/`--htpasswd`/`--share-link`. Ahead of everything but the
+        // logger and rate limiter, so no route — live reload and `/_css/`
+        // included — answers an unauthorized request.
+        let gate = match self.access.clone().gate(self.server_tls.is_tls()) {
+            Ok(gate) => gate,
+            Err(e) => {
+                eprintln!("could not read htpasswd file {e}");
+                std::process::exit(1);
+            }
+        };
+        // `--lan`: every interface's URL, a QR code and the mDNS advertisement,
+        // held until the server stops. Share links go to other people, so under
+        // `--lan` they name the primary address rather than 0.0.0.0.
+        #[cfg(feature = "serve-lan")]
+        let (_advertisement, share_host) = {
+            let lan_addresses = self.lan.addresses();
+            let advertisement = lan_addresses.as_deref().and_then(|addresses| {
+                self.lan
+                    .announce(addresses, self.port, self.server_tls.cert())
+            });
+            let share_host = lan_addresses
+                .as_deref()
+                .and_then(|addresses| addresses.first())
+                .map_or_else(|| self.host.clone(), |address| address.ip().to_string());
+            (advertisement, share_host)
+        };
+        #[cfg(not(feature = "serve-lan"))]
+        let share_host = self.host.clone();
+        if let Some(gate) = &gate {
+            print_share_links(gate, &self, &share_host);
+        }
+
         let server = (
-            Logger::new(),
+            request_log.handler(""),
             self.rate_limit.limiter(),
+            // Ahead of the auth gate, so CORS preflights (which never carry
+            // credentials) are answered and a 401 still gets the headers.
+            self.response_headers.handler(),
+            gate,
             // `Option<Handler>` is a `Handler`, so `None` skips compression entirely.
             (!self.no_compress).then(trillium_compression::compression),
             live,
-            assets,
-            self.forward
-                .clone()
-                .map(|url| Proxy::new(Client::from(Tls::default()), url)),
+            // `--forward /prefix=URL`: ahead of the file handler and writes, since
+            // the user handed those paths to another server.
+            self.forwards.prefixed(),
+            // The built-in routes and query handlers that answer ahead of the
+            // file handler, grouped to keep the tuple within `Handler`'s arity.
+            (assets, writes, archives, thumbnails, search),
+            self.forwards.fallback(),
             static_file_handler,
             render_handler,
             // Runs only when the file handler resolved a directory it had no
             // index for; otherwise leaves the conn untouched for the 404 path.
             self.directory_listing.then_some(directory_listing),
+            // Last: a navigation nothing above answered gets the app shell.
+            spa,
         );
 
         let config = trillium_smol::config()
             .with_nodelay()
             .with_port(self.port)
-            .with_host(&self.host);
+            .with_host(&host);
 
         self.server_tls.run_with_tls(config, server);
     }
 }
+
+/// Print each `--share-link` URL, for the user to hand out.
+fn print_share_links(gate: &auth::Gate, cli: &StaticCli, host: &str) {
+    let tokens = gate.share_tokens();
+    if tokens.is_empty() {
+        return;
+    }
+    let scheme = if cli.server_tls.is_tls() {
+        "https"
+    } else {
+        "http"
+    };
+    match gate.share_expiry() {
+        Some(expiry) => println!(
+            "share links (single use, expiring in {}):",
+            humantime::format_duration(expiry)
+        ),
+        None => println!("share links (single use):"),
+    }
+    // An IPv6 literal needs brackets to be a URL host.
+    let host = if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    };
+    for token in tokens {
+        println!(
+            "  {}",
+            format!("{scheme}://{host}:{}/?share={token}", cli.port).bold()
+        );
+    }
+}
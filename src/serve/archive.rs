@@ -0,0 +1,379 @@
+//! `--archives`: download a directory as a `.zip` or `.tar.gz`.
+//!
+//! A `GET` of a directory with `?archive=zip` or `?archive=tar.gz` answers with
+//! an archive of everything beneath it, and the directory listing links both.
+//! [`Archives`] sits ahead of the file handler, so a directory with an index
+//! file can be downloaded too.
+//!
+//! The archive is never held in memory: a blocking task walks the tree and
+//! writes the archive into a pipe, and the response body streams from the other
+//! end. A client that goes away closes the pipe, which stops the walk. Entries
+//! that can't be read are skipped with a log line rather than failing the
+//! download, and anything whose real path lies outside the root — a symlink
+//! pointing out of it — is left out, as are symlinked directories, which could
+//! loop. What the listing hides — dotfiles without `--show-hidden`, ignored
+//! files under `--respect-gitignore` — is left out of the archive too.
+
+use crate::directory_listing::{ancestor_gitignores, is_ignored, push_gitignore};
+use blocking::{Unblock, unblock};
+use flate2::{Compression, write::GzEncoder};
+use ignore::gitignore::Gitignore;
+use percent_encoding::percent_decode_str;
+use querystrong::QueryStrong;
+use std::{
+    fs::{self, File},
+    io::{self, PipeWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use trillium::{
+    Body, Conn, Handler,
+    KnownHeaderName::{ContentDisposition, ContentType},
+    Method, Status,
+};
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+/// The archive formats `?archive=` accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Zip,
+    TarGz,
+}
+
+impl Format {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "zip" => Some(Format::Zip),
+            "tar.gz" | "tgz" => Some(Format::TarGz),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::TarGz => "tar.gz",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Zip => "application/zip",
+            Format::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// Answers `?archive=` requests for directories beneath the root; passes
+/// everything else through.
+#[derive(Debug, Clone)]
+pub struct Archives {
+    root: PathBuf,
+    show_hidden: bool,
+    gitignore: bool,
+}
+
+impl Archives {
+    pub fn new(root: PathBuf) -> Self {
+        let root = root.canonicalize().unwrap_or(root);
+        Self {
+            root,
+            show_hidden: false,
+            gitignore: false,
+        }
+    }
+
+    /// Include dotfiles, as the listing does under `--show-hidden`.
+    pub fn with_hidden_files(self) -> Self {
+        Self {
+            show_hidden: true,
+            ..self
+        }
+    }
+
+    /// Leave out what the root's `.gitignore` files ignore, as the listing
+    /// does under `--respect-gitignore`.
+    pub fn with_gitignore(self) -> Self {
+        Self {
+            gitignore: true,
+            ..self
+        }
+    }
+
+    /// The directory a request path names, if it is one beneath the root once
+    /// symlinks are followed.
+    fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode_str(url_path).decode_utf8().ok()?;
+        let mut path = self.root.clone();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                segment => path.push(segment),
+            }
+        }
+        let path = path.canonicalize().ok()?;
+        (path.starts_with(&self.root) && path.is_dir()).then_some(path)
+    }
+}
+
+impl Handler for Archives {
+    async fn run(&self, conn: Conn) -> Conn {
+        if conn.method() != Method::Get {
+            return conn;
+        }
+        let Some(format) = QueryStrong::parse(conn.querystring())
+            .get_str("archive")
+            .and_then(Format::parse)
+        else {
+            return conn;
+        };
+        let Some(dir) = self.resolve(conn.path()) else {
+            return conn;
+        };
+
+        // The folder the archive unpacks into, named for the directory.
+        let name = dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("archive")
+            .to_string();
+        let (reader, writer) = match io::pipe() {
+            Ok(pipe) => pipe,
+            Err(error) => {
+                log::error!("could not open a pipe for {}: {error}", dir.display());
+                return conn.with_status(Status::InternalServerError).halt();
+            }
+        };
+
+        let root = self.root.clone();
+        let prefix = name.clone();
+        let (show_hidden, gitignore) = (self.show_hidden, self.gitignore);
+        unblock(move || {
+            let mut skip = Skip {
+                show_hidden,
+                gitignore,
+                ignores: if gitignore {
+                    ancestor_gitignores(&root, &dir)
+                } else {
+                    Vec::new()
+                },
+            };
+            let result = match format {
+                Format::Zip => write_zip(&root, &dir, &prefix, &mut skip, writer),
+                Format::TarGz => write_tar_gz(&root, &dir, &prefix, &mut skip, writer),
+            };
+            match result {
+                Ok(()) => {}
+                Err(error) if error.kind() == io::ErrorKind::BrokenPipe => {
+                    log::debug!("client went away mid-archive of {}", dir.display());
+                }
+                Err(error) => log::warn!("archive of {} cut short: {error}", dir.display()),
+            }
+        })
+        .detach();
+
+        let file_name = format!("{name}.{}", format.extension()).replace(['"', '\\'], "_");
+        conn.with_response_header(ContentType, format.content_type())
+            .with_response_header(
+                ContentDisposition,
+                format!("attachment; filename=\"{file_name}\""),
+            )
+            .with_body(Body::new_streaming(Unblock::new(reader), None))
+            .with_status(Status::Ok)
+            .halt()
+    }
+}
+
+/// One entry to archive: its path on disk and its name inside the archive.
+struct Entry {
+    path: PathBuf,
+    name: String,
+    metadata: fs::Metadata,
+}
+
+/// What the listing hides, and so the archive leaves out.
+struct Skip {
+    show_hidden: bool,
+    gitignore: bool,
+    /// The `.gitignore` of every directory from the root down to the one being
+    /// walked, when `gitignore` is set.
+    ignores: Vec<Gitignore>,
+}
+
+impl Skip {
+    fn skips(&self, file_name: &str, path: &Path, is_dir: bool) -> bool {
+        (file_name.starts_with('.') && !self.show_hidden) || is_ignored(&self.ignores, path, is_dir)
+    }
+}
+
+/// Walk `dir` depth-first, calling `visit` for each directory (before its
+/// contents) and file. Unreadable entries, symlinks whose targets are outside
+/// `root`, symlinked directories and whatever `skip` hides are skipped.
+fn walk(
+    root: &Path,
+    dir: &Path,
+    name: &str,
+    skip: &mut Skip,
+    visit: &mut impl FnMut(Entry) -> io::Result<()>,
+) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            log::info!("archive: skipping {}: {error}", dir.display());
+            return Ok(());
+        }
+    };
+    let mut entries = entries.filter_map(Result::ok).collect::<Vec<_>>();
+    entries.sort_by_key(fs::DirEntry::file_name);
+
+    for entry in entries {
+        let path = entry.path();
+        let Some(file_name) = entry.file_name().to_str().map(str::to_string) else {
+            log::info!("archive: skipping {}: name is not utf-8", path.display());
+            continue;
+        };
+        let Ok(link_metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        let metadata = if link_metadata.is_symlink() {
+            match path.canonicalize().and_then(|real| {
+                if real.starts_with(root) {
+                    fs::metadata(&real)
+                } else {
+                    Err(io::ErrorKind::PermissionDenied.into())
+                }
+            }) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => {
+                    log::info!("archive: skipping symlink {}", path.display());
+                    continue;
+                }
+            }
+        } else {
+            link_metadata
+        };
+
+        if skip.skips(&file_name, &path, metadata.is_dir()) {
+            continue;
+        }
+
+        let name = format!("{name}/{file_name}");
+        if metadata.is_dir() {
+            visit(Entry {
+                path: path.clone(),
+                name: name.clone(),
+                metadata,
+            })?;
+            let pushed = skip.gitignore && push_gitignore(&mut skip.ignores, &path);
+            let walked = walk(root, &path, &name, skip, visit);
+            if pushed {
+                skip.ignores.pop();
+            }
+            walked?;
+        } else if metadata.is_file() {
+            visit(Entry {
+                path,
+                name,
+                metadata,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Open a file to archive, or log and return `None` if it can't be read.
+fn open(path: &Path) -> Option<File> {
+    File::open(path)
+        .inspect_err(|error| log::info!("archive: skipping {}: {error}", path.display()))
+        .ok()
+}
+
+fn write_zip(
+    root: &Path,
+    dir: &Path,
+    name: &str,
+    skip: &mut Skip,
+    writer: PipeWriter,
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new_stream(writer);
+    // Directories go in via `start_file` with a trailing slash, not
+    // `add_directory`: in stream mode the latter flags a data descriptor it
+    // never writes, which unzip rejects.
+    zip.start_file(format!("{name}/"), zip_options(&fs::metadata(dir)?))?;
+    walk(root, dir, name, skip, &mut |entry| {
+        let options = zip_options(&entry.metadata);
+        if entry.metadata.is_dir() {
+            zip.start_file(format!("{}/", entry.name), options)?;
+            return Ok(());
+        }
+        let Some(mut file) = open(&entry.path) else {
+            return Ok(());
+        };
+        zip.start_file(entry.name, options)?;
+        io::copy(&mut file, &mut zip)?;
+        Ok(())
+    })?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Per-entry zip options: the entry's mtime and (on unix) mode, deflate for
+/// files and zip64 for those too big without it.
+fn zip_options(metadata: &fs::Metadata) -> SimpleFileOptions {
+    let mut options = SimpleFileOptions::default();
+    options = if metadata.is_dir() {
+        options.compression_method(CompressionMethod::Stored)
+    } else {
+        options
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(metadata.len() >= u64::from(u32::MAX))
+    };
+    if let Some(modified) = metadata.modified().ok().and_then(zip_time) {
+        options = options.last_modified_time(modified);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        options = options.unix_permissions(metadata.permissions().mode());
+    }
+    options
+}
+
+fn write_tar_gz(
+    root: &Path,
+    dir: &Path,
+    name: &str,
+    skip: &mut Skip,
+    writer: PipeWriter,
+) -> io::Result<()> {
+    let mut tar = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+    tar.append_dir(name, dir)?;
+    walk(root, dir, name, skip, &mut |entry| {
+        if entry.metadata.is_dir() {
+            return tar.append_dir(&entry.name, &entry.path);
+        }
+        match open(&entry.path) {
+            Some(mut file) => tar.append_file(&entry.name, &mut file),
+            None => Ok(()),
+        }
+    })?;
+    tar.into_inner()?.finish()?.flush()
+}
+
+/// A modification time as a zip timestamp (UTC, two-second resolution), or
+/// `None` outside the years zip can represent.
+fn zip_time(modified: SystemTime) -> Option<zip::DateTime> {
+    // `YYYY-MM-DDTHH:MM:SSZ`
+    let rfc3339 = humantime::format_rfc3339_seconds(modified).to_string();
+    let field = |range: std::ops::Range<usize>| rfc3339.get(range)?.parse::<u16>().ok();
+    zip::DateTime::from_date_and_time(
+        field(0..4)?,
+        field(5..7)?.try_into().ok()?,
+        field(8..10)?.try_into().ok()?,
+        field(11..13)?.try_into().ok()?,
+        field(14..16)?.try_into().ok()?,
+        field(17..19)?.try_into().ok()?,
+    )
+    .ok()
+}
@@ -0,0 +1,429 @@
+//! Archives as roots: `serve docs.zip` serves a zip or tarball's entries
+//! without extracting it.
+//!
+//! [`ArchiveRoot::open`] indexes the archive once, at startup — a zip's central
+//! directory, or a walk over a tarball's headers. Stored zip entries and the
+//! files of a plain `.tar` are then read straight out of the archive at their
+//! offset, so ranges are cheap; deflated zip entries are inflated per request,
+//! streamed from the start of the range. A gzipped tarball can't be read at an
+//! offset without inflating everything before it, so a `.tar.gz` is inflated
+//! into memory once instead, and refused if that would take more than
+//! [`MAX_INFLATED`] bytes.
+//!
+//! Only directories and regular files are served: links, devices and
+//! encrypted zip entries are left out, as is any name that would climb out of
+//! the root. The archive isn't watched; restart to serve a new one.
+
+use super::content::{Spec, if_range_holds, parse_specs, resolve_ranges};
+use crate::directory_listing::Entry;
+use blocking::{Unblock, unblock};
+use flate2::read::{DeflateDecoder, GzDecoder};
+use futures_lite::io::AsyncReadExt;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug},
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use trillium::{
+    Body, Conn,
+    KnownHeaderName::{AcceptRanges, ContentRange, Etag, LastModified, Range},
+    Status,
+};
+use trillium_static::StaticConnExt;
+use zip::{CompressionMethod, ZipArchive};
+
+/// The most a `.tar.gz` may inflate to, since it's held in memory: 512 MiB.
+const MAX_INFLATED: u64 = 512 << 20;
+
+/// Whether `path` is a file `serve` reads as an archive root, going by its
+/// name: `.zip`, `.tar`, `.tar.gz` or `.tgz`.
+pub fn is_archive(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    path.is_file()
+        && [".zip", ".tar", ".tar.gz", ".tgz"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+}
+
+/// An archive's entries, served as a read-only tree.
+pub struct ArchiveRoot {
+    path: PathBuf,
+    /// Every directory and file, keyed by its `/`-separated path; the root is
+    /// `""`. Directories that have no entry of their own are implied by the
+    /// paths beneath them.
+    entries: BTreeMap<String, Node>,
+    data: Data,
+}
+
+/// Where the entries' bytes are read from.
+enum Data {
+    /// A zip, held open for the entries that aren't plain deflate.
+    Zip(Mutex<ZipArchive<File>>),
+    /// A plain tar, read at offsets.
+    Tar,
+    /// A `.tar.gz`, inflated.
+    Memory(Vec<u8>),
+}
+
+struct Node {
+    modified: Option<SystemTime>,
+    kind: Kind,
+}
+
+enum Kind {
+    Dir,
+    File { len: u64, content: Content },
+}
+
+#[derive(Clone, Copy)]
+enum Content {
+    /// Stored uncompressed at this offset into the archive (or the inflated
+    /// tarball).
+    At(u64),
+    /// Deflated, `compressed` bytes at this offset into the zip.
+    Deflated { at: u64, compressed: u64 },
+    /// The zip entry at this index, read through the zip crate.
+    Zipped(usize),
+}
+
+impl Debug for ArchiveRoot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveRoot")
+            .field("path", &self.path)
+            .field("entries", &self.entries.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ArchiveRoot {
+    /// Index the archive at `path`, which [`is_archive`] accepted.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let name = path.to_string_lossy().to_ascii_lowercase();
+        let (mut entries, data) = if name.ends_with(".zip") {
+            index_zip(File::open(path)?)?
+        } else if name.ends_with(".tar") {
+            (index_tar(File::open(path)?)?, Data::Tar)
+        } else {
+            let mut bytes = Vec::new();
+            GzDecoder::new(File::open(path)?)
+                .take(MAX_INFLATED + 1)
+                .read_to_end(&mut bytes)?;
+            if bytes.len() as u64 > MAX_INFLATED {
+                return Err(io::Error::other(format!(
+                    "inflates to more than {} MiB; extract it, or serve it as a plain .tar",
+                    MAX_INFLATED >> 20
+                )));
+            }
+            (index_tar(bytes.as_slice())?, Data::Memory(bytes))
+        };
+        entries.insert(
+            String::new(),
+            Node {
+                modified: path.metadata().and_then(|meta| meta.modified()).ok(),
+                kind: Kind::Dir,
+            },
+        );
+        log::info!(
+            "serving {} entries from {}",
+            entries.len() - 1,
+            path.display()
+        );
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+            data,
+        })
+    }
+
+    /// The archive file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether `name` is a directory in the archive; `""` is the root.
+    pub fn is_dir(&self, name: &str) -> bool {
+        matches!(
+            self.entries.get(name),
+            Some(Node {
+                kind: Kind::Dir,
+                ..
+            })
+        )
+    }
+
+    /// Whether `name` is a file in the archive.
+    pub fn is_file(&self, name: &str) -> bool {
+        matches!(
+            self.entries.get(name),
+            Some(Node {
+                kind: Kind::File { .. },
+                ..
+            })
+        )
+    }
+
+    /// The entries directly inside the directory `dir`, or `None` if it isn't
+    /// one.
+    pub fn read_dir(&self, dir: &str) -> Option<Vec<Entry>> {
+        if !self.is_dir(dir) {
+            return None;
+        }
+        let prefix = if dir.is_empty() {
+            String::new()
+        } else {
+            format!("{dir}/")
+        };
+        let entries = self
+            .entries
+            .range(prefix.clone()..)
+            .take_while(|(name, _)| name.starts_with(&prefix))
+            .filter_map(|(name, node)| {
+                let name = &name[prefix.len()..];
+                (!name.is_empty() && !name.contains('/')).then(|| Entry {
+                    name: name.to_string(),
+                    is_dir: matches!(node.kind, Kind::Dir),
+                    len: match node.kind {
+                        Kind::Dir => None,
+                        Kind::File { len, .. } => Some(len),
+                    },
+                    modified: node.modified,
+                })
+            })
+            .collect();
+        Some(entries)
+    }
+
+    /// Serve the file `name` the way the static handler serves one from disk:
+    /// with its content type, `Last-Modified`, an etag and `Accept-Ranges`,
+    /// answering a single `Range` with a 206. A name that isn't a file is a
+    /// 404.
+    pub async fn send(self: &Arc<Self>, mut conn: Conn, name: &str) -> Conn {
+        let Some(&Node {
+            modified,
+            kind: Kind::File { len, content },
+        }) = self.entries.get(name)
+        else {
+            return conn.with_status(Status::NotFound).halt();
+        };
+
+        let mtime = modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let headers = conn.response_headers_mut();
+        if let Some(modified) = modified {
+            headers.insert(LastModified, httpdate::fmt_http_date(modified));
+        }
+        headers.insert(Etag, format!("\"{:x}-{len:x}\"", mtime.as_secs()));
+        headers.insert(AcceptRanges, "bytes");
+        let mut conn = conn.with_mime_from_path(name);
+
+        let range = conn
+            .request_headers()
+            .get_str(Range)
+            .and_then(parse_specs)
+            .filter(|specs| specs.len() == 1 && if_range_holds(&conn));
+        let (status, start, part) = match range.as_deref().map(|spec| range_of(spec, len)) {
+            None => (Status::Ok, 0, len),
+            Some(Some((start, end))) => {
+                conn.response_headers_mut()
+                    .insert(ContentRange, format!("bytes {start}-{end}/{len}"));
+                (Status::PartialContent, start, end - start + 1)
+            }
+            Some(None) => {
+                conn.response_headers_mut()
+                    .insert(ContentRange, format!("bytes */{len}"));
+                return conn
+                    .with_status(Status::RequestedRangeNotSatisfiable)
+                    .with_body("")
+                    .halt();
+            }
+        };
+
+        match self.read(content, start, part).await {
+            Ok(body) => conn.with_status(status).with_body(body).halt(),
+            Err(error) => {
+                log::warn!(
+                    "could not read {name} from {}: {error}",
+                    self.path.display()
+                );
+                conn.with_status(Status::InternalServerError).halt()
+            }
+        }
+    }
+
+    /// `len` bytes of an entry, from `start`.
+    async fn read(self: &Arc<Self>, content: Content, start: u64, len: u64) -> io::Result<Body> {
+        match (&self.data, content) {
+            (Data::Memory(bytes), Content::At(offset)) => slice(bytes, offset + start, len),
+            (_, Content::At(offset)) => {
+                let mut file = File::open(&self.path)?;
+                file.seek(SeekFrom::Start(offset + start))?;
+                Ok(Body::new_streaming(Unblock::new(file).take(len), Some(len)))
+            }
+            (_, Content::Deflated { at, compressed }) => {
+                let path = self.path.clone();
+                let decoder = unblock(move || {
+                    let mut file = File::open(path)?;
+                    file.seek(SeekFrom::Start(at))?;
+                    let mut decoder = DeflateDecoder::new(file.take(compressed));
+                    skip(&mut decoder, start)?;
+                    Ok::<_, io::Error>(decoder)
+                })
+                .await?;
+                Ok(Body::new_streaming(
+                    Unblock::new(decoder).take(len),
+                    Some(len),
+                ))
+            }
+            (_, Content::Zipped(index)) => {
+                let archive = Arc::clone(self);
+                let bytes = unblock(move || archive.inflate(index, start, len)).await?;
+                Ok(Body::from(bytes))
+            }
+        }
+    }
+
+    /// `len` bytes of the zip entry at `index`, from `start`, inflated.
+    fn inflate(&self, index: usize, start: u64, len: u64) -> io::Result<Vec<u8>> {
+        let Data::Zip(zip) = &self.data else {
+            return Err(io::Error::other("not a zip"));
+        };
+        let mut zip = zip.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut file = zip.by_index(index)?;
+        skip(&mut file, start)?;
+        let mut bytes = Vec::with_capacity(usize::try_from(len).unwrap_or_default());
+        file.take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(io::Error::other("entry shorter than its recorded size"));
+        }
+        Ok(bytes)
+    }
+}
+
+/// Read past the first `len` bytes of `reader`.
+fn skip(reader: &mut impl Read, len: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.by_ref().take(len), &mut io::sink())?;
+    if skipped < len {
+        return Err(io::Error::other("entry shorter than its recorded size"));
+    }
+    Ok(())
+}
+
+/// `len` bytes of `bytes` from `start`, as a body.
+fn slice(bytes: &[u8], start: u64, len: u64) -> io::Result<Body> {
+    usize::try_from(start)
+        .ok()
+        .zip(usize::try_from(len).ok())
+        .and_then(|(start, len)| bytes.get(start..start.checked_add(len)?))
+        .map(|bytes| Body::from(bytes.to_vec()))
+        .ok_or_else(|| io::Error::other("entry shorter than its recorded size"))
+}
+
+/// The one range `spec` selects in an entry of `len` bytes, or `None` if it's
+/// unsatisfiable.
+fn range_of(spec: &[Spec], len: u64) -> Option<(u64, u64)> {
+    resolve_ranges(spec, len).first().copied()
+}
+
+/// An entry's path as a key: its normal components joined with `/`, or `None`
+/// for a name that isn't UTF-8 or would climb out of the root.
+fn key(path: &Path) -> Option<String> {
+    let mut segments = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(segment) => segments.push(segment.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!segments.is_empty()).then(|| segments.join("/"))
+}
+
+/// Add `node` at `name`, and every directory above it that isn't there yet.
+fn insert(entries: &mut BTreeMap<String, Node>, name: String, node: Node) {
+    let mut end = 0;
+    while let Some(slash) = name[end..].find('/') {
+        end += slash;
+        entries.entry(name[..end].to_string()).or_insert(Node {
+            modified: None,
+            kind: Kind::Dir,
+        });
+        end += 1;
+    }
+    entries.insert(name, node);
+}
+
+fn index_zip(file: File) -> io::Result<(BTreeMap<String, Node>, Data)> {
+    let mut zip = ZipArchive::new(file)?;
+    let mut entries = BTreeMap::new();
+    for index in 0..zip.len() {
+        let file = zip.by_index_raw(index)?;
+        let Some(name) = file.enclosed_name().as_deref().and_then(key) else {
+            continue;
+        };
+        let modified = file.last_modified().and_then(zip_time);
+        let kind = if file.is_dir() {
+            Kind::Dir
+        } else if file.is_file() && !file.encrypted() {
+            let content = match (file.compression(), file.data_start()) {
+                (CompressionMethod::Stored, Some(start)) => Content::At(start),
+                (CompressionMethod::Deflated, Some(at)) => Content::Deflated {
+                    at,
+                    compressed: file.compressed_size(),
+                },
+                _ => Content::Zipped(index),
+            };
+            Kind::File {
+                len: file.size(),
+                content,
+            }
+        } else {
+            continue;
+        };
+        insert(&mut entries, name, Node { modified, kind });
+    }
+    Ok((entries, Data::Zip(Mutex::new(zip))))
+}
+
+/// A zip timestamp, which has no time zone, read as UTC.
+fn zip_time(stamp: zip::DateTime) -> Option<SystemTime> {
+    let month = time::Month::try_from(stamp.month()).ok()?;
+    let date = time::Date::from_calendar_date(stamp.year().into(), month, stamp.day()).ok()?;
+    let datetime = date
+        .with_hms(stamp.hour(), stamp.minute(), stamp.second())
+        .ok()?;
+    Some(datetime.assume_utc().into())
+}
+
+fn index_tar(reader: impl Read) -> io::Result<BTreeMap<String, Node>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = BTreeMap::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let Some(name) = key(&entry.path()?) else {
+            continue;
+        };
+        let header = entry.header();
+        let modified = header
+            .mtime()
+            .ok()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        let kind = match header.entry_type() {
+            kind if kind.is_dir() => Kind::Dir,
+            kind if kind.is_file() || kind.is_contiguous() => Kind::File {
+                len: entry.size(),
+                content: Content::At(entry.raw_file_position()),
+            },
+            _ => continue,
+        };
+        insert(&mut entries, name, Node { modified, kind });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,476 @@
+//! Access control for `serve`: basic auth and share links.
+//!
+//! `--auth user:pass` and `--htpasswd FILE` put every route behind HTTP basic
+//! auth — the live-reload routes and the embedded `/_css/` assets included,
+//! since [`Gate`] sits ahead of all of them. Htpasswd entries may be bcrypt
+//! (`$2y$`), apr1 (`$apr1$`) or `{SHA}` hashes, as `htpasswd -B`, `-m` and `-s`
+//! write them. A successful bcrypt check is remembered for the life of the
+//! process, so a page's dozen asset requests don't each pay for a hash.
+//!
+//! `--share-link` prints random, unguessable URLs at startup and turns away
+//! anyone without one. Each link works once: the first visit swaps the token
+//! for a session cookie and redirects to the same URL without it, so relative
+//! links (and reloads) keep working and the token doesn't linger in the address
+//! bar or a `Referer`. `--share-link-expiry` bounds both the links and the
+//! sessions they start.
+//!
+//! With both configured, either a share session or valid credentials gets in.
+
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use clap::Parser;
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    fs, io,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use trillium::{
+    Conn, Handler,
+    KnownHeaderName::{Authorization, Cookie, Location, SetCookie, WwwAuthenticate},
+    Status,
+};
+
+/// The query param a share link carries its token in.
+const SHARE_PARAM: &str = "share";
+
+/// The cookie a redeemed share link leaves behind.
+const SESSION_COOKIE: &str = "trillium_serve_share";
+
+/// The `--auth`/`--htpasswd`/`--share-link` flags, flattened into `serve`'s
+/// args.
+#[derive(Parser, Debug, Clone)]
+pub struct Access {
+    /// require HTTP basic auth with these credentials
+    ///
+    /// Applies to every route, including the live-reload and stylesheet
+    /// routes. Prefer the AUTH env var to keep the password out of your shell
+    /// history and the process list.
+    #[arg(
+        long,
+        env,
+        value_name = "USER:PASS",
+        value_parser = parse_credentials,
+        help_heading = "Access"
+    )]
+    auth: Option<(String, String)>,
+
+    /// require HTTP basic auth against an htpasswd file
+    ///
+    /// Understands bcrypt, apr1 (MD5) and {SHA} entries. Combines with --auth.
+    #[arg(long, env, value_name = "FILE", help_heading = "Access")]
+    htpasswd: Option<PathBuf>,
+
+    /// print COUNT one-time share links at startup and reject requests without
+    /// one
+    ///
+    /// Each link's first visit sets a session cookie and redirects to the same
+    /// page without the token, so relative links keep working. Defaults to a
+    /// single link.
+    #[arg(
+        long,
+        env,
+        value_name = "COUNT",
+        num_args = 0..=1,
+        default_missing_value = "1",
+        help_heading = "Access"
+    )]
+    share_link: Option<usize>,
+
+    /// expire share links, and the sessions they start, after DURATION, e.g.
+    /// 1h or 30min
+    #[arg(
+        long,
+        env,
+        value_name = "DURATION",
+        value_parser = humantime::parse_duration,
+        requires = "share_link",
+        help_heading = "Access"
+    )]
+    share_link_expiry: Option<Duration>,
+}
+
+fn parse_credentials(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((user, pass)) if !user.is_empty() => Ok((user.to_string(), pass.to_string())),
+        _ => Err(format!("expected USER:PASS (got {s:?})")),
+    }
+}
+
+impl Access {
+    /// The access gate, or `None` when no access flag was given. `secure` marks
+    /// the share session cookie `Secure`, for when serving over TLS.
+    pub fn gate(self, secure: bool) -> io::Result<Option<Gate>> {
+        let auth_required = self.auth.is_some() || self.htpasswd.is_some();
+        let mut users = HashMap::new();
+        if let Some(path) = &self.htpasswd {
+            let htpasswd = fs::read_to_string(path).map_err(|error| {
+                io::Error::new(error.kind(), format!("{}: {error}", path.display()))
+            })?;
+            users.extend(parse_htpasswd(&htpasswd));
+            if users.is_empty() {
+                log::warn!("{} has no usable entries", path.display());
+            }
+        }
+        if let Some((user, pass)) = self.auth {
+            users.insert(user, Hash::Plain(pass));
+        }
+        let basic = auth_required.then(|| Basic {
+            decoy: decoy(&users),
+            users,
+            verified: Mutex::default(),
+        });
+
+        let shares = self.share_link.map(|count| {
+            let expires = self.share_link_expiry.map(|ttl| Instant::now() + ttl);
+            Shares {
+                links: Mutex::new((0..count).map(|_| (random_token(), expires)).collect()),
+                sessions: Mutex::default(),
+                ttl: self.share_link_expiry,
+                secure,
+            }
+        });
+
+        Ok((basic.is_some() || shares.is_some()).then_some(Gate { basic, shares }))
+    }
+}
+
+/// Turns away requests that carry neither a share session nor valid basic
+/// auth credentials. Sits ahead of every other route.
+#[derive(Debug)]
+pub struct Gate {
+    basic: Option<Basic>,
+    shares: Option<Shares>,
+}
+
+impl Gate {
+    /// The not-yet-redeemed share tokens, for printing as links at startup.
+    pub fn share_tokens(&self) -> Vec<String> {
+        self.shares.as_ref().map_or_else(Vec::new, |shares| {
+            shares.links.lock().unwrap().keys().cloned().collect()
+        })
+    }
+
+    /// When the share links stop working, if they expire.
+    pub fn share_expiry(&self) -> Option<Duration> {
+        self.shares.as_ref().and_then(|shares| shares.ttl)
+    }
+}
+
+impl Handler for Gate {
+    async fn run(&self, conn: Conn) -> Conn {
+        if let Some(shares) = &self.shares {
+            if let Some(token) = share_token(conn.querystring())
+                && let Some((session, expires)) = shares.redeem(token)
+            {
+                return shares.start_session(conn, session, expires);
+            }
+            if shares.has_session(&conn) {
+                return conn;
+            }
+        }
+
+        match &self.basic {
+            Some(basic) if basic.authorizes(&conn).await => conn,
+            Some(_) => conn
+                .with_response_header(
+                    WwwAuthenticate,
+                    r#"Basic realm="trillium serve", charset="UTF-8""#,
+                )
+                .with_status(Status::Unauthorized)
+                .halt(),
+            None => conn
+                .with_body(
+                    "open this server through a share link; each works once, and may expire\n",
+                )
+                .with_status(Status::Forbidden)
+                .halt(),
+        }
+    }
+}
+
+/// A stored password, as `--auth` gives it or an htpasswd line hashes it.
+#[derive(Debug, Clone)]
+enum Hash {
+    Plain(String),
+    Bcrypt(String),
+    Apr1 { salt: String, hash: String },
+    Sha1(String),
+}
+
+/// Parse the entries of an htpasswd file, skipping (with a warning) any whose
+/// hash format isn't supported.
+fn parse_htpasswd(htpasswd: &str) -> impl Iterator<Item = (String, Hash)> + '_ {
+    htpasswd.lines().filter_map(|line| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (user, hash) = line.split_once(':')?;
+        let hash = if hash.starts_with("$2") {
+            Hash::Bcrypt(hash.to_string())
+        } else if let Some(rest) = hash.strip_prefix("$apr1$") {
+            let (salt, hash) = rest.split_once('$')?;
+            Hash::Apr1 {
+                salt: salt.to_string(),
+                hash: hash.to_string(),
+            }
+        } else if let Some(digest) = hash.strip_prefix("{SHA}") {
+            Hash::Sha1(digest.to_string())
+        } else {
+            log::warn!("htpasswd: skipping {user}, whose hash format is not supported");
+            return None;
+        };
+        Some((user.to_string(), hash))
+    })
+}
+
+/// The hash an unknown username's password is checked against, so that
+/// turning it away takes as long as a wrong password for a real one: one of
+/// the users' own, a bcrypt one if there is one, since those are the slow ones.
+fn decoy(users: &HashMap<String, Hash>) -> Hash {
+    users
+        .values()
+        .find(|hash| matches!(hash, Hash::Bcrypt(_)))
+        .or_else(|| users.values().next())
+        .cloned()
+        .unwrap_or_else(|| Hash::Plain(random_token()))
+}
+
+/// Basic auth against `--auth` and `--htpasswd`.
+#[derive(Debug)]
+struct Basic {
+    users: HashMap<String, Hash>,
+    /// Checked in place of a user that doesn't exist; see [`decoy`].
+    decoy: Hash,
+    /// SHA-1 digests of `Authorization` headers already checked against a
+    /// bcrypt hash, which is deliberately slow.
+    verified: Mutex<HashSet<[u8; 20]>>,
+}
+
+impl Basic {
+    async fn authorizes(&self, conn: &Conn) -> bool {
+        let Some(header) = conn.request_headers().get_str(Authorization) else {
+            return false;
+        };
+        let Some((user, pass)) = header
+            .strip_prefix("Basic ")
+            .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| {
+                let (user, pass) = decoded.split_once(':')?;
+                Some((user.to_string(), pass.to_string()))
+            })
+        else {
+            return false;
+        };
+        let (hash, known) = match self.users.get(&user) {
+            Some(hash) => (hash, true),
+            None => (&self.decoy, false),
+        };
+
+        let matches = match hash {
+            Hash::Plain(expected) => constant_time_eq(pass.as_bytes(), expected.as_bytes()),
+            Hash::Sha1(expected) => constant_time_eq(
+                STANDARD.encode(Sha1::digest(pass.as_bytes())).as_bytes(),
+                expected.as_bytes(),
+            ),
+            Hash::Apr1 { salt, hash } => {
+                constant_time_eq(apr1(pass.as_bytes(), salt).as_bytes(), hash.as_bytes())
+            }
+            Hash::Bcrypt(hash) => {
+                let key: [u8; 20] = Sha1::digest(header.as_bytes()).into();
+                if self.verified.lock().unwrap().contains(&key) {
+                    return true;
+                }
+                let hash = hash.clone();
+                let verified = blocking::unblock(move || bcrypt::verify(pass, &hash))
+                    .await
+                    .unwrap_or(false);
+                if verified && known {
+                    self.verified.lock().unwrap().insert(key);
+                }
+                verified
+            }
+        };
+        known && matches
+    }
+}
+
+/// Compare without returning early, so the time taken says nothing about
+/// how much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Apache's variant of MD5-crypt, as `htpasswd -m` writes it: the encoded hash
+/// that follows `$apr1$SALT$`.
+fn apr1(password: &[u8], salt: &str) -> String {
+    const MAGIC: &[u8] = b"$apr1$";
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+
+    let alternate = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+    let mut context = Md5::new()
+        .chain_update(password)
+        .chain_update(MAGIC)
+        .chain_update(salt);
+    for chunk in password.chunks(16) {
+        context.update(&alternate[..chunk.len()]);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            context.update([0]);
+        } else {
+            context.update(&password[..1]);
+        }
+        length >>= 1;
+    }
+    let mut digest = context.finalize();
+
+    for round in 0..1000 {
+        let mut context = Md5::new();
+        if round & 1 == 1 {
+            context.update(password);
+        } else {
+            context.update(digest);
+        }
+        if round % 3 != 0 {
+            context.update(salt);
+        }
+        if round % 7 != 0 {
+            context.update(password);
+        }
+        if round & 1 == 1 {
+            context.update(digest);
+        } else {
+            context.update(password);
+        }
+        digest = context.finalize();
+    }
+
+    const ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut encoded = String::with_capacity(22);
+    let mut push = |mut value: u32, chars: usize| {
+        for _ in 0..chars {
+            encoded.push(ALPHABET[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+    for [a, b, c] in [[0, 6, 12], [1, 7, 13], [2, 8, 14], [3, 9, 15], [4, 10, 5]] {
+        push(
+            (u32::from(digest[a]) << 16) | (u32::from(digest[b]) << 8) | u32::from(digest[c]),
+            4,
+        );
+    }
+    push(u32::from(digest[11]), 2);
+    encoded
+}
+
+/// `--share-link`: the unredeemed links, and the sessions redeemed ones
+/// started. Both map a random token to its expiry, if any.
+#[derive(Debug)]
+struct Shares {
+    links: Mutex<HashMap<String, Option<Instant>>>,
+    sessions: Mutex<HashMap<String, Option<Instant>>>,
+    ttl: Option<Duration>,
+    secure: bool,
+}
+
+impl Shares {
+    /// Spend a link, returning the session it starts (and when that session
+    /// expires) if the link was still good.
+    fn redeem(&self, token: &str) -> Option<(String, Option<Instant>)> {
+        let expires = self.links.lock().unwrap().remove(token)?;
+        if expires.is_some_and(|expires| expires <= Instant::now()) {
+            return None;
+        }
+        let session = random_token();
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, expires| expires.is_none_or(|expires| expires > now));
+        sessions.insert(session.clone(), expires);
+        Some((session, expires))
+    }
+
+    fn has_session(&self, conn: &Conn) -> bool {
+        let Some(session) = conn
+            .request_headers()
+            .get_str(Cookie)
+            .and_then(|cookies| cookie(cookies, SESSION_COOKIE))
+        else {
+            return false;
+        };
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(session)
+            .is_some_and(|expires| expires.is_none_or(|expires| expires > Instant::now()))
+    }
+
+    /// Set the session cookie and send the browser back to the URL it asked
+    /// for, minus the share token.
+    fn start_session(&self, conn: Conn, session: String, expires: Option<Instant>) -> Conn {
+        let mut cookie = format!("{SESSION_COOKIE}={session}; Path=/; HttpOnly; SameSite=Lax");
+        if let Some(expires) = expires {
+            let remaining = expires.saturating_duration_since(Instant::now());
+            let _ = write!(cookie, "; Max-Age={}", remaining.as_secs());
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+
+        let remaining = conn
+            .querystring()
+            .split('&')
+            .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(SHARE_PARAM))
+            .collect::<Vec<_>>()
+            .join("&");
+        let location = if remaining.is_empty() {
+            conn.path().to_string()
+        } else {
+            format!("{}?{remaining}", conn.path())
+        };
+
+        conn.with_response_header(SetCookie, cookie)
+            .with_response_header(Location, location)
+            .with_status(Status::SeeOther)
+            .halt()
+    }
+}
+
+/// The share token in a querystring, if there is one.
+fn share_token(querystring: &str) -> Option<&str> {
+    querystring.split('&').find_map(|pair| {
+        pair.split_once('=')
+            .filter(|(name, _)| *name == SHARE_PARAM)
+            .map(|(_, token)| token)
+    })
+}
+
+/// The value of the cookie `name` in a `Cookie` request header.
+fn cookie<'a>(cookies: &'a str, name: &str) -> Option<&'a str> {
+    cookies.split(';').find_map(|pair| {
+        pair.trim()
+            .split_once('=')
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    })
+}
+
+/// 256 random bits, URL-safe.
+fn random_token() -> String {
+    let mut bytes = [0; 32];
+    getrandom::fill(&mut bytes).expect("the system random number generator failed");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
@@ -0,0 +1,491 @@
+//! `--mime`, `--mime-file` and `--download`: what a served file is sent as —
+//! plus `304 Not Modified` answers to `If-None-Match` and `If-Modified-Since`,
+//! and `multipart/byteranges` answers to multi-range requests, which the
+//! static handler only parses one range of.
+//!
+//! [`Files`] wraps the roots' file handlers ([`Layers`]) and does its work in
+//! `before_send`, on responses the static handler produced. Being at the file
+//! handler's place in the tuple, that runs after `?render` has had its turn:
+//! [`render`](super::render) strips `Accept-Ranges` from the pages it builds,
+//! so a rendered page keeps its own content type and is never a download, nor
+//! a 304 on the strength of its source file's etag.
+//!
+//! A multi-range `Range` (`bytes=0-99,500-599`) reaches the static handler as
+//! unparseable, so it serves the whole file with a 200. Here that becomes a
+//! 206 whose parts are streamed from the file — sorted and with overlaps
+//! merged, as RFC 9110 allows — unless `If-Range` no longer matches, the body
+//! is a precompressed sidecar, the file came from an archive root, or the
+//! request asks for more than [`MAX_RANGES`] ranges, in which case the 200
+//! stands.
+
+use super::roots::{Layers, relative_path};
+use blocking::Unblock;
+use clap::Parser;
+use futures_lite::io::{AsyncRead, AsyncReadExt, Cursor};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use std::{
+    fs::{self, File},
+    io::{Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use trillium::{
+    Body, Conn, Handler, Info,
+    KnownHeaderName::{
+        AcceptRanges, ContentDisposition, ContentEncoding, ContentRange, ContentType, Etag,
+        IfRange, LastModified, Range,
+    },
+    Method, Status,
+};
+
+/// More ranges than this in one request are answered with the whole file.
+const MAX_RANGES: usize = 64;
+
+/// Characters escaped in an RFC 8187 `filename*` value.
+const FILENAME: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The `--mime`, `--mime-file` and `--download` flags, flattened into
+/// `serve`'s args.
+#[derive(Parser, Debug, Clone)]
+pub struct ContentOptions {
+    /// Serve files ending in .EXT as TYPE (repeatable)
+    ///
+    /// For extensions the built-in table gets wrong or doesn't know, e.g.
+    /// `--mime ts=text/javascript` or `--mime parquet=application/vnd.apache.parquet`.
+    /// EXT may have several parts (`tar.zst`); the longest match wins. Text
+    /// types get `; charset=utf-8` unless TYPE has parameters of its own.
+    #[arg(
+        long,
+        env,
+        value_name = "EXT=TYPE",
+        value_parser = parse_mime,
+        help_heading = "Content types"
+    )]
+    mime: Vec<(String, String)>,
+
+    /// Read extension mappings from a mime.types file (repeatable)
+    ///
+    /// One `type/subtype ext ext …` per line, `#` comments, as in Apache's and
+    /// nginx's. --mime wins over these, and later files over earlier ones.
+    #[arg(
+        long,
+        env,
+        value_name = "FILE",
+        value_parser = parse_mime_file,
+        help_heading = "Content types"
+    )]
+    mime_file: Vec<MimeTypes>,
+
+    /// Send files matching GLOB as downloads, with Content-Disposition:
+    /// attachment (repeatable; default: every file)
+    ///
+    /// Gitignore-style globs against the path below the root: `*.zip`,
+    /// `/exports/`, `data/**/*.parquet`.
+    #[arg(
+        long,
+        env,
+        value_name = "GLOB",
+        num_args = 0..=1,
+        default_missing_value = "*",
+        value_parser = parse_glob,
+        help_heading = "Content types"
+    )]
+    download: Vec<String>,
+}
+
+/// The mappings read from one `--mime-file`.
+#[derive(Debug, Clone)]
+pub struct MimeTypes(Vec<(String, String)>);
+
+fn parse_mime(mapping: &str) -> Result<(String, String), String> {
+    let (ext, mime) = mapping
+        .split_once('=')
+        .ok_or_else(|| format!("expected EXT=TYPE, got `{mapping}`"))?;
+    let ext = ext.trim().trim_start_matches('.').to_ascii_lowercase();
+    let mime = mime.trim();
+    if ext.is_empty() {
+        return Err(format!("no extension in `{mapping}`"));
+    }
+    if !mime.contains('/') {
+        return Err(format!("`{mime}` is not a type/subtype"));
+    }
+    Ok((ext, mime.to_string()))
+}
+
+fn parse_mime_file(path: &str) -> Result<MimeTypes, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let mut mappings = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        // nginx's `types { … }` block ends each entry with a `;`.
+        let mut words = line
+            .split_whitespace()
+            .map(|word| word.trim_end_matches(';'))
+            .filter(|word| !word.is_empty() && !matches!(*word, "types" | "{" | "}"));
+        let Some(mime) = words.next() else {
+            continue;
+        };
+        if !mime.contains('/') {
+            return Err(format!(
+                "{path}:{}: `{mime}` is not a type/subtype",
+                number + 1
+            ));
+        }
+        mappings.extend(words.map(|ext| (ext.to_ascii_lowercase(), mime.to_string())));
+    }
+    Ok(MimeTypes(mappings))
+}
+
+fn parse_glob(glob: &str) -> Result<String, String> {
+    GitignoreBuilder::new("")
+        .add_line(None, glob)
+        .map_err(|error| error.to_string())?;
+    Ok(glob.to_string())
+}
+
+impl ContentOptions {
+    /// Wrap the roots' file handlers, whose directories serve `index`.
+    pub fn wrap(&self, files: Layers, index: Option<String>) -> Files {
+        // Later entries win, so --mime goes last.
+        let mimes = self
+            .mime_file
+            .iter()
+            .flat_map(|file| file.0.iter().cloned())
+            .chain(self.mime.iter().cloned())
+            .collect();
+
+        let downloads = (!self.download.is_empty()).then(|| {
+            let mut builder = GitignoreBuilder::new("");
+            for glob in &self.download {
+                // Checked by `parse_glob`.
+                let _ = builder.add_line(None, glob);
+            }
+            builder.build().unwrap_or_else(|_| Gitignore::empty())
+        });
+
+        Files {
+            files,
+            index,
+            mimes,
+            downloads,
+        }
+    }
+}
+
+/// The file handlers, with content-type overrides, downloads and multi-range
+/// responses.
+#[derive(Debug)]
+pub struct Files {
+    files: Layers,
+    index: Option<String>,
+    mimes: Vec<(String, String)>,
+    downloads: Option<Gitignore>,
+}
+
+impl Files {
+    /// The file the static handler for `root` served for `relative`: the file
+    /// itself, a directory's index, or the root when it is a single file.
+    fn resolve(&self, root: &Path, relative: &str) -> Option<PathBuf> {
+        if root.is_file() {
+            return Some(root.to_path_buf());
+        }
+        let path = root.join(relative).canonicalize().ok()?;
+        if !path.starts_with(root) {
+            return None;
+        }
+        if path.is_dir() {
+            let index = path.join(self.index.as_deref()?);
+            return index.is_file().then_some(index);
+        }
+        path.is_file().then_some(path)
+    }
+
+    /// The override for a file named `name`: the longest `--mime` extension
+    /// it ends with.
+    fn mime_for(&self, name: &str) -> Option<String> {
+        let name = name.to_ascii_lowercase();
+        let (_, mime) = self
+            .mimes
+            .iter()
+            .rev()
+            .filter(|(ext, _)| {
+                name.len() > ext.len()
+                    && name.ends_with(ext.as_str())
+                    && name.as_bytes()[name.len() - ext.len() - 1] == b'.'
+            })
+            .max_by_key(|(ext, _)| ext.len())?;
+        let is_text = mime.starts_with("text/")
+            || mime.ends_with("javascript")
+            || mime.ends_with("json")
+            || mime.ends_with("+xml");
+        Some(if is_text && !mime.contains(';') {
+            format!("{mime}; charset=utf-8")
+        } else {
+            mime.clone()
+        })
+    }
+}
+
+impl Handler for Files {
+    async fn init(&mut self, info: &mut Info) {
+        self.files.init(info).await;
+    }
+
+    async fn run(&self, conn: Conn) -> Conn {
+        self.files.run(conn).await
+    }
+
+    async fn before_send(&self, mut conn: Conn) -> Conn {
+        // Only what the static handler served carries `Accept-Ranges`; the
+        // rendered pages, listings, forwards and errors are left alone.
+        let served = conn
+            .response_headers()
+            .eq_ignore_ascii_case(AcceptRanges, "bytes");
+        if !served || !matches!(conn.status(), Some(Status::Ok | Status::PartialContent)) {
+            return conn;
+        }
+        let Some(relative) = relative_path(conn.path()) else {
+            return conn;
+        };
+        let name = if relative.is_empty() || conn.path().ends_with('/') {
+            let dir = relative.rsplit('/').next().unwrap_or_default();
+            self.index.clone().unwrap_or_else(|| dir.to_string())
+        } else {
+            relative.rsplit('/').next().unwrap_or_default().to_string()
+        };
+
+        if let Some(mime) = self.mime_for(&name) {
+            conn.response_headers_mut().insert(ContentType, mime);
+        }
+
+        if let Some(downloads) = &self.downloads
+            && !name.is_empty()
+            && downloads
+                .matched_path_or_any_parents(&relative, false)
+                .is_ignore()
+        {
+            conn.response_headers_mut()
+                .insert(ContentDisposition, attachment(&name));
+        }
+
+        // The static handler sends `Etag` and `Last-Modified` but never checks
+        // them; a request that still has the file gets a 304 instead.
+        if matches!(conn.method(), Method::Get | Method::Head) {
+            conn = trillium_caching_headers::caching_headers()
+                .before_send(conn)
+                .await;
+        }
+
+        if conn.status() == Some(Status::Ok) && conn.method() == Method::Get {
+            conn = self.multi_range(conn, &relative).await;
+        }
+        conn
+    }
+}
+
+/// `Content-Disposition: attachment` for `name`, with an ASCII fallback and
+/// the exact name in RFC 8187 form.
+fn attachment(name: &str) -> String {
+    let fallback = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if fallback == name {
+        format!("attachment; filename=\"{name}\"")
+    } else {
+        format!(
+            "attachment; filename=\"{fallback}\"; filename*=UTF-8''{}",
+            utf8_percent_encode(name, FILENAME)
+        )
+    }
+}
+
+/// One requested range, before it's resolved against the file's length.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Spec {
+    FromTo(u64, u64),
+    From(u64),
+    Suffix(u64),
+}
+
+/// The ranges in a `Range: bytes=…` header with more than one; `None` for a
+/// single range (the static handler's) or anything malformed.
+fn parse_ranges(header: &str) -> Option<Vec<Spec>> {
+    parse_specs(header).filter(|specs| specs.len() > 1)
+}
+
+/// The ranges in a `Range: bytes=…` header, however many; `None` if it's
+/// malformed.
+pub(super) fn parse_specs(header: &str) -> Option<Vec<Spec>> {
+    header
+        .trim()
+        .strip_prefix("bytes=")?
+        .split(',')
+        .map(|spec| match spec.trim().split_once('-')? {
+            ("", suffix) => suffix.parse().ok().map(Spec::Suffix),
+            (start, "") => start.parse().ok().map(Spec::From),
+            (start, end) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(Spec::FromTo(start, end))
+            }
+        })
+        .collect()
+}
+
+/// The inclusive byte ranges `specs` select in a file of `total` bytes,
+/// sorted and with overlapping or adjacent ranges merged.
+pub(super) fn resolve_ranges(specs: &[Spec], total: u64) -> Vec<(u64, u64)> {
+    let mut ranges = specs
+        .iter()
+        .filter_map(|spec| match *spec {
+            Spec::FromTo(start, end) => (start < total).then(|| (start, end.min(total - 1))),
+            Spec::From(start) => (start < total).then(|| (start, total - 1)),
+            Spec::Suffix(0) => None,
+            Spec::Suffix(len) => (total > 0).then(|| (total.saturating_sub(len), total - 1)),
+        })
+        .collect::<Vec<_>>();
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+impl Files {
+    /// Turn a full-file 200 into a 206 for a multi-range request, when it
+    /// can be.
+    async fn multi_range(&self, mut conn: Conn, relative: &str) -> Conn {
+        let Some(specs) = conn.request_headers().get_str(Range).and_then(parse_ranges) else {
+            return conn;
+        };
+        if specs.len() > MAX_RANGES || conn.response_headers().has_header(ContentEncoding) {
+            return conn;
+        }
+        if !if_range_holds(&conn) {
+            return conn;
+        }
+        let Some(total) = conn.response_body().and_then(Body::len) else {
+            return conn;
+        };
+        let Some(path) = self
+            .files
+            .served_root(&conn)
+            .and_then(|root| self.resolve(root, relative))
+        else {
+            return conn;
+        };
+
+        let ranges = resolve_ranges(&specs, total);
+        if ranges.is_empty() {
+            conn.response_headers_mut()
+                .insert(ContentRange, format!("bytes */{total}"));
+            return conn
+                .with_status(Status::RequestedRangeNotSatisfiable)
+                .with_body("");
+        }
+
+        let content_type = conn
+            .response_headers()
+            .get_str(ContentType)
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let body = match byteranges(&path, total, &ranges, &content_type) {
+            Ok(body) => body,
+            Err(error) => {
+                log::warn!(
+                    "could not read {} for a range request: {error}",
+                    path.display()
+                );
+                return conn;
+            }
+        };
+        conn.response_headers_mut().insert(ContentType, body.0);
+        conn.with_status(Status::PartialContent).with_body(body.1)
+    }
+}
+
+/// Whether a request's ranges still apply to the response: a stale `If-Range`
+/// means the client's pieces are from another version of the file, and it
+/// gets the whole new one. Only strong validators count, so a weak `W/"…"`
+/// ETag never matches.
+pub(super) fn if_range_holds(conn: &Conn) -> bool {
+    let Some(if_range) = conn.request_headers().get_str(IfRange) else {
+        return true;
+    };
+    let if_range = if_range.trim();
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    let validator = if if_range.starts_with('"') {
+        conn.response_headers().get_str(Etag)
+    } else {
+        conn.response_headers().get_str(LastModified)
+    };
+    validator == Some(if_range)
+}
+
+/// A `multipart/byteranges` body with a part for each of `ranges` of the file
+/// at `path`, streamed from the file; and the content type naming its
+/// boundary.
+fn byteranges(
+    path: &Path,
+    total: u64,
+    ranges: &[(u64, u64)],
+    content_type: &str,
+) -> std::io::Result<(String, Body)> {
+    // The file changed since the static handler measured it; serve that
+    // response instead of parts of a different file.
+    if fs::metadata(path)?.len() != total {
+        return Err(std::io::Error::other("the file changed while being served"));
+    }
+
+    let mut random = [0u8; 12];
+    getrandom::fill(&mut random).map_err(std::io::Error::other)?;
+    let boundary = random
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    let mut len = 0;
+    let mut reader: Box<dyn AsyncRead + Send + Unpin> = Box::new(futures_lite::io::empty());
+    for (i, &(start, end)) in ranges.iter().enumerate() {
+        let head = format!(
+            "{}--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes \
+             {start}-{end}/{total}\r\n\r\n",
+            if i == 0 { "" } else { "\r\n" }
+        );
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(start))?;
+        let part = end - start + 1;
+        len += head.len() as u64 + part;
+        reader = Box::new(
+            reader
+                .chain(Cursor::new(head.into_bytes()))
+                .chain(Unblock::new(file).take(part)),
+        );
+    }
+    let tail = format!("\r\n--{boundary}--\r\n");
+    len += tail.len() as u64;
+    let reader = reader.chain(Cursor::new(tail.into_bytes()));
+
+    Ok((
+        format!("multipart/byteranges; boundary={boundary}"),
+        Body::new_streaming(reader, Some(len)),
+    ))
+}
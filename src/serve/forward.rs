@@ -0,0 +1,198 @@
+//! `--forward`: reverse-proxying requests to other servers.
+//!
+//! A bare `--forward URL` is the fallback it has always been: every request is
+//! tried upstream first, and an upstream 404 falls through to the local files.
+//! A prefixed `--forward /api=URL` owns its prefix instead — matching requests
+//! are forwarded ahead of the file handler and writes, and whatever the
+//! upstream answers (404s included) is the response. Both forward websocket
+//! upgrades, so a dev server's hot-module-reload socket works through `serve`.
+//!
+//! `http+unix://` targets dial a Unix domain socket. The socket path is the
+//! percent-encoded host (`http+unix://%2Ftmp%2Fapp.sock/v1`, as other tools
+//! write it), or, with an empty host, the whole path (`http+unix:///tmp/app.sock`).
+
+use crate::{
+    access_log::{Recorded, Upstream},
+    tls::{Tls, parse_url},
+};
+use clap::Parser;
+#[cfg(unix)]
+use percent_encoding::percent_decode_str;
+#[cfg(unix)]
+use std::path::PathBuf;
+use trillium::{Conn, Handler};
+use trillium_proxy::{Client, Proxy, Url, upstream::UpstreamSelector};
+
+/// The `--forward` flags, flattened into `serve`'s args.
+#[derive(Parser, Debug, Clone)]
+pub struct Forwards {
+    /// Forward (reverse proxy) requests to another server (repeatable)
+    ///
+    /// A bare URL receives every request first, and anything it answers with
+    /// a 404 Not Found falls through to the local files. With a PREFIX, the
+    /// URL owns that path: matching requests are forwarded before file lookup,
+    /// and the upstream's response is always the answer. Websocket upgrades
+    /// are forwarded too.
+    ///
+    /// Examples:
+    ///    `--forward localhost:8081`
+    ///    `--forward /api=http://localhost:9000`
+    ///    `--forward /auth=https://auth.example.com/v2`
+    ///    `--forward /rpc=http+unix://%2Frun%2Fapp.sock`
+    ///
+    /// An http+unix:// URL dials a Unix domain socket, named by the
+    /// percent-encoded host or, when the host is empty, by the whole path.
+    #[arg(
+        short = 'f',
+        long = "forward",
+        env = "FORWARD",
+        value_name = "[PREFIX=]URL",
+        value_parser = parse_target,
+        help_heading = "Forwarding"
+    )]
+    targets: Vec<Target>,
+
+    /// Drop the matched PREFIX before forwarding, so `--forward
+    /// /api=http://localhost:9000` sends /api/users to /users
+    #[arg(long, env, help_heading = "Forwarding")]
+    forward_strip_prefix: bool,
+}
+
+impl Forwards {
+    /// The prefixed forwards, in the order given; run ahead of the file
+    /// handler. Empty when there are none.
+    pub fn prefixed(&self) -> Vec<Proxy<Recorded<Target>>> {
+        self.proxies(true)
+    }
+
+    /// The bare fallback forwards, tried after the built-in routes but before
+    /// the file handler, passing an upstream 404 through to it.
+    pub fn fallback(&self) -> impl Handler {
+        (self.proxies(false), |mut conn: Conn| async move {
+            // Only reached when no fallback answered: a 404 passed through
+            // is the local files' to answer, and not a forwarded response.
+            conn.take_state::<Upstream>();
+            conn
+        })
+    }
+
+    fn proxies(&self, prefixed: bool) -> Vec<Proxy<Recorded<Target>>> {
+        self.targets
+            .iter()
+            .filter(|target| target.prefix.is_some() == prefixed)
+            .map(|target| {
+                let target = Target {
+                    strip: self.forward_strip_prefix,
+                    ..target.clone()
+                };
+                let proxy = Proxy::new(target.client(), Recorded(target)).with_websocket_upgrades();
+                if prefixed {
+                    proxy.proxy_not_found()
+                } else {
+                    proxy
+                }
+            })
+            .collect()
+    }
+}
+
+/// One `--forward`: where to send requests, and which ones.
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// The path prefix this target owns, without a trailing slash (`""` for
+    /// `/=URL`); `None` for a bare fallback.
+    prefix: Option<String>,
+    url: Url,
+    #[cfg(unix)]
+    socket: Option<PathBuf>,
+    strip: bool,
+}
+
+impl Target {
+    fn client(&self) -> Client {
+        #[cfg(unix)]
+        if let Some(socket) = &self.socket {
+            return Client::new(trillium_smol::UnixClientConfig::new(socket));
+        }
+        Client::from(Tls::default())
+    }
+}
+
+impl UpstreamSelector for Target {
+    fn determine_upstream(&self, conn: &mut Conn) -> Option<Url> {
+        let path = conn.path();
+        let path = match &self.prefix {
+            None => path,
+            Some(prefix) => {
+                let rest = path.strip_prefix(prefix.as_str())?;
+                // `/api` owns `/api` and `/api/…`, not `/apiary`.
+                if !rest.is_empty() && !rest.starts_with('/') {
+                    return None;
+                }
+                if self.strip { rest } else { path }
+            }
+        };
+
+        let mut url = self.url.clone();
+        let base = url.path().trim_end_matches('/');
+        let path = match path {
+            "" => format!("{base}/"),
+            path => format!("{base}{path}"),
+        };
+        url.set_path(&path);
+        url.set_query(Some(conn.querystring()).filter(|query| !query.is_empty()));
+        Some(url)
+    }
+}
+
+fn parse_target(src: &str) -> Result<Target, String> {
+    let (prefix, url) = match src.split_once('=') {
+        Some((prefix, url)) if prefix.starts_with('/') => {
+            (Some(prefix.trim_end_matches('/').to_string()), url)
+        }
+        _ => (None, src),
+    };
+
+    if let Some(rest) = url.strip_prefix("http+unix://") {
+        return parse_unix(prefix, rest);
+    }
+
+    Ok(Target {
+        prefix,
+        url: parse_url(url)?,
+        #[cfg(unix)]
+        socket: None,
+        strip: false,
+    })
+}
+
+#[cfg(unix)]
+fn parse_unix(prefix: Option<String>, rest: &str) -> Result<Target, String> {
+    let (host, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    let (socket, path) = if host.is_empty() {
+        (path, "/")
+    } else {
+        (host, path)
+    };
+    let socket = percent_decode_str(socket)
+        .decode_utf8()
+        .map_err(|e| e.to_string())?;
+    if socket.is_empty() || socket == "/" {
+        return Err("expected a socket path, e.g. http+unix:///run/app.sock".into());
+    }
+    // The socket is the address; the url only supplies the path and `Host`.
+    let url = format!("http://localhost{path}")
+        .parse::<Url>()
+        .map_err(|e| e.to_string())?;
+    Ok(Target {
+        prefix,
+        url,
+        socket: Some(PathBuf::from(&*socket)),
+        strip: false,
+    })
+}
+
+#[cfg(not(unix))]
+fn parse_unix(_prefix: Option<String>, _rest: &str) -> Result<Target, String> {
+    Err("http+unix:// forwards need Unix domain sockets, which this platform lacks".into())
+}
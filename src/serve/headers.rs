@@ -0,0 +1,235 @@
+//! `--header`, `--cors`, `--cross-origin-isolated` and `--cache-control`: response
+//! headers added to everything `serve` sends — files, listings, rendered pages
+//! and errors alike.
+//!
+//! The headers are set in `before_send`, and the handler sits near the front of
+//! the tuple, so they are applied after every other handler has had its say
+//! (earlier in the tuple runs later on the way out) and win over anything the
+//! file handler set. With `--cors`, a CORS preflight — an `OPTIONS` carrying
+//! `Access-Control-Request-Method` — is answered here with a 204, ahead of the
+//! auth gate, since browsers never send credentials on a preflight.
+
+use clap::Parser;
+use trillium::{
+    Conn, Handler, HeaderName,
+    KnownHeaderName::{
+        AccessControlAllowHeaders, AccessControlAllowMethods, AccessControlAllowOrigin,
+        AccessControlMaxAge, AccessControlRequestHeaders, AccessControlRequestMethod, CacheControl,
+        Origin, Vary,
+    },
+    Method, Status,
+};
+
+/// The methods a preflight is told are allowed: everything `serve` answers.
+const ALLOWED_METHODS: &str = "GET, HEAD, PUT, DELETE, OPTIONS, PROPFIND, MKCOL, MOVE";
+
+/// The `--header`, `--cors`, `--cross-origin-isolated` and `--cache-control`
+/// flags, flattened into `serve`'s args.
+#[derive(Parser, Debug, Clone)]
+pub struct ResponseHeaders {
+    /// add NAME: VALUE to every response, replacing any the server set
+    /// (repeatable)
+    ///
+    /// Written NAME=VALUE or "NAME: VALUE", e.g. --header
+    /// X-Robots-Tag=noindex.
+    #[arg(
+        long = "header",
+        env = "HEADER",
+        value_name = "NAME=VALUE",
+        value_parser = parse_header,
+        help_heading = "Headers"
+    )]
+    headers: Vec<(HeaderName<'static>, String)>,
+
+    /// allow cross-origin requests from ORIGIN (default: any), answering CORS
+    /// preflights
+    ///
+    /// ORIGIN may be a comma-separated list; a request from one of them gets
+    /// its own origin back, with Vary: Origin. Without ORIGIN, every origin is
+    /// allowed with `*`.
+    #[arg(
+        long,
+        env,
+        value_name = "ORIGIN",
+        num_args = 0..=1,
+        default_missing_value = "*",
+        help_heading = "Headers"
+    )]
+    cors: Option<String>,
+
+    /// send Cross-Origin-Opener-Policy and Cross-Origin-Embedder-Policy, as
+    /// SharedArrayBuffer and WebAssembly threads require
+    #[arg(long, env, help_heading = "Headers")]
+    cross_origin_isolated: bool,
+
+    /// send this Cache-Control with every response
+    ///
+    /// Either a raw value (`public, max-age=600`) or a preset: `no-store`
+    /// (never cache), `revalidate` (cache, but check first — `no-cache`), or
+    /// `immutable` (a year, never rechecked — for fingerprinted assets). A
+    /// check for an unchanged file gets an empty 304.
+    #[arg(
+        long,
+        env,
+        value_name = "POLICY",
+        value_parser = parse_cache_control,
+        conflicts_with = "no_cache",
+        help_heading = "Headers"
+    )]
+    cache_control: Option<String>,
+
+    /// shorthand for --cache-control no-store: browsers keep nothing, so every
+    /// reload fetches fresh files
+    #[arg(long, env, help_heading = "Headers")]
+    no_cache: bool,
+}
+
+fn parse_header(header: &str) -> Result<(HeaderName<'static>, String), String> {
+    // Whichever separator comes first, since either may appear in the value.
+    let (name, value) = header
+        .split_once([':', '='])
+        .ok_or_else(|| format!("expected NAME=VALUE, got `{header}`"))?;
+    let name = name
+        .trim()
+        .parse::<HeaderName<'static>>()
+        .map_err(|_| format!("`{}` is not a valid header name", name.trim()))?;
+    Ok((name, value.trim().to_string()))
+}
+
+fn parse_cache_control(policy: &str) -> Result<String, String> {
+    Ok(match policy {
+        "no-store" | "none" => "no-store",
+        "revalidate" => "no-cache",
+        "immutable" => "public, max-age=31536000, immutable",
+        "" => return Err("expected a Cache-Control value or preset".into()),
+        other => other,
+    }
+    .to_string())
+}
+
+impl ResponseHeaders {
+    /// The header handler, or `None` when no flag asks for any headers.
+    pub fn handler(&self) -> Option<SetHeaders> {
+        let mut headers = Vec::new();
+        if self.cross_origin_isolated {
+            headers.push((
+                "Cross-Origin-Opener-Policy".parse().ok()?,
+                "same-origin".into(),
+            ));
+            headers.push((
+                "Cross-Origin-Embedder-Policy".parse().ok()?,
+                "require-corp".into(),
+            ));
+        }
+        let cache_control = if self.no_cache {
+            Some("no-store".to_string())
+        } else {
+            self.cache_control.clone()
+        };
+        if let Some(cache_control) = cache_control {
+            headers.push((CacheControl.into(), cache_control));
+        }
+        // Last, so an explicit `--header` overrides the presets above.
+        headers.extend(self.headers.iter().cloned());
+
+        let cors = self.cors.as_deref().map(|origins| {
+            if origins.split(',').any(|origin| origin.trim() == "*") {
+                Cors::Any
+            } else {
+                Cors::Origins(
+                    origins
+                        .split(',')
+                        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                        .filter(|origin| !origin.is_empty())
+                        .collect(),
+                )
+            }
+        });
+
+        (!headers.is_empty() || cors.is_some()).then_some(SetHeaders { headers, cors })
+    }
+}
+
+/// Which origins `--cors` allows.
+#[derive(Debug, Clone)]
+enum Cors {
+    Any,
+    Origins(Vec<String>),
+}
+
+impl Cors {
+    /// The `Access-Control-Allow-Origin` for a request from `origin`, if it's
+    /// allowed.
+    fn allow_origin(&self, origin: Option<&str>) -> Option<String> {
+        match self {
+            Cors::Any => Some("*".into()),
+            Cors::Origins(origins) => {
+                let origin = origin?;
+                origins
+                    .iter()
+                    .any(|allowed| allowed == origin)
+                    .then(|| origin.to_string())
+            }
+        }
+    }
+}
+
+/// Sets the configured headers on every response and answers CORS preflights.
+#[derive(Debug, Clone)]
+pub struct SetHeaders {
+    headers: Vec<(HeaderName<'static>, String)>,
+    cors: Option<Cors>,
+}
+
+impl Handler for SetHeaders {
+    async fn run(&self, conn: Conn) -> Conn {
+        let Some(cors) = &self.cors else {
+            return conn;
+        };
+        let is_preflight = conn.method() == Method::Options
+            && conn
+                .request_headers()
+                .has_header(AccessControlRequestMethod);
+        if !is_preflight {
+            return conn;
+        }
+        let origin = conn.request_headers().get_str(Origin).map(str::to_string);
+        // A disallowed origin gets a bare 204, which the browser reads as a
+        // refusal.
+        let conn = if cors.allow_origin(origin.as_deref()).is_some() {
+            // Allow whatever headers the page asked to send.
+            let requested = conn
+                .request_headers()
+                .get_str(AccessControlRequestHeaders)
+                .map(str::to_string);
+            let conn = conn
+                .with_response_header(AccessControlAllowMethods, ALLOWED_METHODS)
+                .with_response_header(AccessControlMaxAge, "86400");
+            match requested {
+                Some(requested) => conn.with_response_header(AccessControlAllowHeaders, requested),
+                None => conn,
+            }
+        } else {
+            conn
+        };
+        conn.with_status(Status::NoContent).halt()
+    }
+
+    async fn before_send(&self, mut conn: Conn) -> Conn {
+        if let Some(cors) = &self.cors {
+            let origin = conn.request_headers().get_str(Origin).map(str::to_string);
+            if let Some(allowed) = cors.allow_origin(origin.as_deref()) {
+                conn.response_headers_mut()
+                    .insert(AccessControlAllowOrigin, allowed);
+            }
+            if matches!(cors, Cors::Origins(_)) {
+                conn.response_headers_mut().append(Vary, "Origin");
+            }
+        }
+        for (name, value) in &self.headers {
+            conn.response_headers_mut()
+                .insert(name.clone(), value.clone());
+        }
+        conn
+    }
+}
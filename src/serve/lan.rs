@@ -0,0 +1,285 @@
+//! `--lan`: sharing `serve` with phones, tablets and other machines on the
+//! local network.
+//!
+//! The server listens on every IPv4 interface, and startup prints a URL for
+//! each non-loopback address — the primary one, which the default route leaves
+//! from, first and with a terminal QR code a phone camera can open. `--mdns`
+//! also advertises the server over DNS-SD, so it shows up in Bonjour/Avahi
+//! browsers. Under tls, addresses the certificate doesn't name are called out,
+//! since every browser on another device would reject them.
+
+use clap::Parser;
+use colored::Colorize;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use qrcode::{QrCode, render::unicode::Dense1x2};
+use std::{
+    net::{IpAddr, Ipv4Addr, UdpSocket},
+    time::Duration,
+};
+use x509_parser::{extensions::GeneralName, pem::Pem};
+
+/// The `--lan` flags, flattened into `serve`'s args.
+#[derive(Parser, Debug, Clone)]
+pub struct Lan {
+    /// Share on the local network: listen on every interface and print a URL
+    /// for each, with a QR code for the primary one
+    ///
+    /// Listens on 0.0.0.0, so it can't be combined with --host.
+    #[arg(long, env, conflicts_with = "host", help_heading = "LAN sharing")]
+    lan: bool,
+
+    /// Advertise the server over mDNS/DNS-SD as NAME (default: "trillium
+    /// serve on HOSTNAME")
+    ///
+    /// Registered as `_http._tcp`, or `_https._tcp` when serving tls.
+    #[arg(
+        long,
+        env,
+        value_name = "NAME",
+        num_args = 0..=1,
+        requires = "lan",
+        help_heading = "LAN sharing"
+    )]
+    mdns: Option<Option<String>>,
+
+    /// Don't print the QR code
+    #[arg(long, env, requires = "lan", help_heading = "LAN sharing")]
+    no_qr: bool,
+}
+
+/// A network interface's name and IPv4 address.
+#[derive(Debug, Clone)]
+pub struct Address {
+    interface: String,
+    ip: Ipv4Addr,
+}
+
+impl Address {
+    pub fn ip(&self) -> Ipv4Addr {
+        self.ip
+    }
+}
+
+impl Lan {
+    /// The host to listen on in place of `--host`, when `--lan` is set.
+    pub fn host(&self) -> Option<&'static str> {
+        self.lan.then_some("0.0.0.0")
+    }
+
+    /// The addresses other devices can reach this one at, primary first, or
+    /// `None` without `--lan`.
+    pub fn addresses(&self) -> Option<Vec<Address>> {
+        if !self.lan {
+            return None;
+        }
+        let mut addresses = if_addrs::get_if_addrs()
+            .unwrap_or_else(|e| {
+                log::error!("could not list network interfaces: {e}");
+                Vec::new()
+            })
+            .into_iter()
+            .filter_map(|interface| match interface.ip() {
+                IpAddr::V4(ip) if !ip.is_loopback() && !ip.is_link_local() => Some(Address {
+                    interface: interface.name,
+                    ip,
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if let Some(primary) = primary_ip() {
+            // Stable, so the rest keep the order the OS listed them in.
+            addresses.sort_by_key(|address| address.ip != primary);
+        }
+        Some(addresses)
+    }
+
+    /// Print a URL for each address and a QR code for the first, warn about
+    /// any the certificate doesn't cover, and start advertising if `--mdns` was
+    /// given. Hold the returned [`Advertisement`] for as long as the server
+    /// runs.
+    pub fn announce(
+        &self,
+        addresses: &[Address],
+        port: u16,
+        tls_cert: Option<Vec<u8>>,
+    ) -> Option<Advertisement> {
+        let scheme = if tls_cert.is_some() { "https" } else { "http" };
+        let url = |ip: Ipv4Addr| format!("{scheme}://{ip}:{port}/");
+
+        if addresses.is_empty() {
+            println!(
+                "  {} no network interface besides loopback is up; only this machine can connect",
+                "warning:".yellow()
+            );
+            return None;
+        }
+
+        println!("sharing on the local network:");
+        let width = addresses
+            .iter()
+            .map(|address| address.interface.len())
+            .max()
+            .unwrap_or_default();
+        for address in addresses {
+            println!(
+                "  {:width$}  {}",
+                address.interface.dimmed(),
+                url(address.ip).bold()
+            );
+        }
+        if !self.no_qr {
+            print_qr(&url(addresses[0].ip));
+        }
+
+        if let Some(cert) = tls_cert {
+            warn_uncovered(&cert, addresses);
+        }
+
+        let name = self.mdns.as_ref()?;
+        match advertise(name.as_deref(), addresses, port, scheme) {
+            Ok(advertisement) => {
+                println!("advertising as \"{}\" over mDNS", advertisement.name);
+                Some(advertisement)
+            }
+            Err(e) => {
+                println!(
+                    "  {} could not advertise over mDNS: {e}",
+                    "warning:".yellow()
+                );
+                None
+            }
+        }
+    }
+}
+
+/// The address the default route leaves from. Connecting a UDP socket sends
+/// nothing; it only asks the OS which local address it would use.
+fn primary_ip() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80)).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+        _ => None,
+    }
+}
+
+fn print_qr(url: &str) {
+    let Ok(code) = QrCode::new(url) else {
+        return;
+    };
+    // Light modules drawn dark and vice versa: most terminals are dark, and
+    // phone cameras read the inverted code just as well.
+    let qr = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build();
+    println!("{qr}");
+}
+
+/// Warn about each address the certificate's subject alternative names don't
+/// list.
+fn warn_uncovered(cert: &[u8], addresses: &[Address]) {
+    let covered = match cert_ips(cert) {
+        Ok(ips) => ips,
+        Err(e) => {
+            println!(
+                "  {} could not read the tls certificate's names: {e}",
+                "warning:".yellow()
+            );
+            return;
+        }
+    };
+    for address in addresses {
+        if !covered.contains(&IpAddr::V4(address.ip)) {
+            println!(
+                "  {} the tls certificate doesn't name {} ({}); browsers on other devices will \
+                 refuse it",
+                "warning:".yellow(),
+                address.ip,
+                address.interface
+            );
+        }
+    }
+}
+
+/// The IP addresses among a certificate's subject alternative names. Takes
+/// the first certificate of a PEM file or chain, or DER.
+fn cert_ips(cert: &[u8]) -> Result<Vec<IpAddr>, String> {
+    let der = match Pem::iter_from_buffer(cert).next() {
+        Some(pem) => pem.map_err(|e| e.to_string())?.contents,
+        None => cert.to_vec(),
+    };
+    let (_, cert) = x509_parser::parse_x509_certificate(&der).map_err(|e| e.to_string())?;
+    let Some(names) = cert.subject_alternative_name().map_err(|e| e.to_string())? else {
+        return Ok(Vec::new());
+    };
+    Ok(names
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match *name {
+            GeneralName::IPAddress(&[a, b, c, d]) => Some(IpAddr::from([a, b, c, d])),
+            GeneralName::IPAddress(octets) => <[u8; 16]>::try_from(octets).ok().map(IpAddr::from),
+            _ => None,
+        })
+        .collect())
+}
+
+/// A service registered with a running mDNS responder. Dropping it — once the
+/// server has shut down — withdraws the service, so browsers on other devices
+/// forget it right away instead of when its record expires.
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    name: String,
+    fullname: String,
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        if let Ok(unregistered) = self.daemon.unregister(&self.fullname) {
+            let _ = unregistered.recv_timeout(Duration::from_secs(1));
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Register the server with a new mDNS responder.
+fn advertise(
+    name: Option<&str>,
+    addresses: &[Address],
+    port: u16,
+    scheme: &str,
+) -> Result<Advertisement, mdns_sd::Error> {
+    let hostname = hostname::get()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+        .and_then(|name| name.split('.').next().map(str::to_string))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "trillium".into());
+    let name = name
+        .filter(|name| !name.is_empty())
+        .map_or_else(|| format!("trillium serve on {hostname}"), str::to_string);
+    let ips = addresses
+        .iter()
+        .map(|address| IpAddr::V4(address.ip))
+        .collect::<Vec<_>>();
+
+    let daemon = ServiceDaemon::new()?;
+    let service = ServiceInfo::new(
+        &format!("_{scheme}._tcp.local."),
+        &name,
+        &format!("{hostname}.local."),
+        &ips[..],
+        port,
+        &[("path", "/")][..],
+    )?;
+    let fullname = service.get_fullname().to_string();
+    daemon.register(service)?;
+    Ok(Advertisement {
+        daemon,
+        name,
+        fullname,
+    })
+}
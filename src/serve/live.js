@@ -1,16 +1,112 @@
-// Live reload for `trillium serve --render`. Opens a websocket back to the
-// server; any message means "something changed on disk" — reload the page.
-// Reconnects if the server restarts, so a restart of `trillium serve` doesn't
-// leave the page stranded.
+// Live reload for `trillium serve --live-reload` (and `--render`, which implies
+// it). Opens a websocket back to the server, which sends `{"paths": [...]}`
+// naming the files in each burst of changes. Stylesheets are swapped in place
+// and images re-fetched, so scroll position and form state survive; the page
+// only reloads when it, or a script it loaded, changed. An empty list means
+// "reload". Reconnects if the server restarts, so a restart of `trillium serve`
+// doesn't leave the page stranded.
 (() => {
+  const SCROLL_KEY = "_serve_live_scroll";
+  const IMAGE = /\.(apng|avif|bmp|gif|ico|jpe?g|png|svg|webp)$/i;
+
+  // The decoded path of a same-origin URL, to compare with the server's paths;
+  // `null` for anything elsewhere.
+  function pathOf(url) {
+    try {
+      const parsed = new URL(url, location.href);
+      if (parsed.origin !== location.origin) return null;
+      return decodeURIComponent(parsed.pathname);
+    } catch {
+      return null;
+    }
+  }
+
+  // `url` with a cache-busting query param, so the browser fetches it afresh.
+  function bust(url) {
+    const parsed = new URL(url, location.href);
+    parsed.searchParams.set("_live", Date.now());
+    return parsed.href;
+  }
+
+  // Whether `path` changing means this page has to reload: it is the page, a
+  // script the page loaded, or — on a directory listing or a directory's
+  // index — something beneath the directory.
+  function affectsPage(path) {
+    const here = decodeURIComponent(location.pathname);
+    if (path === here) return true;
+    if (here.endsWith("/") && path.startsWith(here)) return true;
+    return [...document.scripts].some((script) => pathOf(script.src) === path);
+  }
+
+  // Replace a stylesheet with a fresh copy, dropping the old one only once the
+  // new one has loaded so the page never flashes unstyled.
+  function swapStylesheet(link) {
+    const fresh = link.cloneNode();
+    fresh.href = bust(link.href);
+    fresh.addEventListener("load", () => link.remove());
+    fresh.addEventListener("error", () => link.remove());
+    link.after(fresh);
+  }
+
+  function reload() {
+    sessionStorage.setItem(
+      SCROLL_KEY,
+      JSON.stringify({ href: location.href, x: scrollX, y: scrollY }),
+    );
+    location.reload();
+  }
+
+  function apply(paths) {
+    if (!paths.length) return reload();
+    // Checked first for every path, so viewing a stylesheet or an image (or
+    // listing its directory) still reloads.
+    if (paths.some(affectsPage)) return reload();
+    const styles = paths.filter((path) => path.toLowerCase().endsWith(".css"));
+    const images = new Set(paths.filter((path) => IMAGE.test(path)));
+
+    if (styles.length) {
+      const links = [...document.querySelectorAll('link[rel~="stylesheet"]')].filter(
+        (link) => pathOf(link.href) !== null,
+      );
+      const named = links.filter((link) => styles.includes(pathOf(link.href)));
+      // A changed stylesheet the page doesn't link directly may be
+      // `@import`ed by one it does, so refresh them all.
+      (named.length ? named : links).forEach(swapStylesheet);
+    }
+    for (const img of document.images) {
+      if (images.has(pathOf(img.src))) img.src = bust(img.src);
+    }
+  }
+
+  // Put the page back where it was before a reload this script triggered,
+  // once images and the like have laid out.
+  function restoreScroll() {
+    const saved = sessionStorage.getItem(SCROLL_KEY);
+    if (!saved) return;
+    sessionStorage.removeItem(SCROLL_KEY);
+    const { href, x, y } = JSON.parse(saved);
+    if (href !== location.href) return;
+    if (document.readyState === "complete") scrollTo(x, y);
+    else addEventListener("load", () => scrollTo(x, y), { once: true });
+  }
+
   function connect() {
     const scheme = location.protocol === "https:" ? "wss" : "ws";
     const sock = new WebSocket(`${scheme}://${location.host}/_serve_live.ws`);
-    sock.addEventListener("message", () => location.reload());
-    // The server went away (e.g. restarted); keep trying, and reload once it's
-    // back so we pick up whatever changed while it was down.
+    sock.addEventListener("message", (event) => {
+      let paths;
+      try {
+        paths = JSON.parse(event.data).paths;
+      } catch {
+        paths = [];
+      }
+      apply(Array.isArray(paths) ? paths : []);
+    });
+    // The server went away (e.g. restarted); keep trying.
     sock.addEventListener("close", () => setTimeout(connect, 1000));
     sock.addEventListener("error", () => sock.close());
   }
+
+  restoreScroll();
   connect();
 })();
@@ -1,22 +1,43 @@
-//! Live reload for `serve --render`.
+//! Live reload for `serve --live-reload` (and `--render`, which implies it).
 //!
-//! A recursive [`notify`] watcher on the served root broadcasts a tick whenever
-//! a file changes or appears; an injected client script (see `live.js`) holds a
-//! websocket open and reloads the page on each tick. Because the reload is
-//! driven from the browser, a directory listing re-fetches and reflects new or
-//! removed files for free — there's nothing file-specific to diff.
+//! A recursive [`notify`] watcher on the served root (and any `--watch`
+//! directories) broadcasts the URL paths of the files in each burst of
+//! changes; an injected client script (see `live.js`) holds a websocket open
+//! and decides what to do with them. A changed stylesheet is swapped in place
+//! and a changed image re-fetched, keeping scroll position and form state; the
+//! page reloads only when it, or a script it loaded, changed — or, for a
+//! directory listing, anything beneath it. Because the reload is driven from
+//! the browser, a listing re-fetches and reflects new or removed files for
+//! free — there's nothing file-specific to diff. A change in a `--watch`
+//! directory outside the root has no URL, so it reloads every page.
+//!
+//! Editor swap files, `.git`, anything `.gitignore`d and anything matching a
+//! `--watch-ignore` glob are dropped before they reach a browser, so a save
+//! that churns a `node_modules` tree or a `.swp` file reloads nothing. The
+//! search index and the markdown sidebar still hear about every change, since
+//! what a browser reloads for has no bearing on what they hold.
 //!
 //! The script is injected by an [`HtmlRewriter`] into any `text/html` response,
 //! so it lights up plain HTML files, rendered pages, and directory listings
 //! alike without touching the file handler.
 
-use async_broadcast::Sender;
+use crate::directory_listing::is_gitignored;
+use async_broadcast::{RecvError, Sender};
+use clap::Parser;
 use futures_lite::{StreamExt, future};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use notify::{
     EventKind, RecommendedWatcher, RecursiveMode, Watcher,
     event::{CreateKind, ModifyKind, RemoveKind},
 };
-use std::{path::PathBuf, pin::pin, sync::mpsc, thread, time::Duration};
+use serde_json::json;
+use std::{
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::{Arc, mpsc},
+    thread,
+    time::Duration,
+};
 use trillium::{Conn, Handler, KnownHeaderName::ContentType, State};
 use trillium_html_rewriter::{
     HtmlRewriter,
@@ -25,19 +46,176 @@ use trillium_html_rewriter::{
 use trillium_router::Router;
 use trillium_websockets::{WebSocket, WebSocketConn};
 
-/// Build the live-reload handler: an [`HtmlRewriter`] that injects the client
-/// script into HTML responses, plus routes serving that script and the reload
-/// websocket. Spawns the filesystem watcher as a side effect.
-///
-/// Place this ahead of the file handler so its routes intercept
-/// `/_serve_live.*`, and after compression so the rewriter runs on the
-/// uncompressed body (both are `before_send` handlers; earlier in the tuple runs
-/// later on the way out).
-pub fn handler(root: PathBuf) -> impl Handler {
-    // A tick channel: the watcher thread broadcasts `()`, each connected browser
-    // holds its own receiver. Overflow-drop and don't-wait-for-a-receiver so a
-    // burst of changes (or no browser at all) never blocks the watcher.
-    let (mut sender, keepalive_rx) = async_broadcast::broadcast::<()>(8);
+/// Always ignored: version control internals and editors' swap, backup and
+/// lock files (vim's `4913` is its write-permission probe).
+const DEFAULT_IGNORES: &[&str] = &[
+    ".git/", ".hg/", ".svn/", "*.swp", "*.swx", "*~", ".#*", "#*#", "4913",
+];
+
+/// The live-reload flags, flattened into `serve`'s args.
+#[derive(Parser, Debug, Clone)]
+pub struct LiveReload {
+    /// refresh open pages when files under the root change
+    ///
+    /// Injects a small script into HTML responses. Changed stylesheets and
+    /// images are swapped in place; the page reloads, keeping its scroll
+    /// position, when it or one of its scripts changes (or, for a listing,
+    /// anything beneath it). Implied by --render.
+    #[arg(long, env, help_heading = "Live reload")]
+    live_reload: bool,
+
+    /// also watch DIR, outside the root; any change there reloads every page
+    /// (repeatable)
+    #[arg(
+        long,
+        env,
+        value_name = "DIR",
+        value_delimiter = ',',
+        help_heading = "Live reload"
+    )]
+    watch: Vec<PathBuf>,
+
+    /// ignore changes to paths matching GLOB, in .gitignore syntax, e.g.
+    /// `node_modules/` or `*.log` (repeatable)
+    ///
+    /// Globs match relative to the root (or the --watch directory).
+    /// Version-control directories and editor swap files are always ignored.
+    #[arg(
+        long,
+        env,
+        value_name = "GLOB",
+        value_delimiter = ',',
+        value_parser = parse_glob,
+        help_heading = "Live reload"
+    )]
+    watch_ignore: Vec<String>,
+
+    /// don't skip changes to .gitignored paths
+    #[arg(long, env, help_heading = "Live reload")]
+    watch_gitignored: bool,
+
+    /// how long the files must be quiet before a burst of changes is sent,
+    /// e.g. 300ms
+    #[arg(
+        long,
+        env,
+        value_name = "DURATION",
+        default_value = "100ms",
+        value_parser = humantime::parse_duration,
+        help_heading = "Live reload"
+    )]
+    debounce: Duration,
+}
+
+fn parse_glob(glob: &str) -> Result<String, String> {
+    GitignoreBuilder::new("")
+        .add_line(None, glob)
+        .map_err(|error| error.to_string())?;
+    Ok(glob.to_string())
+}
+
+impl LiveReload {
+    /// Whether --live-reload is on, or implied by `render`.
+    pub fn is_enabled(&self, render: bool) -> bool {
+        self.live_reload || render
+    }
+
+    /// The live-reload handler for `root`, or `None` unless --live-reload or
+    /// `render` is on: an [`HtmlRewriter`] that injects the client script into
+    /// HTML responses, plus routes serving that script and the reload
+    /// websocket. Spawns the filesystem watcher as a side effect; it calls
+    /// `on_change` with every path in each burst of changes, ignored or not,
+    /// before telling browsers to reload, so the search index is current by the
+    /// time they do.
+    ///
+    /// Place this ahead of the file handler so its routes intercept
+    /// `/_serve_live.*`, and after compression so the rewriter runs on the
+    /// uncompressed body (both are `before_send` handlers; earlier in the tuple
+    /// runs later on the way out).
+    pub fn handler(
+        &self,
+        root: PathBuf,
+        render: bool,
+        on_change: impl Fn(&[PathBuf]) + Send + 'static,
+    ) -> Option<impl Handler> {
+        if !self.is_enabled(render) {
+            return None;
+        }
+        let mut dirs = vec![root.clone()];
+        for dir in &self.watch {
+            match dir.canonicalize() {
+                Ok(dir) => dirs.push(dir),
+                Err(error) => log::warn!("live reload: can't watch {}: {error}", dir.display()),
+            }
+        }
+        let filter = Filter {
+            dirs: dirs
+                .into_iter()
+                .map(|dir| {
+                    let globs = ignore_globs(&dir, &self.watch_ignore);
+                    (dir, globs)
+                })
+                .collect(),
+            gitignore: !self.watch_gitignored,
+        };
+        Some(handler(root, filter, self.debounce, on_change))
+    }
+}
+
+/// [`DEFAULT_IGNORES`] and `globs`, rooted at `dir`.
+fn ignore_globs(dir: &Path, globs: &[String]) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(dir);
+    for glob in DEFAULT_IGNORES
+        .iter()
+        .copied()
+        .chain(globs.iter().map(String::as_str))
+    {
+        // Checked by `parse_glob` already.
+        let _ = builder.add_line(None, glob);
+    }
+    builder.build().unwrap_or_else(|_| Gitignore::empty())
+}
+
+/// The watched directories, and which changes beneath them to drop.
+struct Filter {
+    /// The root first, then the `--watch` directories, each with its ignore
+    /// globs.
+    dirs: Vec<(PathBuf, Gitignore)>,
+    /// Whether to drop `.gitignore`d paths.
+    gitignore: bool,
+}
+
+impl Filter {
+    /// Whether a change to `path` should be dropped.
+    fn is_ignored(&self, path: &Path) -> bool {
+        // The innermost watched directory, in case one is inside another.
+        let Some((dir, globs)) = self
+            .dirs
+            .iter()
+            .filter(|(dir, _)| path.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+        else {
+            return false;
+        };
+        globs
+            .matched_path_or_any_parents(path, path.is_dir())
+            .is_ignore()
+            || (self.gitignore && is_gitignored(dir, path))
+    }
+}
+
+/// Build the handler [`LiveReload::handler`] describes.
+fn handler(
+    root: PathBuf,
+    filter: Filter,
+    debounce: Duration,
+    on_change: impl Fn(&[PathBuf]) + Send + 'static,
+) -> impl Handler {
+    // A change channel: the watcher thread broadcasts each burst's message, each
+    // connected browser holds its own receiver. Overflow-drop and
+    // don't-wait-for-a-receiver so a burst of changes (or no browser at all)
+    // never blocks the watcher.
+    let (mut sender, keepalive_rx) = async_broadcast::broadcast::<Arc<str>>(8);
     sender.set_overflow(true);
     sender.set_await_active(false);
 
@@ -46,7 +224,7 @@ pub fn handler(root: PathBuf) -> impl Handler {
         // Hold a receiver for the whole run so the channel never fully closes
         // between browser connections.
         let _keepalive = keepalive_rx;
-        watch(root, watch_sender);
+        watch(root, filter, debounce, watch_sender, on_change);
     });
 
     (
@@ -71,51 +249,96 @@ pub fn handler(root: PathBuf) -> impl Handler {
     )
 }
 
-/// One connected browser: forward every tick as a websocket message (the client
-/// reloads on any message), and end the task when the socket closes.
+/// One connected browser: forward every change message over the websocket, and
+/// end the task when the socket closes.
 async fn reload(mut conn: WebSocketConn) {
-    let Some(sender) = conn.state::<Sender<()>>().cloned() else {
+    let Some(sender) = conn.state::<Sender<Arc<str>>>().cloned() else {
         return;
     };
-    let mut ticks = sender.new_receiver();
+    let mut changes = sender.new_receiver();
 
     loop {
-        // Race a tick against the socket closing; scope the borrows so `conn` is
-        // free to send afterward.
+        // Race a change against the socket closing; scope the borrows so `conn`
+        // is free to send afterward.
         let event = {
-            let tick = pin!(async { Event::Tick(ticks.recv_direct().await.is_ok()) });
+            let change = pin!(async {
+                match changes.recv_direct().await {
+                    Ok(message) => Event::Change(message),
+                    // This browser fell behind and missed some: without knowing
+                    // which files they named, it has to reload.
+                    Err(RecvError::Overflowed(_)) => Event::Change(full_reload()),
+                    Err(RecvError::Closed) => Event::Closed,
+                }
+            });
             let socket = pin!(async {
                 conn.next().await;
                 Event::Closed
             });
-            future::or(tick, socket).await
+            future::or(change, socket).await
         };
 
         match event {
-            // A change: tell the browser to reload.
-            Event::Tick(true) => {
-                if conn.send_string("reload".to_string()).await.is_err() {
+            Event::Change(message) => {
+                if conn.send_string(message.to_string()).await.is_err() {
                     return;
                 }
             }
             // The channel closed, or the socket closed/errored: we're done.
-            Event::Tick(false) | Event::Closed => return,
+            Event::Closed => return,
         }
     }
 }
 
-/// The outcome of the tick/socket race in [`reload`].
+/// The outcome of the change/socket race in [`reload`].
 enum Event {
-    /// A watcher tick arrived; `true` if the channel is still open.
-    Tick(bool),
-    /// The websocket produced no more messages (closed/errored).
+    /// A burst of changes to pass on.
+    Change(Arc<str>),
+    /// The channel or the websocket closed (or errored).
     Closed,
 }
 
-/// Watch `root` recursively and broadcast a tick on each burst of real changes.
-/// Runs on its own thread; returns only if the watcher can't start or the
-/// channel closes.
-fn watch(root: PathBuf, sender: Sender<()>) {
+/// The message for a burst of changes: `{"paths": [...]}`, each the
+/// root-relative URL path of a changed file, unencoded (`/css/site.css`). A
+/// change outside the root has no path to name, so it makes the message a
+/// [`full_reload`].
+fn changes_message(root: &Path, changed: &[PathBuf]) -> Arc<str> {
+    match changed
+        .iter()
+        .map(|path| url_path(root, path))
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(paths) => json!({ "paths": paths }).to_string().into(),
+        None => full_reload(),
+    }
+}
+
+/// A message naming no paths, which the client takes as "reload the page".
+fn full_reload() -> Arc<str> {
+    json!({ "paths": [] }).to_string().into()
+}
+
+/// `path`'s URL path under `root`, or `None` outside it (or not UTF-8).
+fn url_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let mut url_path = String::new();
+    for component in relative.components() {
+        url_path.push('/');
+        url_path.push_str(component.as_os_str().to_str()?);
+    }
+    Some(url_path)
+}
+
+/// Watch `filter`'s directories recursively and, on each burst of real changes
+/// (one ending after `debounce` of quiet), pass the changed paths to
+/// `on_change` and broadcast those `filter` keeps. Runs on its own thread; returns
+/// only if the watcher can't start or the channel closes.
+fn watch(
+    root: PathBuf,
+    filter: Filter,
+    debounce: Duration,
+    sender: Sender<Arc<str>>,
+    on_change: impl Fn(&[PathBuf]),
+) {
     let (events_tx, events_rx) = mpsc::channel();
     let mut watcher = match RecommendedWatcher::new(events_tx, notify::Config::default()) {
         Ok(watcher) => watcher,
@@ -124,29 +347,47 @@ fn watch(root: PathBuf, sender: Sender<()>) {
             return;
         }
     };
-    if let Err(error) = watcher.watch(&root, RecursiveMode::Recursive) {
-        log::warn!("live reload: could not watch {}: {error}", root.display());
-        return;
+    for (dir, _) in &filter.dirs {
+        match watcher.watch(dir, RecursiveMode::Recursive) {
+            Ok(()) => log::info!("live reload watching {}", dir.display()),
+            Err(error) => log::warn!("live reload: could not watch {}: {error}", dir.display()),
+        }
     }
-    log::info!("live reload watching {}", root.display());
 
     loop {
         let Ok(first) = events_rx.recv() else {
             return;
         };
         // Coalesce a burst (one save can touch several files) into a single
-        // reload; only broadcast if at least one event was a real change rather
+        // message; only broadcast if at least one event was a real change rather
         // than a metadata/access blip.
-        let mut relevant = is_change(&first);
-        while let Ok(event) = events_rx.recv_timeout(Duration::from_millis(100)) {
-            relevant |= is_change(&event);
+        let mut changed = Vec::new();
+        collect_change(first, &mut changed);
+        while let Ok(event) = events_rx.recv_timeout(debounce) {
+            collect_change(event, &mut changed);
+        }
+        if changed.is_empty() {
+            continue;
         }
-        if relevant {
-            let _ = sender.try_broadcast(());
+        changed.sort();
+        changed.dedup();
+        on_change(&changed);
+        changed.retain(|path| !filter.is_ignored(path));
+        if !changed.is_empty() {
+            let _ = sender.try_broadcast(changes_message(&root, &changed));
         }
     }
 }
 
+/// Add an event's paths to `changed` if it is a real change (see [`is_change`]).
+fn collect_change(event: notify::Result<notify::Event>, changed: &mut Vec<PathBuf>) {
+    if is_change(&event)
+        && let Ok(event) = event
+    {
+        changed.extend(event.paths);
+    }
+}
+
 /// Whether an event is a content change worth reloading for — a create, modify,
 /// or remove. Access events (a plain read) and errors are ignored so merely
 /// serving a file doesn't trigger a reload loop.
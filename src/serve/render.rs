@@ -1,6 +1,8 @@
 //! `?render`: turn a file the static handler just served into a viewable HTML
-//! page — syntax-highlighted source, rendered markdown, or (as a universal
-//! escape hatch) a JSON envelope.
+//! page — syntax-highlighted source, rendered markdown, a table of CSV or TSV,
+//! a collapsible tree of JSON, YAML or TOML, a Jupyter notebook, an image
+//! viewer, an audio or video player, an embedded PDF, or (as a universal escape
+//! hatch) a JSON envelope.
 //!
 //! This handler is placed *after* [`StaticFileHandler`][trillium_static::StaticFileHandler],
 //! so it never resolves paths itself: the file has already been read into the
@@ -10,18 +12,54 @@
 //! rendered page. Anything else is left untouched for the normal file / listing
 //! / 404 paths.
 //!
+//! Media pages embed the raw file by relative URL, so the browser fetches it
+//! (with range requests, for seeking) like any other file. Only an image's body
+//! is read here, for its dimensions and EXIF summary; an audio, video or PDF
+//! body is dropped unread.
+//!
+//! The data views live in submodules: [`table`], [`tree`] and [`notebook`].
+//! A data file that doesn't parse gets the highlighted source view instead.
+//!
+//! Markdown pages get anchored headings and a table of contents, and [`site`]
+//! adds the rest of a docs site: the sidebar, front matter titles, and links
+//! between `.md` files that stay on rendered pages.
+//!
+//! Source files get numbered lines: `#L10` or `#L10-L20` highlights a line or a
+//! range (click a number, shift-click another to extend), and
+//! `?render&lines=10-20` renders just that excerpt. Long lines can be wrapped
+//! with a toggle that the browser remembers.
+//!
 //! Syntax highlighting uses [`two_face`]'s expanded syntax set (the stock
-//! syntect bundle omits Rust, TOML, and much else) and is CPU-bound, so it runs
-//! on the `blocking` pool the same way the directory listing's `read_dir` does.
+//! syntect bundle omits Rust, TOML, and much else) and its themes, one for light
+//! pages and one for dark (`--theme-light`, `--theme-dark`). It is CPU-bound,
+//! so it runs on the `blocking` pool the same way the directory listing's
+//! `read_dir` does.
+
+mod notebook;
+mod site;
+mod table;
+mod tree;
 
-use crate::assets::{BASE_CSS, MARKDOWN_CSS, THEME_HEAD, THEME_TOGGLE};
+use crate::assets::{
+    BASE_CSS, CODE_CSS, DATA_CSS, MARKDOWN_CSS, MEDIA_CSS, SEARCH_FORM, THEME_HEAD, THEME_TOGGLE,
+};
+use exif::{In, Tag as ExifTag};
 use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
 use pulldown_cmark::{
-    CodeBlockKind, Event, Options, Parser as MarkdownParser, Tag, TagEnd, html::push_html,
+    CodeBlockKind, Event, HeadingLevel, MetadataBlockKind, Options, Parser as MarkdownParser, Tag,
+    TagEnd, html::push_html,
+};
+use querystrong::QueryStrong;
+use size::Size;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
 };
-use std::{path::Path, sync::LazyLock};
 use syntect::{
-    highlighting::{Theme, ThemeSet},
+    highlighting::{Color, Theme},
     html::{ClassStyle, ClassedHTMLGenerator, css_for_theme_with_class_style},
     parsing::{SyntaxReference, SyntaxSet},
     util::LinesWithEndings,
@@ -32,15 +70,37 @@ use trillium::{
     Status,
 };
 use trillium_static::StaticConnExt;
+use two_face::theme::{EmbeddedLazyThemeSet, EmbeddedThemeName};
 
 /// The expanded syntax set (Rust, TOML, and friends), loaded once.
 static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(two_face::syntax::extra_newlines);
-/// syntect's bundled themes; we use a light one by default and a dark one under
-/// `prefers-color-scheme: dark`.
-static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
-/// The `<style>` block for highlighted code: token colors for both themes plus
-/// the `<pre>` background/foreground, built once from [`THEMES`].
-static CODE_CSS: LazyLock<String> = LazyLock::new(build_code_css);
+/// two-face's themes, each decoded on first use: one for light pages and one
+/// for dark.
+static THEMES: LazyLock<EmbeddedLazyThemeSet> = LazyLock::new(two_face::theme::extra);
+
+/// The light theme without `--theme-light`.
+pub const DEFAULT_LIGHT_THEME: EmbeddedThemeName = EmbeddedThemeName::InspiredGithub;
+/// The dark theme without `--theme-dark`: warm, so it sits on base.css's warm
+/// dark page instead of clashing like a cool blue-grey theme would.
+pub const DEFAULT_DARK_THEME: EmbeddedThemeName = EmbeddedThemeName::Base16MochaDark;
+
+/// Parse a `--theme-light`/`--theme-dark` value: a two-face theme name, in any
+/// case.
+pub fn parse_theme(name: &str) -> Result<EmbeddedThemeName, String> {
+    let themes = EmbeddedLazyThemeSet::theme_names();
+    themes
+        .iter()
+        .copied()
+        .find(|theme| theme.as_name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names = themes
+                .iter()
+                .map(|theme| format!("\"{theme}\""))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("unknown theme; choose one of {names}")
+        })
+}
 
 /// Output format selected by the `render` query param: bare `?render` (or
 /// `?render=html`) renders a page; `?render=json` returns a JSON envelope.
@@ -66,9 +126,101 @@ fn requested_format(querystring: &str) -> Option<Format> {
     format
 }
 
-/// Renders a served file as an HTML page (or JSON) when `?render` is present.
+/// Files `?render` shows in a viewer or player rather than as text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Media {
+    Image,
+    Audio,
+    Video,
+    Pdf,
+}
+
+/// The kind of media a (lower-cased) extension names, if any: the formats
+/// browsers display or play natively.
+fn media_kind(ext: &str) -> Option<Media> {
+    match ext {
+        "jpg" | "jpeg" | "png" | "gif" | "webp" | "avif" | "svg" | "bmp" | "ico" => {
+            Some(Media::Image)
+        }
+        "mp3" | "wav" | "ogg" | "oga" | "opus" | "flac" | "m4a" | "aac" => Some(Media::Audio),
+        "mp4" | "m4v" | "webm" | "ogv" | "mov" => Some(Media::Video),
+        "pdf" => Some(Media::Pdf),
+        _ => None,
+    }
+}
+
+/// Files `?render` shows as structured data rather than as source.
 #[derive(Debug, Clone, Copy)]
-pub struct Render;
+enum Data {
+    /// CSV or TSV, split on this delimiter.
+    Table(u8),
+    Tree(tree::Syntax),
+    Notebook,
+}
+
+/// The data view for a (lower-cased) extension, if it has one.
+fn data_kind(ext: &str) -> Option<Data> {
+    match ext {
+        "csv" => Some(Data::Table(b',')),
+        "tsv" | "tab" => Some(Data::Table(b'\t')),
+        "ipynb" => Some(Data::Notebook),
+        _ => tree::Syntax::from_extension(ext).map(Data::Tree),
+    }
+}
+
+/// Renders a served file as an HTML page (or JSON) when `?render` is present.
+/// `root` is the first served root, where markdown pages find their sidebar;
+/// `None` when that's an archive, which leaves the sidebar off.
+#[derive(Debug, Clone)]
+pub struct Render {
+    root: Option<PathBuf>,
+    /// The sidebar, shared with the clone live reload invalidates it through.
+    nav: Arc<site::Nav>,
+    /// The `<style>` for highlighted code, from the light and dark themes.
+    highlight_css: Arc<str>,
+    /// Whether markdown pages load mermaid and KaTeX from a CDN.
+    cdn_scripts: bool,
+}
+
+impl Render {
+    /// Render files with the sidebar built from `root`, highlighting code in
+    /// the default themes.
+    pub fn new(root: Option<PathBuf>) -> Self {
+        Self {
+            root,
+            nav: Arc::default(),
+            highlight_css: build_highlight_css(
+                THEMES.get(DEFAULT_LIGHT_THEME),
+                THEMES.get(DEFAULT_DARK_THEME),
+            )
+            .into(),
+            cdn_scripts: false,
+        }
+    }
+
+    /// Draw mermaid diagrams and math on markdown pages with scripts from
+    /// jsDelivr, rather than leaving them as source.
+    pub fn with_cdn_scripts(self) -> Self {
+        Self {
+            cdn_scripts: true,
+            ..self
+        }
+    }
+
+    /// Rebuild the markdown sidebar on the next page, after files under the
+    /// root changed.
+    pub fn files_changed(&self) {
+        self.nav.invalidate();
+    }
+
+    /// Highlight code in `light` on light pages and `dark` on dark ones.
+    pub fn with_themes(self, light: EmbeddedThemeName, dark: EmbeddedThemeName) -> Self {
+        Self {
+            highlight_css: build_highlight_css(THEMES.get(light), THEMES.get(dark)).into(),
+            ..self
+        }
+    }
+}
 
 impl Handler for Render {
     // `before_send`, not `run`: the static handler `halt`s when it serves a file
@@ -97,6 +249,10 @@ impl Handler for Render {
             .unwrap_or_default()
             .to_ascii_lowercase();
 
+        if let (Format::Html, Some(media)) = (format, media_kind(&ext)) {
+            return render_media(conn, &path, media).await;
+        }
+
         let Some(body) = conn.take_response_body() else {
             return conn;
         };
@@ -110,7 +266,7 @@ impl Handler for Render {
 
         match format {
             Format::Json => render_json(conn, &path, &ext, bytes),
-            Format::Html => render_html(conn, &path, &ext, bytes).await,
+            Format::Html => render_html(conn, &path, &ext, bytes, self).await,
         }
     }
 }
@@ -118,8 +274,8 @@ impl Handler for Render {
 /// Build the HTML page. Non-UTF-8 (binary) content can't become a text page, so
 /// the original bytes are restored and served as-is; an unrecognized text type
 /// is likewise served raw (use `?render=json` to force a structured view).
-async fn render_html(conn: Conn, path: &str, ext: &str, bytes: Vec<u8>) -> Conn {
-    let text = match String::from_utf8(bytes) {
+async fn render_html(conn: Conn, path: &str, ext: &str, bytes: Vec<u8>, render: &Render) -> Conn {
+    let mut text = match String::from_utf8(bytes) {
         Ok(text) => text,
         // Restore the untouched body; the static handler's content-type stands.
         Err(error) => return conn.with_body(error.into_bytes()),
@@ -130,15 +286,41 @@ async fn render_html(conn: Conn, path: &str, ext: &str, bytes: Vec<u8>) -> Conn
     // it resolves against the current `…/name?render` URL to `…/name` regardless
     // of any router mount prefix.
     let raw_href = raw_href(path);
+
+    if let Some(kind) = data_kind(ext) {
+        let querystring = conn.querystring().to_string();
+        // As with highlighting, a miss hands the text back for the source view.
+        match blocking::unblock(move || render_data(kind, &text, &querystring).ok_or(text)).await {
+            Ok(content) => {
+                let body = data_page(&title, &raw_href, kind, &content, &render.highlight_css);
+                return finalize(conn, "text/html; charset=utf-8", body);
+            }
+            Err(returned) => text = returned,
+        }
+    }
+
     let body = if is_markdown(ext) {
-        let rendered = blocking::unblock(move || markdown_to_html(&text)).await;
-        markdown_page(&title, &raw_href, &rendered)
+        let (root, nav, current) = (render.root.clone(), render.nav.clone(), path.to_string());
+        let (markdown, sidebar) = blocking::unblock(move || {
+            (
+                Markdown::render(&text),
+                root.and_then(|root| site::sidebar(&root, &nav, &current)),
+            )
+        })
+        .await;
+        markdown_page(&title, &raw_href, &markdown, sidebar.as_deref(), render)
     } else {
         let ext = ext.to_string();
+        let excerpt = Excerpt::parse(conn.querystring());
         // The closure owns `text` (blocking::unblock needs `'static`); on a miss
         // it hands the text back so we can serve the file untouched.
         match blocking::unblock(move || highlight(&ext, &text).ok_or(text)).await {
-            Ok(inner) => code_page(&title, &raw_href, &inner),
+            Ok(inner) => code_page(
+                &title,
+                &raw_href,
+                &numbered_lines(&inner, excerpt),
+                &render.highlight_css,
+            ),
             // Unrecognized text type: serve the file as the static handler had it.
             Err(text) => return conn.with_body(text.into_bytes()),
         }
@@ -147,6 +329,134 @@ async fn render_html(conn: Conn, path: &str, ext: &str, bytes: Vec<u8>) -> Conn
     finalize(conn, "text/html; charset=utf-8", body)
 }
 
+/// A data file's page content, or `None` when it doesn't parse as its
+/// extension says. CPU-bound: call on the blocking pool.
+fn render_data(kind: Data, text: &str, querystring: &str) -> Option<String> {
+    match kind {
+        Data::Table(delimiter) => table::render(text, delimiter, querystring),
+        Data::Tree(syntax) => tree::render(text, syntax),
+        Data::Notebook => notebook::render(text),
+    }
+}
+
+/// Build a viewer (images, PDFs) or player (audio, video) page around the raw
+/// file. An image is read for its dimensions and EXIF summary; any other body
+/// is dropped unread, since the page fetches the file itself.
+async fn render_media(mut conn: Conn, path: &str, media: Media) -> Conn {
+    let title = display_path(path);
+    let raw_href = raw_href(path);
+    let Some(body) = conn.take_response_body() else {
+        return conn;
+    };
+    let mut caption = body
+        .len()
+        .map(|len| Size::from_bytes(len).to_string())
+        .unwrap_or_default();
+
+    let content = match media {
+        Media::Image => {
+            let bytes = match body.into_bytes().await {
+                Ok(bytes) => bytes.into_owned(),
+                Err(error) => {
+                    log::warn!("could not read {path} for rendering: {error}");
+                    return conn.with_status(Status::InternalServerError);
+                }
+            };
+            let details = blocking::unblock(move || ImageDetails::read(&bytes)).await;
+            if let Some((width, height)) = details.dimensions {
+                caption = format!("{width} \u{d7} {height} px \u{b7} {caption}");
+            }
+            format!(
+                "<figure class=\"media\"><a href=\"{raw_href}\"><img src=\"{raw_href}\" \
+                 alt=\"{title}\"></a><figcaption>{caption}</figcaption></figure>\n{}",
+                exif_table(&details.exif)
+            )
+        }
+        Media::Audio => format!(
+            "<figure class=\"media\"><audio controls preload=\"metadata\" \
+             src=\"{raw_href}\"></audio><figcaption>{caption}</figcaption></figure>"
+        ),
+        Media::Video => format!(
+            "<figure class=\"media\"><video controls playsinline preload=\"metadata\" \
+             src=\"{raw_href}\"></video><figcaption>{caption}</figcaption></figure>"
+        ),
+        Media::Pdf => {
+            format!("<iframe class=\"pdf\" src=\"{raw_href}\" title=\"{title}\"></iframe>")
+        }
+    };
+
+    // Video and PDFs want more than the reading measure.
+    let body_class = if matches!(media, Media::Video | Media::Pdf) {
+        "wide"
+    } else {
+        ""
+    };
+    let body = page(
+        &title,
+        body_class,
+        &raw_href,
+        &format!("<link rel=\"stylesheet\" href=\"{MEDIA_CSS}\">"),
+        &content,
+    );
+    finalize(conn, "text/html; charset=utf-8", body)
+}
+
+/// The EXIF fields summarized on an image page, with their labels.
+const EXIF_SUMMARY: &[(&str, ExifTag)] = &[
+    ("Camera", ExifTag::Model),
+    ("Lens", ExifTag::LensModel),
+    ("Taken", ExifTag::DateTimeOriginal),
+    ("Exposure", ExifTag::ExposureTime),
+    ("Aperture", ExifTag::FNumber),
+    ("ISO", ExifTag::PhotographicSensitivity),
+    ("Focal length", ExifTag::FocalLength),
+];
+
+/// What an image page shows about the image besides the image itself.
+struct ImageDetails {
+    /// Width and height in pixels, when the header can be read.
+    dimensions: Option<(usize, usize)>,
+    /// The [`EXIF_SUMMARY`] fields the image has, labeled.
+    exif: Vec<(&'static str, String)>,
+}
+
+impl ImageDetails {
+    /// Read an image's details. CPU-bound: call on the blocking pool.
+    fn read(bytes: &[u8]) -> Self {
+        let dimensions = imagesize::blob_size(bytes)
+            .ok()
+            .map(|size| (size.width, size.height));
+        let exif = exif::Reader::new()
+            .read_from_container(&mut Cursor::new(bytes))
+            .map(|exif| {
+                EXIF_SUMMARY
+                    .iter()
+                    .filter_map(|&(label, tag)| {
+                        let field = exif.get_field(tag, In::PRIMARY)?;
+                        let value = field.display_value().with_unit(&exif).to_string();
+                        let value = value.trim_matches('"').trim();
+                        (!value.is_empty()).then(|| (label, value.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { dimensions, exif }
+    }
+}
+
+/// The EXIF summary as a two-column table, or nothing when there is none.
+fn exif_table(fields: &[(&str, String)]) -> String {
+    if fields.is_empty() {
+        return String::new();
+    }
+    let mut table = String::from("<table class=\"exif\">");
+    for (label, value) in fields {
+        let _ = write!(table, "<tr><th>{label}</th><td>{}</td></tr>", escape(value));
+    }
+    table.push_str("</table>");
+    table
+}
+
 /// A JSON envelope for the file — the escape hatch for types we can't render as
 /// a page. Binary content is reported without a `content` field.
 fn render_json(conn: Conn, path: &str, ext: &str, bytes: Vec<u8>) -> Conn {
@@ -196,7 +506,7 @@ fn highlight(ext: &str, text: &str) -> Option<String> {
 }
 
 /// Highlight `source` with `syntax` into class-tagged `<span>`s (the classes are
-/// styled by [`CODE_CSS`]). Shared by the standalone code view and markdown's
+/// styled by [`build_highlight_css`]). Shared by the standalone code view and markdown's
 /// fenced blocks.
 fn spans(syntax: &SyntaxReference, source: &str) -> String {
     let mut generator =
@@ -209,49 +519,348 @@ fn spans(syntax: &SyntaxReference, source: &str) -> String {
     generator.finalize()
 }
 
-/// The markdown extensions we enable — GitHub-flavored-ish, plus smart quotes.
-///
-/// Fenced code blocks are intercepted and syntect-highlighted (reusing the same
-/// syntaxes and CSS as the standalone code view) instead of being emitted as a
-/// plain `<pre><code>`; everything else is left to pulldown's default rendering.
-fn markdown_to_html(text: &str) -> String {
-    let options = Options::ENABLE_TABLES
-        | Options::ENABLE_STRIKETHROUGH
-        | Options::ENABLE_FOOTNOTES
-        | Options::ENABLE_TASKLISTS
-        | Options::ENABLE_SMART_PUNCTUATION
-        | Options::ENABLE_GFM;
-
-    // Rewrite each code block's events (`Start(CodeBlock)`, `Text`…, `End`) into
-    // a single `Html` event carrying the highlighted block. `code` holds the
-    // language token and accumulated source while we're inside one.
-    let mut events = Vec::new();
-    let mut code: Option<(String, String)> = None;
-    for event in MarkdownParser::new_ext(text, options) {
-        match event {
-            Event::Start(Tag::CodeBlock(kind)) => {
-                let lang = match kind {
-                    CodeBlockKind::Fenced(info) => language_token(&info).to_string(),
-                    CodeBlockKind::Indented => String::new(),
-                };
-                code = Some((lang, String::new()));
+/// The lines `?render&lines=10-20` (or `lines=10`) asks for, 1-based and
+/// inclusive.
+#[derive(Debug, Clone, Copy)]
+struct Excerpt {
+    first: usize,
+    last: usize,
+}
+
+impl Excerpt {
+    fn parse(querystring: &str) -> Option<Self> {
+        let qs = QueryStrong::parse(querystring);
+        let lines = qs.get_str("lines")?;
+        let (first, last) = lines.split_once('-').unwrap_or((lines, lines));
+        let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+        (first >= 1 && last >= first).then_some(Self { first, last })
+    }
+}
+
+/// Lay highlighted code out as numbered lines, each `<span class="line"
+/// id="L12">` with a number linking `#L12`. A token spanning lines (a block
+/// comment, a multiline string) has its `<span>`s closed at each line's end and
+/// reopened at the next start, so every line is balanced. With an `excerpt`,
+/// only those lines are kept, keeping their numbers.
+fn numbered_lines(html: &str, excerpt: Option<Excerpt>) -> String {
+    let lines = split_lines(html);
+    let total = lines.len();
+    let (first, last) = match excerpt {
+        Some(Excerpt { first, last }) => (first.min(total.max(1)), last.min(total)),
+        None => (1, total),
+    };
+    let width = total.to_string().len();
+
+    let mut out = String::new();
+    if let Some(Excerpt {
+        first: from,
+        last: to,
+    }) = excerpt
+    {
+        let _ = write!(
+            out,
+            "<nav class=\"code-tools\"><span>lines {first}\u{2013}{last} of {total}</span><a \
+             href=\"?render#L{from}-L{to}\">whole file</a>"
+        );
+    } else {
+        out.push_str("<nav class=\"code-tools\">");
+    }
+    let _ = write!(
+        out,
+        "<button class=\"wrap-toggle\" type=\"button\" aria-pressed=\"false\">wrap \
+         lines</button></nav>\n<pre class=\"code numbered\" style=\"--digits:{width}\"><code>"
+    );
+    for (number, line) in lines
+        .iter()
+        .enumerate()
+        .skip(first - 1)
+        .take(last + 1 - first)
+    {
+        let number = number + 1;
+        let _ = write!(
+            out,
+            "<span class=\"line\" id=\"L{number}\"><a class=\"ln\" href=\"#L{number}\" \
+             data-n=\"{number}\"></a><span class=\"lc\">{line}</span></span>"
+        );
+    }
+    let _ = write!(
+        out,
+        "</code></pre>\n<script>{}</script>",
+        include_str!("render/lines.js")
+    );
+    out
+}
+
+/// Split highlighted HTML into lines, closing the `<span>`s open at each
+/// newline and reopening them on the next line. A trailing newline doesn't
+/// start an empty last line, and a `\r` before a newline is dropped.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut line = String::new();
+    let mut has_text = false;
+    let mut rest = html;
+    while let Some(index) = rest.find(['<', '\n']) {
+        let text = &rest[..index];
+        has_text |= !text.is_empty();
+        line.push_str(text);
+        rest = &rest[index..];
+        if let Some(after) = rest.strip_prefix('\n') {
+            if line.ends_with('\r') {
+                line.pop();
             }
-            // Code content arrives as `Text` events while a block is open.
-            Event::Text(text) if code.is_some() => {
-                code.as_mut().unwrap().1.push_str(&text);
+            for _ in &open {
+                line.push_str("</span>");
             }
-            Event::End(TagEnd::CodeBlock) => {
-                if let Some((lang, source)) = code.take() {
-                    events.push(Event::Html(highlight_fenced(&lang, &source).into()));
+            lines.push(std::mem::take(&mut line));
+            for tag in &open {
+                line.push_str(tag);
+            }
+            has_text = false;
+            rest = after;
+        } else {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            let tag = &rest[..end];
+            if tag.starts_with("</") {
+                open.pop();
+            } else {
+                open.push(tag);
+            }
+            line.push_str(tag);
+            rest = &rest[end..];
+        }
+    }
+    if has_text || !rest.is_empty() || lines.is_empty() {
+        line.push_str(rest);
+        lines.push(line);
+    }
+    lines
+}
+
+/// A rendered markdown page: its HTML, and what the page around it needs to
+/// know.
+struct Markdown {
+    html: String,
+    /// From front matter, if it sets one.
+    title: Option<String>,
+    /// The `h2` and `h3` headings, for the table of contents.
+    toc: Vec<Heading>,
+    /// Whether the page has mermaid diagrams or math for the browser to render.
+    mermaid: bool,
+    math: bool,
+}
+
+/// One table of contents entry.
+struct Heading {
+    level: HeadingLevel,
+    id: String,
+    text: String,
+}
+
+/// Render markdown with no page around it (a notebook's markdown cells).
+fn markdown_to_html(text: &str) -> String {
+    Markdown::render(text).html
+}
+
+impl Markdown {
+    /// The markdown extensions we enable — GitHub-flavored-ish (tables, task
+    /// lists, footnotes, alerts), plus smart quotes, math, `{#id}` heading
+    /// attributes, and YAML or TOML front matter.
+    ///
+    /// Fenced code blocks are intercepted and syntect-highlighted (reusing the
+    /// same syntaxes and CSS as the standalone code view) instead of being
+    /// emitted as a plain `<pre><code>`; `mermaid` and `math` blocks are kept as
+    /// source for the browser to render. Every heading gets an id and an anchor
+    /// link, and links to other markdown files get `?render`. Everything else is
+    /// left to pulldown's default rendering.
+    fn render(text: &str) -> Self {
+        let options = Options::ENABLE_TABLES
+            | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_FOOTNOTES
+            | Options::ENABLE_TASKLISTS
+            | Options::ENABLE_SMART_PUNCTUATION
+            | Options::ENABLE_GFM
+            | Options::ENABLE_MATH
+            | Options::ENABLE_HEADING_ATTRIBUTES
+            | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+            | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+
+        let mut markdown = Markdown {
+            html: String::new(),
+            title: None,
+            toc: Vec::new(),
+            mermaid: false,
+            math: false,
+        };
+
+        // Rewrite each code block's events (`Start(CodeBlock)`, `Text`…, `End`)
+        // into a single `Html` event carrying the highlighted block. `code` holds
+        // the language token and accumulated source while we're inside one;
+        // `front_matter` likewise collects a metadata block, which renders as
+        // nothing.
+        let mut events = Vec::new();
+        let mut code: Option<(String, String)> = None;
+        let mut front_matter: Option<(MetadataBlockKind, String)> = None;
+        for event in MarkdownParser::new_ext(text, options) {
+            match event {
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    let lang = match kind {
+                        CodeBlockKind::Fenced(info) => language_token(&info).to_string(),
+                        CodeBlockKind::Indented => String::new(),
+                    };
+                    code = Some((lang, String::new()));
+                }
+                Event::Start(Tag::MetadataBlock(kind)) => {
+                    front_matter = Some((kind, String::new()));
+                }
+                // Code and front matter content arrives as `Text` events while a
+                // block is open.
+                Event::Text(text) if code.is_some() => {
+                    code.as_mut().unwrap().1.push_str(&text);
+                }
+                Event::Text(text) if front_matter.is_some() => {
+                    front_matter.as_mut().unwrap().1.push_str(&text);
+                }
+                Event::End(TagEnd::CodeBlock) => {
+                    if let Some((lang, source)) = code.take() {
+                        let block = match lang.as_str() {
+                            "mermaid" => {
+                                markdown.mermaid = true;
+                                format!("<pre class=\"mermaid\">{}</pre>", escape(&source))
+                            }
+                            "math" | "latex" | "tex" => {
+                                markdown.math = true;
+                                format!(
+                                    "<div class=\"math math-display\">{}</div>",
+                                    escape(&source)
+                                )
+                            }
+                            _ => highlight_fenced(&lang, &source),
+                        };
+                        events.push(Event::Html(block.into()));
+                    }
+                }
+                Event::End(TagEnd::MetadataBlock(_)) => {
+                    if let Some((kind, source)) = front_matter.take() {
+                        markdown.title = site::front_matter_title(kind, &source);
+                    }
+                }
+                Event::InlineMath(_) | Event::DisplayMath(_) => {
+                    markdown.math = true;
+                    events.push(event);
+                }
+                Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }) => {
+                    let dest_url = site::rewrite_link(&dest_url).map_or(dest_url, Into::into);
+                    events.push(Event::Start(Tag::Link {
+                        link_type,
+                        dest_url,
+                        title,
+                        id,
+                    }));
                 }
+                other => events.push(other),
             }
-            other => events.push(other),
         }
+
+        markdown.toc = anchor_headings(&mut events);
+        push_html(&mut markdown.html, events.into_iter());
+        markdown
     }
+}
+
+/// Give every heading an id — its own `{#id}` or a GitHub-style slug of its
+/// text, made unique — and end it with an anchor link to itself. Returns the
+/// `h2` and `h3` headings, for the table of contents.
+fn anchor_headings(events: &mut Vec<Event>) -> Vec<Heading> {
+    let mut toc = Vec::new();
+    let mut slugs = HashMap::<String, usize>::new();
+    let mut index = 0;
+    while index < events.len() {
+        let Event::Start(Tag::Heading { level, id, .. }) = &events[index] else {
+            index += 1;
+            continue;
+        };
+        let (level, id) = (*level, id.as_ref().map(ToString::to_string));
+        let Some(end) = events[index..]
+            .iter()
+            .position(|event| matches!(event, Event::End(TagEnd::Heading(_))))
+            .map(|offset| index + offset)
+        else {
+            break;
+        };
+        let text = events[index + 1..end]
+            .iter()
+            .filter_map(|event| match event {
+                Event::Text(text) | Event::Code(text) | Event::InlineMath(text) => {
+                    Some(text.as_ref())
+                }
+                _ => None,
+            })
+            .collect::<String>();
 
-    let mut html = String::new();
-    push_html(&mut html, events.into_iter());
-    html
+        let slug = match id {
+            Some(id) => id,
+            None => {
+                let base = slugify(&text);
+                let seen = slugs.entry(base.clone()).or_default();
+                *seen += 1;
+                let slug = match *seen {
+                    1 => base,
+                    n => format!("{base}-{}", n - 1),
+                };
+                if let Event::Start(Tag::Heading { id, .. }) = &mut events[index] {
+                    *id = Some(slug.clone().into());
+                }
+                slug
+            }
+        };
+        events.insert(
+            end,
+            Event::InlineHtml(
+                format!(
+                    "<a class=\"anchor\" href=\"#{}\" aria-label=\"link to this \
+                     section\">#</a>",
+                    escape(&slug)
+                )
+                .into(),
+            ),
+        );
+        if matches!(level, HeadingLevel::H2 | HeadingLevel::H3) {
+            toc.push(Heading {
+                level,
+                id: slug,
+                text,
+            });
+        }
+        index = end + 2;
+    }
+    toc
+}
+
+/// A heading's text as an id, the way GitHub does it: lower-cased, spaces to
+/// hyphens, and punctuation other than `-` and `_` dropped.
+fn slugify(text: &str) -> String {
+    let slug = text
+        .trim()
+        .chars()
+        .filter_map(|ch| match ch {
+            ' ' => Some('-'),
+            '-' | '_' => Some(ch),
+            ch if ch.is_alphanumeric() => Some(ch),
+            _ => None,
+        })
+        .flat_map(char::to_lowercase)
+        .collect::<String>();
+    if slug.is_empty() {
+        "section".into()
+    } else {
+        slug
+    }
 }
 
 /// The language token from a fence info string: the first word, split on commas
@@ -264,7 +873,7 @@ fn language_token(info: &str) -> &str {
 
 /// Render one fenced code block: syntect-highlighted when the language resolves,
 /// otherwise an escaped plain block. Wrapped like the standalone code view so it
-/// picks up [`CODE_CSS`].
+/// picks up the highlight `<style>`.
 fn highlight_fenced(lang: &str, source: &str) -> String {
     let inner = match (!lang.is_empty())
         .then(|| SYNTAXES.find_syntax_by_token(lang))
@@ -281,11 +890,14 @@ fn is_markdown(ext: &str) -> bool {
 }
 
 /// Whether a file with this (lower-cased) extension renders as a *page* —
-/// markdown, or any type syntect can highlight. Used by the directory listing to
-/// decide which rows get a `?render` link. Everything else can still be fetched
-/// as `?render=json`, but that's not worth a link.
+/// markdown, a data view, media, or any type syntect can highlight. Used by the directory
+/// listing to decide which rows get a `?render` link. Everything else can still
+/// be fetched as `?render=json`, but that's not worth a link.
 pub fn is_renderable(ext: &str) -> bool {
-    is_markdown(ext) || SYNTAXES.find_syntax_by_extension(ext).is_some()
+    is_markdown(ext)
+        || data_kind(ext).is_some()
+        || media_kind(ext).is_some()
+        || SYNTAXES.find_syntax_by_extension(ext).is_some()
 }
 
 /// The request path shown as a page title, leading slash trimmed and escaped.
@@ -295,7 +907,7 @@ fn display_path(path: &str) -> String {
 
 /// Characters to percent-encode in the "view raw" link's path segment — controls
 /// plus anything that could break out of the `href` or read as a query.
-const RAW_SEGMENT: &AsciiSet = &CONTROLS
+pub(super) const RAW_SEGMENT: &AsciiSet = &CONTROLS
     .add(b' ')
     .add(b'"')
     .add(b'#')
@@ -314,34 +926,109 @@ fn raw_href(path: &str) -> String {
     utf8_percent_encode(name, RAW_SEGMENT).to_string()
 }
 
-/// Wrap highlighted code spans in a full page. syntect already escapes the code.
-/// The syntect token colors ([`CODE_CSS`]) are generated at runtime, so they're
-/// inlined; the shared shell ([`BASE_CSS`]) is linked from [`page`]. The `wide`
-/// body class drops the reading measure so source lines get room to breathe.
-fn code_page(title: &str, raw_href: &str, inner: &str) -> String {
+/// Wrap numbered code lines in a full page. syntect already escapes the code.
+/// The syntect token colors (`highlight_css`) are generated at runtime from the
+/// chosen themes, so they're inlined; [`CODE_CSS`] lays out the line numbers
+/// and the shared shell ([`BASE_CSS`]) is linked from [`page`]. The `wide` body
+/// class drops the reading measure so source lines get room to breathe.
+fn code_page(title: &str, raw_href: &str, lines: &str, highlight_css: &str) -> String {
     page(
         title,
         "wide",
         raw_href,
-        &format!("<style>{}</style>", *CODE_CSS),
-        &format!("<pre class=\"code\"><code>{inner}</code></pre>"),
+        &format!("<link rel=\"stylesheet\" href=\"{CODE_CSS}\">\n<style>{highlight_css}</style>"),
+        lines,
     )
 }
 
-/// Wrap rendered markdown (already HTML) in a full page. Links [`MARKDOWN_CSS`]
-/// and inlines [`CODE_CSS`] after it, so syntect-highlighted fenced blocks are
-/// colored and `pre.code` wins over the generic `.markdown pre` rule.
-fn markdown_page(title: &str, raw_href: &str, article: &str) -> String {
-    page(
-        title,
-        "",
-        raw_href,
-        &format!(
-            "<link rel=\"stylesheet\" href=\"{MARKDOWN_CSS}\">\n<style>{}</style>",
-            *CODE_CSS
+/// Wrap rendered markdown in a full page. Links [`MARKDOWN_CSS`] and inlines
+/// `highlight_css` after it, so syntect-highlighted fenced blocks are colored and
+/// `pre.code` wins over the generic `.markdown pre` rule.
+///
+/// With a `sidebar`, or two or more headings for a table of contents, the page
+/// takes the `docs` layout: sidebar, article and contents side by side. A front
+/// matter title replaces the path as the page title. Under `--cdn-scripts`, the
+/// mermaid and KaTeX scripts are added only to pages that use them.
+fn markdown_page(
+    title: &str,
+    raw_href: &str,
+    markdown: &Markdown,
+    sidebar: Option<&str>,
+    render: &Render,
+) -> String {
+    let highlight_css = &render.highlight_css;
+    let title = markdown
+        .title
+        .as_deref()
+        .map_or_else(|| title.to_string(), escape);
+    let mut head = format!(
+        "<link rel=\"stylesheet\" href=\"{MARKDOWN_CSS}\">\n<style>{highlight_css}</style>"
+    );
+    if markdown.math && render.cdn_scripts {
+        head.push('\n');
+        head.push_str(site::MATH_SCRIPT);
+    }
+    if markdown.mermaid && render.cdn_scripts {
+        head.push('\n');
+        head.push_str(site::MERMAID_SCRIPT);
+    }
+
+    let article = format!("<article class=\"markdown\">{}</article>", markdown.html);
+    let toc = if markdown.toc.len() >= 2 {
+        let mut toc = String::from("<nav class=\"toc\"><p>On this page</p><ul>");
+        for heading in &markdown.toc {
+            let class = if heading.level == HeadingLevel::H3 {
+                " class=\"sub\""
+            } else {
+                ""
+            };
+            let _ = write!(
+                toc,
+                "<li{class}><a href=\"#{}\">{}</a></li>",
+                escape(&heading.id),
+                escape(&heading.text)
+            );
+        }
+        toc.push_str("</ul></nav>");
+        Some(toc)
+    } else {
+        None
+    };
+
+    if sidebar.is_none() && toc.is_none() {
+        return page(&title, "", raw_href, &head, &article);
+    }
+    // Empty slots keep the article in the middle column.
+    let content = format!(
+        "<div class=\"docs\">{}{article}{}</div>",
+        sidebar.unwrap_or("<nav class=\"sidebar\"></nav>"),
+        toc.as_deref().unwrap_or("<nav class=\"toc\"></nav>"),
+    );
+    page(&title, "docs", raw_href, &head, &content)
+}
+
+/// Wrap a data view in a full page. Links [`DATA_CSS`]; a notebook also gets
+/// [`MARKDOWN_CSS`] and `highlight_css` for its markdown and code cells. Tables
+/// take the `wide` layout, since their columns want the room.
+fn data_page(
+    title: &str,
+    raw_href: &str,
+    kind: Data,
+    content: &str,
+    highlight_css: &str,
+) -> String {
+    let data_css = format!("<link rel=\"stylesheet\" href=\"{DATA_CSS}\">");
+    let (body_class, head) = match kind {
+        Data::Table(_) => ("wide", data_css),
+        Data::Tree(_) => ("", data_css),
+        Data::Notebook => (
+            "",
+            format!(
+                "<link rel=\"stylesheet\" href=\"{MARKDOWN_CSS}\">\n{data_css}\n<style>{highlight_css}</style>"
+            ),
         ),
-        &format!("<article class=\"markdown\">{article}</article>"),
-    )
+    };
+    page(title, body_class, raw_href, &head, content)
 }
 
 /// The shared page shell. [`BASE_CSS`] (fonts + palette + layout) is always
@@ -362,6 +1049,7 @@ fn page(title: &str, body_class: &str, raw_href: &str, head: &str, content: &str
 </head>\n\
 <body class=\"{body_class}\">\n\
 {THEME_TOGGLE}\n\
+{SEARCH_FORM}\n\
 <main>\n\
 <h1>{title}</h1>\n\
 <nav class=\"pagenav\"><a href=\"{raw_href}\">view raw</a></nav>\n\
@@ -373,9 +1061,9 @@ fn page(title: &str, body_class: &str, raw_href: &str, head: &str, content: &str
     )
 }
 
-/// Build the code `<style>` from both themes. `InspiredGitHub` is the light
-/// theme; `base16-mocha.dark` the dark one — warm, so it sits on base.css's warm
-/// dark page instead of clashing like a cool blue-grey theme would.
+/// Build the code `<style>` from both themes: token colors, the `<pre>`
+/// background and foreground, and the line numbers and highlighted lines of the
+/// numbered view, in the theme's gutter and line-highlight colors.
 ///
 /// The dark rules are applied through the same three-way cascade as base.css's
 /// palette: the system-dark default (unless the visitor forced light) *and* an
@@ -383,45 +1071,50 @@ fn page(title: &str, body_class: &str, raw_href: &str, head: &str, content: &str
 /// light OS re-colors the code too, instead of leaving a light block on a dark
 /// page. The `:root{…}` wrappers rely on CSS nesting, so a bare `.comment{…}`
 /// token rule inside becomes `:root[…] .comment`.
-fn build_code_css() -> String {
-    let light = &THEMES.themes["InspiredGitHub"];
-    let dark = &THEMES.themes["base16-mocha.dark"];
-    let light_tokens =
-        css_for_theme_with_class_style(light, ClassStyle::Spaced).unwrap_or_default();
-    let dark_tokens = css_for_theme_with_class_style(dark, ClassStyle::Spaced).unwrap_or_default();
-    let (light_bg, light_fg) = theme_colors(light);
-    let (dark_bg, dark_fg) = theme_colors(dark);
+fn build_highlight_css(light: &Theme, dark: &Theme) -> String {
+    let light_rules = theme_rules(light);
+    let dark_rules = theme_rules(dark);
     format!(
-        "pre.code{{background:{light_bg};color:{light_fg};padding:1rem \
-         1.25rem;overflow:auto;border-radius:8px;font:13px/1.6 'IBM Plex \
-         Mono',ui-monospace,SFMono-Regular,Menlo,monospace;}}\npre.code \
-         code{{white-space:pre;}}\n{light_tokens}\n@media(prefers-color-scheme:dark){{:root:\
-         not([data-theme=\"light\"]){{pre.code{{background:{dark_bg};color:{dark_fg};}}\\
-         n{dark_tokens}\n}}}}\n:root[data-theme=\"dark\"]{{pre.code{{background:{dark_bg};color:\
-         {dark_fg};}}\n{dark_tokens}\n}}"
+        "pre.code{{padding:1rem 1.25rem;overflow:auto;border-radius:8px;font:13px/1.6 'IBM \
+         Plex Mono',ui-monospace,SFMono-Regular,Menlo,monospace;}}\npre.code \
+         code{{white-space:pre;}}\n{light_rules}\n@media(prefers-color-scheme:dark){{:root:\
+         not([data-theme=\"light\"]){{{dark_rules}}}}}\n:root[data-theme=\"dark\"]{{{dark_rules}}}"
     )
 }
 
-/// The background/foreground of a theme as `#rrggbb`, defaulting sensibly when a
-/// theme leaves them unset.
-fn theme_colors(theme: &Theme) -> (String, String) {
-    let hex = |c: syntect::highlighting::Color| format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b);
-    (
-        theme
-            .settings
-            .background
-            .map(hex)
-            .unwrap_or_else(|| "#ffffff".into()),
-        theme
-            .settings
-            .foreground
-            .map(hex)
-            .unwrap_or_else(|| "#000000".into()),
+/// One theme's colors: its token classes, the `<pre>` background and
+/// foreground, the line-number gutter and the highlighted-line background.
+fn theme_rules(theme: &Theme) -> String {
+    let tokens = css_for_theme_with_class_style(theme, ClassStyle::Spaced).unwrap_or_default();
+    let settings = &theme.settings;
+    let background = settings.background.map_or_else(|| "#ffffff".into(), hex);
+    let foreground = settings.foreground.map_or_else(|| "#000000".into(), hex);
+    let gutter = settings
+        .gutter_foreground
+        .map_or_else(|| foreground.clone(), hex);
+    // Many themes' `lineHighlight` is too faint to pick out a linked range, so
+    // prefer the selection color, then that, then a translucent amber.
+    let highlight = settings
+        .selection
+        .or(settings.line_highlight)
+        .map_or_else(|| "rgba(230,170,40,.25)".into(), hex);
+    format!(
+        "pre.code{{background:{background};color:{foreground};}}\npre.code \
+         .ln{{color:{gutter};}}\npre.code .line.hl{{background:{highlight};}}\n{tokens}\n"
     )
 }
 
+/// A theme color as `#rrggbb`, or `#rrggbbaa` when it isn't opaque.
+fn hex(color: Color) -> String {
+    let Color { r, g, b, a } = color;
+    match a {
+        0xff => format!("#{r:02x}{g:02x}{b:02x}"),
+        _ => format!("#{r:02x}{g:02x}{b:02x}{a:02x}"),
+    }
+}
+
 /// HTML-escape text destined for an element body or attribute value.
-fn escape(text: &str) -> String {
+pub(super) fn escape(text: &str) -> String {
     let mut out = String::with_capacity(text.len());
     for ch in text.chars() {
         match ch {
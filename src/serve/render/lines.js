@@ -0,0 +1,60 @@
+// The numbered code view: `#L10` or `#L10-L20` highlights a line or a range,
+// clicking a line number selects it and shift-clicking another extends the
+// range, and the wrap toggle's choice is remembered.
+(() => {
+  const pre = document.querySelector("pre.code.numbered");
+  const toggle = document.querySelector(".code-tools .wrap-toggle");
+  if (!pre) return;
+  const WRAP_KEY = "trillium-wrap";
+  let anchor = null;
+
+  function setWrap(on) {
+    pre.classList.toggle("wrap", on);
+    toggle?.setAttribute("aria-pressed", String(on));
+  }
+  try {
+    setWrap(localStorage.getItem(WRAP_KEY) === "1");
+  } catch {}
+  toggle?.addEventListener("click", () => {
+    const on = !pre.classList.contains("wrap");
+    setWrap(on);
+    try {
+      localStorage.setItem(WRAP_KEY, on ? "1" : "0");
+    } catch {}
+  });
+
+  // Mark the lines the hash names, and optionally bring the first into view.
+  function highlight(scroll) {
+    for (const line of pre.querySelectorAll(".line.hl")) line.classList.remove("hl");
+    const match = /^#L(\d+)(?:-L?(\d+))?$/.exec(location.hash);
+    if (!match) return;
+    let from = Number(match[1]);
+    let to = Number(match[2] ?? match[1]);
+    if (from > to) [from, to] = [to, from];
+    anchor = from;
+    let first = null;
+    for (let number = from; number <= to; number++) {
+      const line = document.getElementById(`L${number}`);
+      if (!line) continue;
+      line.classList.add("hl");
+      first ??= line;
+    }
+    if (scroll && first) first.scrollIntoView({ block: "center" });
+  }
+
+  pre.addEventListener("click", (event) => {
+    const link = event.target.closest("a.ln");
+    if (!link) return;
+    event.preventDefault();
+    const number = Number(link.dataset.n);
+    const hash =
+      event.shiftKey && anchor !== null && anchor !== number
+        ? `#L${Math.min(anchor, number)}-L${Math.max(anchor, number)}`
+        : `#L${number}`;
+    history.replaceState(null, "", hash);
+    highlight(false);
+    if (!event.shiftKey) anchor = number;
+  });
+  addEventListener("hashchange", () => highlight(true));
+  highlight(true);
+})();
@@ -0,0 +1,156 @@
+//! Jupyter notebooks (`.ipynb`) as a page of cells.
+//!
+//! Markdown cells go through the same renderer as a `.md` file, and code cells
+//! are highlighted in the notebook's kernel language, with their `In [n]`
+//! prompt. Saved outputs follow each code cell: the richest representation a
+//! browser can show — an image, HTML, markdown — falling back to plain text.
+//! Tracebacks have their terminal color codes stripped.
+//!
+//! Only nbformat 4 (every notebook written since 2015) is understood; anything
+//! else falls back to the source view.
+
+use super::{escape, highlight_fenced, markdown_to_html};
+use serde_json::Value;
+use std::fmt::Write;
+
+/// Render a notebook's JSON as a page's content. `None` when it isn't an
+/// nbformat 4 notebook.
+pub(super) fn render(text: &str) -> Option<String> {
+    let notebook = serde_json::from_str::<Value>(text).ok()?;
+    if notebook.get("nbformat")?.as_u64()? != 4 {
+        return None;
+    }
+    let metadata = &notebook["metadata"];
+    let language = metadata["language_info"]["name"]
+        .as_str()
+        .or_else(|| metadata["kernelspec"]["language"].as_str())
+        .unwrap_or("python");
+
+    let mut html = String::from("<div class=\"notebook\">\n");
+    for cell in notebook.get("cells")?.as_array()? {
+        let source = joined(&cell["source"]);
+        match cell["cell_type"].as_str() {
+            Some("markdown") => {
+                let _ = writeln!(
+                    html,
+                    "<div class=\"cell markdown\">{}</div>",
+                    markdown_to_html(&source)
+                );
+            }
+            Some("code") => {
+                let prompt = match cell["execution_count"].as_u64() {
+                    Some(count) => format!("In [{count}]"),
+                    None => "In [ ]".into(),
+                };
+                let _ = write!(
+                    html,
+                    "<div class=\"cell code\"><div class=\"prompt\">{prompt}</div>{}",
+                    highlight_fenced(language, &source)
+                );
+                for output in cell["outputs"].as_array().into_iter().flatten() {
+                    write_output(&mut html, output);
+                }
+                html.push_str("</div>\n");
+            }
+            _ => {
+                let _ = writeln!(
+                    html,
+                    "<div class=\"cell raw\"><pre>{}</pre></div>",
+                    escape(&source)
+                );
+            }
+        }
+    }
+    html.push_str("</div>");
+    Some(html)
+}
+
+/// Append one saved output of a code cell.
+fn write_output(html: &mut String, output: &Value) {
+    match output["output_type"].as_str() {
+        Some("stream") => {
+            let stderr = if output["name"] == "stderr" {
+                " stderr"
+            } else {
+                ""
+            };
+            let _ = write!(
+                html,
+                "<pre class=\"output stream{stderr}\">{}</pre>",
+                escape(&strip_ansi(&joined(&output["text"])))
+            );
+        }
+        Some("error") => {
+            let traceback = output["traceback"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("\n");
+            let _ = write!(
+                html,
+                "<pre class=\"output error\">{}</pre>",
+                escape(&strip_ansi(&traceback))
+            );
+        }
+        // `execute_result` and `display_data`: a bundle of representations.
+        _ => {
+            let data = &output["data"];
+            let rich = if let Some(png) = data["image/png"].as_str() {
+                image("png", png)
+            } else if let Some(jpeg) = data["image/jpeg"].as_str() {
+                image("jpeg", jpeg)
+            } else if !data["image/svg+xml"].is_null() {
+                joined(&data["image/svg+xml"])
+            } else if !data["text/html"].is_null() {
+                joined(&data["text/html"])
+            } else if !data["text/markdown"].is_null() {
+                markdown_to_html(&joined(&data["text/markdown"]))
+            } else if !data["text/plain"].is_null() {
+                format!("<pre>{}</pre>", escape(&joined(&data["text/plain"])))
+            } else {
+                return;
+            };
+            let _ = write!(html, "<div class=\"output\">{rich}</div>");
+        }
+    }
+}
+
+/// An inline image from a base64 bundle entry. Notebooks wrap the base64 at
+/// 76 columns, which a `data:` URL doesn't allow.
+fn image(subtype: &str, base64: &str) -> String {
+    let base64 = base64.split_whitespace().collect::<String>();
+    format!("<img src=\"data:image/{subtype};base64,{base64}\" alt=\"\">")
+}
+
+/// A multiline field: notebooks store these as a list of lines (each keeping
+/// its newline) or, less often, as one string.
+fn joined(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+/// Remove terminal escape sequences (`ESC [ … letter`), which tracebacks are
+/// full of.
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\u{1b}' {
+            out.push(ch);
+            continue;
+        }
+        if chars.next() == Some('[') {
+            for ch in chars.by_ref() {
+                if ch.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
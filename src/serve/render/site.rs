@@ -0,0 +1,260 @@
+//! Markdown site mode: what makes a folder of markdown files read as one docs
+//! site under `serve --render`.
+//!
+//! Every markdown page gets a sidebar. When the served root has a `SUMMARY.md`
+//! (mdBook's table of contents), that file *is* the sidebar; otherwise it is
+//! built from the markdown files beneath the root, skipping hidden and
+//! `.gitignore`d paths. That list is built once and kept in a [`Nav`] until
+//! live reload's watcher sees a change. Relative links to `.md` files are
+//! rewritten to their `?render` form so following one stays on rendered pages,
+//! and a page's front matter may set its title.
+//!
+//! Mermaid diagrams and math are left in the page as source. Under
+//! `--cdn-scripts`, pages that have them load pinned versions of mermaid and
+//! KaTeX from jsDelivr to draw them in the browser; otherwise the page makes no
+//! network requests and they stay as source.
+
+use super::{RAW_SEGMENT, escape, is_markdown};
+use ignore::WalkBuilder;
+use percent_encoding::utf8_percent_encode;
+use pulldown_cmark::{
+    Event, HeadingLevel, MetadataBlockKind, Parser as MarkdownParser, Tag, TagEnd, html::push_html,
+};
+use serde::Deserialize;
+use std::{cmp::Ordering, fmt::Write, fs, path::Path, sync::Mutex};
+
+/// The most markdown files the generated sidebar lists.
+const MAX_PAGES: usize = 1000;
+
+/// The most entries the generated sidebar's walk looks at, so a huge tree of
+/// other files can't stall a page.
+const MAX_WALKED: usize = 50_000;
+
+/// Renders `pre.mermaid` blocks, in the page's current light or dark theme.
+pub(super) const MERMAID_SCRIPT: &str = "<script type=\"module\">import mermaid from \
+     \"https://cdn.jsdelivr.net/npm/mermaid@11.4.1/dist/mermaid.esm.min.mjs\";const \
+     t=document.documentElement.dataset.theme;const \
+     dark=t?t===\"dark\":matchMedia(\"(prefers-color-scheme: \
+     dark)\").matches;mermaid.initialize({startOnLoad:false,theme:dark?\"dark\":\"default\"});\
+     await mermaid.run({querySelector:\"pre.mermaid\"});</script>";
+
+/// Renders the `.math` spans and blocks with KaTeX.
+pub(super) const MATH_SCRIPT: &str = "<link rel=\"stylesheet\" \
+     href=\"https://cdn.jsdelivr.net/npm/katex@0.16.11/dist/katex.min.css\">\n<script \
+     type=\"module\">import katex from \
+     \"https://cdn.jsdelivr.net/npm/katex@0.16.11/dist/katex.mjs\";for(const el of \
+     document.querySelectorAll(\".math\"))katex.render(el.textContent,el,{displayMode:el.\
+     classList.contains(\"math-display\"),throwOnError:false});</script>";
+
+/// The front matter fields a page's rendering uses; the rest are ignored.
+#[derive(Debug, Deserialize)]
+struct FrontMatter {
+    title: Option<String>,
+}
+
+/// The `title` from a YAML (`---`) or TOML (`+++`) front matter block, if it
+/// parses and has one.
+pub(super) fn front_matter_title(kind: MetadataBlockKind, text: &str) -> Option<String> {
+    let front_matter = match kind {
+        MetadataBlockKind::YamlStyle => serde_yaml_ng::from_str::<FrontMatter>(text).ok()?,
+        MetadataBlockKind::PlusesStyle => toml::from_str::<FrontMatter>(text).ok()?,
+    };
+    front_matter.title.filter(|title| !title.trim().is_empty())
+}
+
+/// A link to a markdown file, with its `?render` query added: `guide.md#setup`
+/// becomes `guide.md?render#setup`. `None` for anything else — links to other
+/// sites, other file types, and ones that already carry a query.
+pub(super) fn rewrite_link(dest: &str) -> Option<String> {
+    if dest.starts_with('#') || dest.starts_with("//") || dest.contains(':') || dest.contains('?') {
+        return None;
+    }
+    let (path, fragment) = match dest.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (dest, None),
+    };
+    let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    if !is_markdown(&ext) {
+        return None;
+    }
+    Some(match fragment {
+        Some(fragment) => format!("{path}?render#{fragment}"),
+        None => format!("{path}?render"),
+    })
+}
+
+/// The sidebar's contents, built on first use and kept until
+/// [`Nav::invalidate`].
+#[derive(Debug, Default)]
+pub struct Nav(Mutex<Option<Option<String>>>);
+
+impl Nav {
+    /// Rebuild the sidebar for the next page, after files changed.
+    pub fn invalidate(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    /// `SUMMARY.md` when the root has one, otherwise the markdown files
+    /// beneath `root`; `None` when there's nothing to navigate between.
+    fn get(&self, root: &Path) -> Option<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| match fs::read_to_string(root.join("SUMMARY.md")) {
+                Ok(summary) => Some(summary_nav(&summary)),
+                Err(_) => tree_nav(root),
+            })
+            .clone()
+    }
+}
+
+/// The sidebar for the page at `current` (its request path), from `nav`.
+/// Blocking: call on the blocking pool.
+pub(super) fn sidebar(root: &Path, nav: &Nav, current: &str) -> Option<String> {
+    let nav = nav.get(root)?;
+    // Mark the current page so the stylesheet can highlight it.
+    let current = format!("href=\"{current}?render\"");
+    let nav = nav.replacen(&current, &format!("{current} aria-current=\"page\""), 1);
+    Some(format!("<nav class=\"sidebar\">{nav}</nav>"))
+}
+
+/// Render `SUMMARY.md` as the sidebar. Its links are relative to the root, so
+/// they're made absolute; its leading `# Summary` heading is dropped, and draft
+/// chapters (mdBook's empty `[Title]()` links) show as plain text.
+fn summary_nav(summary: &str) -> String {
+    let mut events = Vec::new();
+    let mut seen_title = false;
+    let mut in_title = false;
+    let mut in_draft = false;
+    for event in MarkdownParser::new(summary) {
+        match event {
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H1,
+                ..
+            }) if !seen_title => {
+                seen_title = true;
+                in_title = true;
+            }
+            Event::End(TagEnd::Heading(HeadingLevel::H1)) if in_title => in_title = false,
+            _ if in_title => {}
+            Event::Start(Tag::Link { dest_url, .. }) if dest_url.is_empty() => in_draft = true,
+            Event::End(TagEnd::Link) if in_draft => in_draft = false,
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                let dest_url = match rewrite_link(&dest_url) {
+                    Some(rendered) if !rendered.starts_with('/') => format!("/{rendered}").into(),
+                    Some(rendered) => rendered.into(),
+                    None => dest_url,
+                };
+                events.push(Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }));
+            }
+            other => events.push(other),
+        }
+    }
+    let mut html = String::new();
+    push_html(&mut html, events.into_iter());
+    html
+}
+
+/// A nested list of the markdown files beneath `root`, files ahead of
+/// subdirectories and a directory's README or index first. `None` for fewer
+/// than two pages.
+fn tree_nav(root: &Path) -> Option<String> {
+    let mut pages = WalkBuilder::new(root)
+        .require_git(false)
+        .build()
+        .take(MAX_WALKED)
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| is_markdown(&ext.to_ascii_lowercase()))
+        })
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(root).ok()?;
+            relative
+                .components()
+                .map(|component| component.as_os_str().to_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+        })
+        .take(MAX_PAGES)
+        .collect::<Vec<_>>();
+    if pages.len() < 2 {
+        return None;
+    }
+    pages.sort_by(|a, b| compare_pages(a, b));
+
+    let mut html = String::from("<ul>");
+    // The directories the previous page was in, to open and close nested
+    // lists as the next one moves in or out of them.
+    let mut open: &[String] = &[];
+    for page in &pages {
+        let (name, dirs) = page.split_last()?;
+        let shared = open
+            .iter()
+            .zip(dirs)
+            .take_while(|(open, dir)| open == dir)
+            .count();
+        for _ in shared..open.len() {
+            html.push_str("</ul></li>");
+        }
+        for dir in &dirs[shared..] {
+            let _ = write!(html, "<li><span class=\"dir\">{}</span><ul>", escape(dir));
+        }
+        open = dirs;
+
+        let href = page
+            .iter()
+            .map(|segment| utf8_percent_encode(segment, RAW_SEGMENT).to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        let label = Path::new(name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(name);
+        let _ = write!(
+            html,
+            "<li><a href=\"/{href}?render\">{}</a></li>",
+            escape(label)
+        );
+    }
+    for _ in open {
+        html.push_str("</ul></li>");
+    }
+    html.push_str("</ul>");
+    Some(html)
+}
+
+/// Sidebar order for two root-relative paths: within a directory, files before
+/// subdirectories, a README or index first, then by name.
+fn compare_pages(a: &[String], b: &[String]) -> Ordering {
+    for (depth, (a_name, b_name)) in a.iter().zip(b).enumerate() {
+        if a_name == b_name {
+            continue;
+        }
+        let key = |path: &[String], name: &str| {
+            let is_dir = depth + 1 < path.len();
+            let stem = Path::new(name)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(name)
+                .to_lowercase();
+            let is_index = !is_dir && matches!(stem.as_str(), "readme" | "index");
+            (is_dir, !is_index, stem)
+        };
+        return key(a, a_name).cmp(&key(b, b_name));
+    }
+    a.len().cmp(&b.len())
+}
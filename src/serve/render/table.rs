@@ -0,0 +1,191 @@
+//! CSV and TSV as an HTML table.
+//!
+//! The first record is the header row. Sorting and paging happen here rather
+//! than in the browser, like the directory listing's: the column heads link
+//! `?render&sort=N&order=…`, and a file longer than [`PAGE_SIZE`] rows is split
+//! across `&page=N`. Cells compare as numbers when both parse as one, so a
+//! numeric column sorts `9` before `10`.
+
+use super::escape;
+use querystrong::QueryStrong;
+use std::{cmp::Ordering, fmt::Write};
+
+/// Rows per page.
+const PAGE_SIZE: usize = 500;
+
+/// The sort and page one request asks for.
+struct View {
+    /// Column index; `None` keeps file order.
+    sort: Option<usize>,
+    descending: bool,
+    /// 1-based.
+    page: usize,
+}
+
+impl View {
+    fn parse(querystring: &str) -> Self {
+        let qs = QueryStrong::parse(querystring);
+        Self {
+            sort: qs.get_str("sort").and_then(|sort| sort.parse().ok()),
+            descending: qs.get_str("order") == Some("desc"),
+            page: qs
+                .get_str("page")
+                .and_then(|page| page.parse().ok())
+                .unwrap_or(1)
+                .max(1),
+        }
+    }
+
+    /// The query for this view with `sort`, `order` and `page` replaced.
+    fn href(sort: Option<usize>, descending: bool, page: usize) -> String {
+        let mut href = String::from("?render");
+        if let Some(sort) = sort {
+            let order = if descending { "desc" } else { "asc" };
+            let _ = write!(href, "&amp;sort={sort}&amp;order={order}");
+        }
+        if page > 1 {
+            let _ = write!(href, "&amp;page={page}");
+        }
+        href
+    }
+}
+
+/// Render `text`, split on `delimiter`, as a table page's content. `None` when
+/// it doesn't parse, or has no header row, so the caller can fall back to the
+/// source view.
+pub(super) fn render(text: &str, delimiter: u8, querystring: &str) -> Option<String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut records = reader
+        .records()
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?
+        .into_iter();
+    let header = records.next()?;
+    // Each row keeps its 1-based line in the data, shown in the first column
+    // so a sorted row can still be found in the file.
+    let mut rows = records.enumerate().collect::<Vec<_>>();
+    let columns = rows
+        .iter()
+        .map(|(_, row)| row.len())
+        .chain([header.len()])
+        .max()
+        .unwrap_or_default();
+
+    let view = View::parse(querystring);
+    if let Some(column) = view.sort.filter(|&column| column < columns) {
+        rows.sort_by(|(_, a), (_, b)| {
+            let ordering = compare(a.get(column).unwrap_or(""), b.get(column).unwrap_or(""));
+            if view.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+
+    let pages = rows.len().div_ceil(PAGE_SIZE).max(1);
+    let page = view.page.min(pages);
+    let start = (page - 1) * PAGE_SIZE;
+    let shown = &rows[start..rows.len().min(start + PAGE_SIZE)];
+
+    let mut html = String::new();
+    let plural = |count: usize, noun: &str| match count {
+        1 => format!("1 {noun}"),
+        _ => format!("{count} {noun}s"),
+    };
+    let _ = writeln!(
+        html,
+        "<p class=\"table-summary\">{} \u{b7} {}</p>",
+        plural(rows.len(), "row"),
+        plural(columns, "column")
+    );
+    let nav = pager(&view, page, pages, start, shown.len(), rows.len());
+    html.push_str(&nav);
+    html.push_str("<div class=\"table-scroll\"><table class=\"data\">\n<thead><tr><th>#</th>");
+    for column in 0..columns {
+        let label = escape(header.get(column).unwrap_or(""));
+        let active = view.sort == Some(column);
+        // Clicking the active column flips it; an arrow marks it, pointing the
+        // way values grow reading down.
+        let descending = active && !view.descending;
+        let (class, arrow) = match (active, view.descending) {
+            (true, false) => (" class=\"active\"", " \u{2193}"),
+            (true, true) => (" class=\"active\"", " \u{2191}"),
+            (false, _) => ("", ""),
+        };
+        let _ = write!(
+            html,
+            "<th{class}><a href=\"{}\">{label}{arrow}</a></th>",
+            View::href(Some(column), descending, 1)
+        );
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+    for (line, row) in shown {
+        let _ = write!(html, "<tr><td class=\"line\">{}</td>", line + 1);
+        for column in 0..columns {
+            let cell = row.get(column).unwrap_or("");
+            let class = if cell.trim().parse::<f64>().is_ok() {
+                " class=\"number\""
+            } else {
+                ""
+            };
+            let _ = write!(html, "<td{class}>{}</td>", escape(cell));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table></div>\n");
+    if pages > 1 {
+        html.push_str(&nav);
+    }
+    Some(html)
+}
+
+/// Numbers before text, numbers numerically, text by its lower case.
+fn compare(a: &str, b: &str) -> Ordering {
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.to_lowercase().cmp(&b.to_lowercase()),
+    }
+}
+
+/// Previous/next links and the rows on show; empty on a single page.
+fn pager(
+    view: &View,
+    page: usize,
+    pages: usize,
+    start: usize,
+    shown: usize,
+    total: usize,
+) -> String {
+    if pages == 1 {
+        return String::new();
+    }
+    let link = |label: &str, page: usize| {
+        format!(
+            "<a href=\"{}\">{label}</a>",
+            View::href(view.sort, view.descending, page)
+        )
+    };
+    let previous = if page > 1 {
+        link("\u{2190} previous", page - 1)
+    } else {
+        String::new()
+    };
+    let next = if page < pages {
+        link("next \u{2192}", page + 1)
+    } else {
+        String::new()
+    };
+    format!(
+        "<nav class=\"pages\">{previous}<span>rows {}\u{2013}{} of {total} \u{b7} page {page} \
+         of {pages}</span>{next}</nav>\n",
+        start + 1,
+        start + shown,
+    )
+}
@@ -0,0 +1,204 @@
+//! JSON, YAML and TOML as a collapsible tree.
+//!
+//! All three parse into one [`Node`] through serde, which keeps keys in
+//! document order (a `serde_json::Value` would sort them). Each object and
+//! array becomes a `<details>` element, so the tree folds without any script;
+//! the first two levels start open.
+
+use super::escape;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::fmt::{self, Write};
+
+/// How many levels start expanded.
+const OPEN_DEPTH: usize = 2;
+
+/// The key toml's deserializer wraps a datetime in, so it can tell one apart
+/// from a string. Unwrapped back into a scalar here.
+const TOML_DATETIME: &str = "$__toml_private_datetime";
+
+/// The formats the tree view reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Syntax {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Syntax {
+    pub(super) fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "json" | "geojson" | "webmanifest" => Some(Syntax::Json),
+            "yaml" | "yml" => Some(Syntax::Yaml),
+            "toml" => Some(Syntax::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// One value in the document.
+enum Node {
+    /// A leaf, with the class it is colored by (`string`, `number`, `bool`,
+    /// `null` or `date`) and its text, unquoted.
+    Scalar(&'static str, String),
+    List(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+/// Render `text` as a tree page's content. `None` when it doesn't parse, so
+/// the caller can fall back to the source view.
+pub(super) fn render(text: &str, syntax: Syntax) -> Option<String> {
+    let root = match syntax {
+        Syntax::Json => serde_json::from_str(text).ok()?,
+        Syntax::Toml => toml::from_str(text).ok()?,
+        // A stream of several documents shows as a list of them.
+        Syntax::Yaml => {
+            let mut documents = serde_yaml_ng::Deserializer::from_str(text)
+                .map(Node::deserialize)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            match documents.len() {
+                0 => Node::Scalar("null", "null".into()),
+                1 => documents.remove(0),
+                _ => Node::List(documents),
+            }
+        }
+    };
+    let mut html = String::from("<div class=\"tree\">");
+    write_node(&mut html, None, &root, 0);
+    html.push_str("</div>");
+    Some(html)
+}
+
+/// Append `node` (under `key`, in a map) to `html`, `depth` levels down.
+fn write_node(html: &mut String, key: Option<&str>, node: &Node, depth: usize) {
+    let key = key
+        .map(|key| format!("<span class=\"key\">{}</span>: ", escape(key)))
+        .unwrap_or_default();
+    let (open, close, count) = match node {
+        Node::Scalar(class, text) => {
+            let quote = if *class == "string" { "\"" } else { "" };
+            let _ = write!(
+                html,
+                "<div class=\"leaf\">{key}<span class=\"{class}\">{quote}{}{quote}</span></div>",
+                escape(text)
+            );
+            return;
+        }
+        Node::List(items) => ('[', ']', items.len()),
+        Node::Map(entries) => ('{', '}', entries.len()),
+    };
+    if count == 0 {
+        let _ = write!(
+            html,
+            "<div class=\"leaf\">{key}<span class=\"empty\">{open}{close}</span></div>"
+        );
+        return;
+    }
+
+    let expanded = if depth < OPEN_DEPTH { " open" } else { "" };
+    let noun = match (node, count) {
+        (Node::List(_), 1) => "item",
+        (Node::List(_), _) => "items",
+        (_, 1) => "key",
+        _ => "keys",
+    };
+    let _ = write!(
+        html,
+        "<details{expanded}><summary>{key}<span class=\"brace\">{open}</span> <span \
+         class=\"count\">{count} {noun}</span></summary><div class=\"children\">"
+    );
+    match node {
+        Node::List(items) => {
+            for item in items {
+                write_node(html, None, item, depth + 1);
+            }
+        }
+        Node::Map(entries) => {
+            for (key, value) in entries {
+                write_node(html, Some(key), value, depth + 1);
+            }
+        }
+        Node::Scalar(..) => {}
+    }
+    let _ = write!(html, "</div><span class=\"brace\">{close}</span></details>");
+}
+
+impl Node {
+    /// A map key as text: YAML allows any scalar (or worse) as a key.
+    fn into_key(self) -> String {
+        match self {
+            Node::Scalar(_, text) => text,
+            Node::List(_) => "[\u{2026}]".into(),
+            Node::Map(_) => "{\u{2026}}".into(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON, YAML or TOML value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Node, E> {
+        Ok(Node::Scalar("bool", value.to_string()))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Node, E> {
+        Ok(Node::Scalar("number", value.to_string()))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Node, E> {
+        Ok(Node::Scalar("number", value.to_string()))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Node, E> {
+        Ok(Node::Scalar("number", value.to_string()))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Node, E> {
+        Ok(Node::Scalar("string", value.to_string()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Node, E> {
+        Ok(Node::Scalar("null", "null".into()))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Node, E> {
+        self.visit_unit()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Node, D::Error> {
+        Node::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Node::List(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let mut entries = Vec::new();
+        while let Some((key, value)) = map.next_entry::<Node, Node>()? {
+            entries.push((key.into_key(), value));
+        }
+        if let [(key, Node::Scalar(_, text))] = entries.as_mut_slice()
+            && key == TOML_DATETIME
+        {
+            return Ok(Node::Scalar("date", std::mem::take(text)));
+        }
+        Ok(Node::Map(entries))
+    }
+}
@@ -0,0 +1,298 @@
+//! `serve dist/ public/`: several roots overlaid, each request answered from
+//! the first root that has its path. A root is a directory, a single file, or
+//! an archive served from inside ([`ArchiveRoot`]).
+//!
+//! [`Layers`] runs each root's file handler in turn until one answers. A
+//! directory that no root has an index for is listed with the entries of
+//! every root that has it, the earlier root's entry winning a name they
+//! share. When only one root on disk has it, the listing gets a plain
+//! [`ResolvedDirectory`], as with a single root.
+
+use super::{
+    archive_root::{ArchiveRoot, is_archive},
+    root_path::RootPath,
+};
+use crate::directory_listing::{
+    Entry, ListDirectory, VirtualDirectory, ancestor_gitignores, is_ignored,
+};
+use percent_encoding::percent_decode_str;
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use trillium::{Conn, Handler, Info};
+use trillium_static::{ResolvedDirectory, StaticFileHandler};
+
+/// One served root.
+#[derive(Debug, Clone)]
+pub enum Root {
+    /// A directory, or a single file served for every path.
+    Disk(PathBuf),
+    Archive(Arc<ArchiveRoot>),
+}
+
+impl Root {
+    /// The root at `path`, indexing it now if it's an archive. Exits if the
+    /// archive can't be read.
+    pub fn open(path: RootPath) -> Self {
+        let path = PathBuf::from(path);
+        if !is_archive(&path) {
+            return Self::Disk(path);
+        }
+        match ArchiveRoot::open(&path) {
+            Ok(archive) => Self::Archive(Arc::new(archive)),
+            Err(error) => {
+                eprintln!("could not read archive {}: {error}", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// The directory, file or archive.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Disk(path) => path,
+            Self::Archive(archive) => archive.path(),
+        }
+    }
+
+    /// The root's path on disk, unless it's an archive.
+    pub fn disk(&self) -> Option<&Path> {
+        match self {
+            Self::Disk(path) => Some(path),
+            Self::Archive(_) => None,
+        }
+    }
+}
+
+/// The path below the root a request names, decoded; `None` if it climbs out.
+pub fn relative_path(url_path: &str) -> Option<String> {
+    let decoded = percent_decode_str(url_path).decode_utf8().ok()?;
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// `name` inside the directory `dir`, either of which may be the root (`""`).
+fn join(dir: &str, name: &str) -> String {
+    match (dir, name) {
+        ("", name) => name.to_string(),
+        (dir, "") => dir.to_string(),
+        (dir, name) => format!("{dir}/{name}"),
+    }
+}
+
+/// The roots' file handlers, tried in order.
+#[derive(Debug)]
+pub struct Layers {
+    layers: Vec<Layer>,
+    index: Option<String>,
+    gitignore: bool,
+}
+
+#[derive(Debug)]
+enum Layer {
+    Disk {
+        files: StaticFileHandler,
+        root: PathBuf,
+    },
+    Archive(Arc<ArchiveRoot>),
+}
+
+/// Which layer answered a request, for [`Layers::served_root`].
+struct Served(usize);
+
+impl Layers {
+    /// Serve `roots`, whose directories serve `index`; `files` builds the
+    /// static handler for a root on disk.
+    pub fn new(
+        roots: &[Root],
+        index: Option<String>,
+        files: impl Fn(&Path) -> StaticFileHandler,
+    ) -> Self {
+        let layers = roots
+            .iter()
+            .map(|root| match root {
+                Root::Disk(root) => Layer::Disk {
+                    files: files(root),
+                    root: root.clone(),
+                },
+                Root::Archive(archive) => Layer::Archive(Arc::clone(archive)),
+            })
+            .collect();
+        Self {
+            layers,
+            index,
+            gitignore: false,
+        }
+    }
+
+    /// Leave `.gitignore`d files on disk out of listings that merge several
+    /// roots, as [`DirectoryListing::with_gitignore`] does for the rest.
+    ///
+    /// [`DirectoryListing::with_gitignore`]: crate::directory_listing::DirectoryListing::with_gitignore
+    pub fn with_gitignore(self) -> Self {
+        Self {
+            gitignore: true,
+            ..self
+        }
+    }
+
+    /// The root on disk whose static handler answered `conn`, if one did.
+    pub fn served_root(&self, conn: &Conn) -> Option<&Path> {
+        match self.layers.get(conn.state::<Served>()?.0)? {
+            Layer::Disk { root, .. } => Some(root),
+            Layer::Archive(_) => None,
+        }
+    }
+}
+
+impl Handler for Layers {
+    async fn init(&mut self, info: &mut Info) {
+        for layer in &mut self.layers {
+            if let Layer::Disk { files, .. } = layer {
+                files.init(info).await;
+            }
+        }
+    }
+
+    async fn run(&self, mut conn: Conn) -> Conn {
+        let relative = relative_path(conn.path());
+        let mut dirs = Vec::new();
+        for (i, layer) in self.layers.iter().enumerate() {
+            conn = match (layer, &relative) {
+                (Layer::Disk { files, root }, _) => {
+                    let mut conn = files.run(conn).await;
+                    // Taken, so a later root that serves the path isn't
+                    // mistaken for a directory by `?render`.
+                    if let Some(dir) = conn.take_state::<ResolvedDirectory>() {
+                        dirs.push(Listed::Disk(root.clone(), dir));
+                    }
+                    conn
+                }
+                (Layer::Archive(archive), Some(relative)) if archive.is_file(relative) => {
+                    archive.send(conn, relative).await
+                }
+                (Layer::Archive(archive), Some(relative)) if archive.is_dir(relative) => {
+                    let index = self
+                        .index
+                        .as_deref()
+                        .map(|index| join(relative, index))
+                        .filter(|index| archive.is_file(index));
+                    match index {
+                        Some(index) => archive.send(conn, &index).await,
+                        None => {
+                            dirs.push(Listed::Archive(Arc::clone(archive), relative.clone()));
+                            conn
+                        }
+                    }
+                }
+                (Layer::Archive(_), _) => conn,
+            };
+            // A range the static handler answered has a status but isn't
+            // halted.
+            if conn.is_halted() || conn.status().is_some() {
+                return conn.with_state(Served(i));
+            }
+        }
+
+        if dirs.is_empty() {
+            return conn;
+        }
+        if let [Listed::Disk(_, dir)] = dirs.as_slice() {
+            return conn.with_state(dir.clone());
+        }
+        conn.with_state(VirtualDirectory(Arc::new(Overlay {
+            dirs,
+            gitignore: self.gitignore,
+        })))
+    }
+}
+
+/// A directory some root has, for the listing.
+enum Listed {
+    /// On disk, beneath the root.
+    Disk(PathBuf, ResolvedDirectory),
+    /// In an archive, at this path.
+    Archive(Arc<ArchiveRoot>, String),
+}
+
+/// The same directory in one or more roots, listed as one.
+struct Overlay {
+    dirs: Vec<Listed>,
+    gitignore: bool,
+}
+
+impl ListDirectory for Overlay {
+    fn read_dir(&self, relative: &str) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let mut names = HashSet::new();
+        let mut listed = false;
+        let mut error = None;
+        for dir in &self.dirs {
+            let read = match dir {
+                Listed::Disk(root, dir) => {
+                    read_disk(root, &dir.path().join(relative), self.gitignore)
+                }
+                Listed::Archive(archive, dir) => archive
+                    .read_dir(&join(dir, relative))
+                    .ok_or_else(|| io::ErrorKind::NotFound.into()),
+            };
+            match read {
+                Ok(read) => {
+                    listed = true;
+                    entries.extend(
+                        read.into_iter()
+                            .filter(|entry| names.insert(entry.name.clone())),
+                    );
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(error) if !listed => Err(error),
+            _ => Ok(entries),
+        }
+    }
+}
+
+/// The entries of `dir` on disk, beneath `root`.
+fn read_disk(root: &Path, dir: &Path, gitignore: bool) -> io::Result<Vec<Entry>> {
+    // The `.gitignore`s are read once for the directory, as the listing does.
+    // `dir` comes canonicalized from the static handler, so `root` has to be
+    // too for it to be found beneath it.
+    let ignores = if gitignore {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        ancestor_gitignores(&root, dir)
+    } else {
+        Vec::new()
+    };
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        // Like the listing, don't follow symlinks.
+        let meta = entry.metadata().ok();
+        let is_dir = meta.as_ref().is_some_and(std::fs::Metadata::is_dir);
+        if is_ignored(&ignores, &entry.path(), is_dir) {
+            continue;
+        }
+        entries.push(Entry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir,
+            len: meta
+                .as_ref()
+                .filter(|_| !is_dir)
+                .map(std::fs::Metadata::len),
+            modified: meta.as_ref().and_then(|m| m.modified().ok()),
+        });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,545 @@
+//! Full-text search for `serve --render`, at `/_serve_search?q=`.
+//!
+//! An in-memory inverted index over the text files beneath the root: for each
+//! word, the files containing it and how often. It is built on a background
+//! thread at startup — searches meanwhile see the files indexed so far — and
+//! kept current by the live-reload watcher, which hands every changed path to
+//! [`Index::update`]. Hidden and `.gitignore`d paths are left out, as are
+//! binary files and anything over [`MAX_FILE_SIZE`].
+//!
+//! A query matches files containing every one of its words, ranked by BM25
+//! with a boost for words in the file's path. The index holds only counts, so
+//! the few files on a results page are re-read for their snippets: the first
+//! [`SNIPPETS`] matching lines, each linking to `?render#L<line>`.
+
+use super::render::{RAW_SEGMENT, escape};
+use crate::{
+    assets::{BASE_CSS, SEARCH_CSS, THEME_HEAD, THEME_TOGGLE},
+    directory_listing::is_gitignored,
+};
+use ignore::WalkBuilder;
+use percent_encoding::utf8_percent_encode;
+use querystrong::QueryStrong;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    thread,
+};
+use trillium::{
+    Conn, Handler,
+    KnownHeaderName::{Accept, ContentType},
+};
+use trillium_router::Router;
+
+/// Files bigger than this aren't indexed.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// Results on a page.
+const RESULTS: usize = 30;
+/// Matching lines shown per result.
+const SNIPPETS: usize = 3;
+/// How many occurrences a word in a file's path counts for.
+const PATH_BOOST: u32 = 5;
+/// BM25's term-frequency saturation and length normalization.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// The search index, shared between the watcher that updates it and the
+/// handler that queries it.
+#[derive(Debug, Clone)]
+pub struct Index {
+    root: PathBuf,
+    inner: Arc<RwLock<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Root-relative, `/`-separated path to document id.
+    ids: HashMap<String, u32>,
+    /// By id; `None` once a file is removed, until the id is reused.
+    docs: Vec<Option<Doc>>,
+    free: Vec<u32>,
+    /// Word to the documents containing it and how many times.
+    postings: HashMap<Box<str>, HashMap<u32, u32>>,
+    /// Sum of document lengths, for BM25's average.
+    total_len: u64,
+    /// Whether the startup walk has finished.
+    ready: bool,
+}
+
+#[derive(Debug)]
+struct Doc {
+    path: String,
+    /// Words, counting the path's boost.
+    len: u32,
+    /// Its distinct words, to unindex it by.
+    words: Vec<Box<str>>,
+}
+
+/// One ranked result.
+struct Hit {
+    path: String,
+    score: f64,
+}
+
+impl Index {
+    /// An index of `root`, built on a background thread.
+    pub fn new(root: PathBuf) -> Self {
+        let index = Self {
+            root,
+            inner: Arc::default(),
+        };
+        let builder = index.clone();
+        thread::spawn(move || {
+            let files = builder.index_tree(&builder.root);
+            builder.write().ready = true;
+            log::info!("search: indexed {files} files");
+        });
+        index
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Inner> {
+        self.inner
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Inner> {
+        self.inner
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Index every included file beneath `dir`, returning how many were text.
+    fn index_tree(&self, dir: &Path) -> usize {
+        WalkBuilder::new(dir)
+            .require_git(false)
+            .build()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+            .filter(|entry| self.refresh(entry.path()))
+            .count()
+    }
+
+    /// Re-read and re-index one file, or drop it if it's gone or no longer
+    /// text. Returns whether it is now indexed.
+    fn refresh(&self, path: &Path) -> bool {
+        let Some(relative) = self.relative(path) else {
+            return false;
+        };
+        let counts = read_text(path).map(|text| count_words(&relative, &text));
+        let mut inner = self.write();
+        inner.remove(&relative);
+        match counts {
+            Some(counts) => {
+                inner.insert(relative, counts);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Bring the index up to date with paths the watcher saw change: a file is
+    /// re-read, a new directory walked, and anything gone — or now hidden or
+    /// ignored — dropped along with whatever was beneath it.
+    pub fn update(&self, paths: &[PathBuf]) {
+        for path in paths {
+            let Some(relative) = self.relative(path) else {
+                continue;
+            };
+            if self.is_excluded(path) || !path.exists() {
+                self.write().remove_tree(&relative);
+            } else if path.is_dir() {
+                self.index_tree(path);
+            } else {
+                self.refresh(path);
+            }
+        }
+    }
+
+    /// `path` relative to the root, `/`-separated, if it is beneath it.
+    fn relative(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let segments = relative
+            .components()
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()?;
+        (!segments.is_empty()).then(|| segments.join("/"))
+    }
+
+    /// Whether the startup walk would have skipped `path`: hidden, or ignored
+    /// by a `.gitignore` between the root and it.
+    fn is_excluded(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        if relative
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+        {
+            return true;
+        }
+        is_gitignored(&self.root, path)
+    }
+
+    /// The best [`RESULTS`] files containing every word of `query`, and whether
+    /// the index is complete.
+    fn search(&self, query: &str) -> (Vec<Hit>, bool) {
+        let words = query_words(query);
+        let inner = self.read();
+        if words.is_empty() {
+            return (Vec::new(), inner.ready);
+        }
+        let mut postings = Vec::with_capacity(words.len());
+        for word in &words {
+            match inner.postings.get(word.as_str()) {
+                Some(docs) => postings.push(docs),
+                None => return (Vec::new(), inner.ready),
+            }
+        }
+        // Walk the rarest word's documents, keeping those with all the others.
+        postings.sort_by_key(|docs| docs.len());
+        let count = inner.ids.len() as f64;
+        let average_len = (inner.total_len as f64 / count).max(1.0);
+        let mut hits = postings[0]
+            .keys()
+            .filter(|id| postings[1..].iter().all(|docs| docs.contains_key(id)))
+            .filter_map(|&id| {
+                let doc = inner.docs.get(id as usize)?.as_ref()?;
+                let length = f64::from(doc.len) / average_len;
+                let score = postings
+                    .iter()
+                    .map(|docs| {
+                        let frequency = f64::from(docs[&id]);
+                        let rarity = docs.len() as f64;
+                        let idf = (1.0 + (count - rarity + 0.5) / (rarity + 0.5)).ln();
+                        idf * frequency * (K1 + 1.0) / (frequency + K1 * (1.0 - B + B * length))
+                    })
+                    .sum();
+                Some(Hit {
+                    path: doc.path.clone(),
+                    score,
+                })
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.path.cmp(&b.path)));
+        hits.truncate(RESULTS);
+        (hits, inner.ready)
+    }
+}
+
+impl Inner {
+    fn insert(&mut self, path: String, counts: HashMap<String, u32>) {
+        let id = match self.free.pop() {
+            Some(id) => id,
+            None => {
+                self.docs.push(None);
+                (self.docs.len() - 1) as u32
+            }
+        };
+        let mut len = 0;
+        let mut words = Vec::with_capacity(counts.len());
+        for (word, count) in counts {
+            len += count;
+            let word = Box::<str>::from(word);
+            self.postings
+                .entry(word.clone())
+                .or_default()
+                .insert(id, count);
+            words.push(word);
+        }
+        self.total_len += u64::from(len);
+        self.ids.insert(path.clone(), id);
+        self.docs[id as usize] = Some(Doc { path, len, words });
+    }
+
+    fn remove(&mut self, path: &str) {
+        let Some(id) = self.ids.remove(path) else {
+            return;
+        };
+        let Some(doc) = self.docs[id as usize].take() else {
+            return;
+        };
+        for word in doc.words {
+            if let Some(docs) = self.postings.get_mut(&word) {
+                docs.remove(&id);
+                if docs.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+        self.total_len -= u64::from(doc.len);
+        self.free.push(id);
+    }
+
+    /// Remove `path` and, if it was a directory, everything beneath it.
+    fn remove_tree(&mut self, path: &str) {
+        let prefix = format!("{path}/");
+        let beneath = self
+            .ids
+            .keys()
+            .filter(|indexed| *indexed == path || indexed.starts_with(&prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        for indexed in beneath {
+            self.remove(&indexed);
+        }
+    }
+}
+
+/// A file's contents, if it is small enough and text: no NUL bytes up front,
+/// and valid UTF-8.
+fn read_text(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() > MAX_FILE_SIZE {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    if bytes.iter().take(8192).any(|&byte| byte == 0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The words of `text`, with their byte offsets: runs of letters and digits,
+/// two to 64 characters long. Case is left to the caller.
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| (2..=64).contains(&word.chars().count()))
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+/// How many times each (lower-cased) word appears in a file, counting words in
+/// its path [`PATH_BOOST`] times.
+fn count_words(path: &str, text: &str) -> HashMap<String, u32> {
+    let mut counts = HashMap::<String, u32>::new();
+    for (_, word) in words(text) {
+        *counts.entry(word.to_lowercase()).or_default() += 1;
+    }
+    for (_, word) in words(path) {
+        *counts.entry(word.to_lowercase()).or_default() += PATH_BOOST;
+    }
+    counts
+}
+
+/// A query's distinct words, lower-cased.
+fn query_words(query: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    words(query)
+        .map(|(_, word)| word.to_lowercase())
+        .filter(|word| seen.insert(word.clone()))
+        .collect()
+}
+
+/// The link to a result, percent-encoded, optionally to a line.
+fn href(path: &str, line: Option<usize>) -> String {
+    let path = path
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, RAW_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    match line {
+        Some(line) => format!("/{path}?render#L{line}"),
+        None => format!("/{path}?render"),
+    }
+}
+
+/// A matching line: its 1-based number and its text (trimmed to a window
+/// around the first match), as HTML with each matched word in a `<mark>`.
+struct Snippet {
+    line: usize,
+    text: String,
+    html: String,
+}
+
+/// The first [`SNIPPETS`] lines of `path` containing any of `words`.
+fn snippets(root: &Path, path: &str, words: &HashSet<String>) -> Vec<Snippet> {
+    let Some(text) = read_text(&root.join(path)) else {
+        return Vec::new();
+    };
+    text.lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let first = self::words(line)
+                .find(|(_, word)| words.contains(&word.to_lowercase()))?
+                .0;
+            // About 60 characters of lead-in and 200 in all.
+            let mut start = first.saturating_sub(60);
+            while !line.is_char_boundary(start) {
+                start -= 1;
+            }
+            let mut end = (start + 200).min(line.len());
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            let window = &line[start..end];
+
+            let mut html = String::new();
+            if start > 0 {
+                html.push('\u{2026}');
+            }
+            let mut written = 0;
+            for (offset, word) in self::words(window) {
+                if words.contains(&word.to_lowercase()) {
+                    let _ = write!(
+                        html,
+                        "{}<mark>{}</mark>",
+                        escape(&window[written..offset]),
+                        escape(word)
+                    );
+                    written = offset + word.len();
+                }
+            }
+            html.push_str(&escape(&window[written..]));
+            if end < line.len() {
+                html.push('\u{2026}');
+            }
+            Some(Snippet {
+                line: index + 1,
+                text: window.trim().to_string(),
+                html,
+            })
+        })
+        .take(SNIPPETS)
+        .collect()
+}
+
+/// The `/_serve_search` route: results as an HTML page, or JSON with
+/// `?format=json` or `Accept: application/json`.
+pub fn handler(index: Index) -> impl Handler {
+    Router::new().get("/_serve_search", move |conn: Conn| {
+        let index = index.clone();
+        async move {
+            let qs = QueryStrong::parse(conn.querystring());
+            let query = qs.get_str("q").unwrap_or_default().trim().to_string();
+            let json = qs.get_str("format") == Some("json")
+                || conn
+                    .request_headers()
+                    .get_str(Accept)
+                    .is_some_and(|accept| {
+                        accept.contains("application/json") && !accept.contains("text/html")
+                    });
+
+            let search_query = query.clone();
+            let (results, ready) = blocking::unblock(move || {
+                let (hits, ready) = index.search(&search_query);
+                let words = query_words(&search_query)
+                    .into_iter()
+                    .collect::<HashSet<_>>();
+                let results = hits
+                    .into_iter()
+                    .map(|hit| {
+                        let snippets = snippets(&index.root, &hit.path, &words);
+                        (hit, snippets)
+                    })
+                    .collect::<Vec<_>>();
+                (results, ready)
+            })
+            .await;
+
+            if json {
+                let results = results
+                    .iter()
+                    .map(|(hit, snippets)| {
+                        json!({
+                            "path": hit.path,
+                            "href": href(&hit.path, None),
+                            "score": hit.score,
+                            "matches": snippets
+                                .iter()
+                                .map(|snippet| json!({
+                                    "line": snippet.line,
+                                    "text": snippet.text,
+                                    "href": href(&hit.path, Some(snippet.line)),
+                                }))
+                                .collect::<Vec<_>>(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let body = json!({ "query": query, "complete": ready, "results": results });
+                return conn
+                    .with_response_header(ContentType, "application/json")
+                    .ok(body.to_string());
+            }
+
+            conn.with_response_header(ContentType, "text/html; charset=utf-8")
+                .ok(render(&query, &results, ready))
+        }
+    })
+}
+
+/// The results page.
+fn render(query: &str, results: &[(Hit, Vec<Snippet>)], ready: bool) -> String {
+    let mut list = String::new();
+    for (hit, snippets) in results {
+        let _ = write!(
+            list,
+            "<li><a class=\"path\" href=\"{}\">{}</a>",
+            href(&hit.path, None),
+            escape(&hit.path)
+        );
+        if !snippets.is_empty() {
+            list.push_str("<ol class=\"snippets\">");
+            for snippet in snippets {
+                let _ = write!(
+                    list,
+                    "<li><a href=\"{}\"><span class=\"line\">{}</span><code>{}</code></a></li>",
+                    href(&hit.path, Some(snippet.line)),
+                    snippet.line,
+                    snippet.html
+                );
+            }
+            list.push_str("</ol>");
+        }
+        list.push_str("</li>\n");
+    }
+
+    let summary = match (query.is_empty(), results.len()) {
+        (true, _) => String::new(),
+        (false, 0) => "<p class=\"summary\">no files match</p>\n".into(),
+        (false, 1) => "<p class=\"summary\">1 file</p>\n".into(),
+        (false, count) if count == RESULTS => {
+            format!("<p class=\"summary\">the best {count} files</p>\n")
+        }
+        (false, count) => format!("<p class=\"summary\">{count} files</p>\n"),
+    };
+    let indexing = if ready {
+        ""
+    } else {
+        "<p class=\"summary\">still indexing \u{2014} results may be incomplete</p>\n"
+    };
+    let title = if query.is_empty() {
+        "Search".to_string()
+    } else {
+        format!("Search: {}", escape(query))
+    };
+
+    format!(
+        "<!DOCTYPE html>\n\
+<html lang=\"en\">\n\
+<head>\n\
+<meta charset=\"utf-8\">\n\
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+<title>{title}</title>\n\
+{THEME_HEAD}\n\
+<link rel=\"stylesheet\" href=\"{BASE_CSS}\">\n\
+<link rel=\"stylesheet\" href=\"{SEARCH_CSS}\">\n\
+</head>\n\
+<body class=\"search\">\n\
+{THEME_TOGGLE}\n\
+<main>\n\
+<h1>{title}</h1>\n\
+<form class=\"search-page\" method=\"get\" action=\"/_serve_search\" role=\"search\"><input \
+         type=\"search\" name=\"q\" value=\"{}\" placeholder=\"search files\" aria-label=\"search \
+         files\" autofocus></form>\n\
+{indexing}{summary}<ol class=\"results\">\n{list}</ol>\n\
+<footer>served by <a href=\"https://trillium.rs\">trillium</a></footer>\n\
+</main>\n\
+</body>\n\
+</html>\n",
+        escape(query)
+    )
+}
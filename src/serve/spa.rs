@@ -0,0 +1,74 @@
+//! `--spa`: a fallback document for single-page apps.
+//!
+//! A built React/Vue/Svelte app routes on the client, so a browser that
+//! navigates straight to `/settings/profile` asks the server for a path that
+//! exists only in the app's router. [`Spa`] sits after the file handler (and
+//! the directory listing) and answers such a miss with the fallback document
+//! and a 200, letting the app boot and route.
+//!
+//! Only navigations fall back: a `GET`/`HEAD` whose `Accept` includes
+//! `text/html`, for a path whose last segment has no extension. A missing
+//! `/app.js` or `/logo.png` — or a `fetch` for JSON — still 404s, so a broken
+//! asset reference fails loudly instead of parsing `index.html` as JavaScript.
+//!
+//! The fallback is served through the normal file path, so it gets its etag,
+//! content-type, compression and (under `--render`) the live-reload script like
+//! any other HTML file. With several roots, it comes from the first that has
+//! it.
+
+use super::roots::Root;
+use trillium::{Conn, Handler, KnownHeaderName::Accept, Method};
+use trillium_static::StaticConnExt;
+
+/// Serves `fallback` for navigation requests nothing else handled.
+#[derive(Debug, Clone)]
+pub struct Spa {
+    root: Root,
+    fallback: String,
+}
+
+impl Spa {
+    /// `fallback` is relative to the served roots.
+    pub fn new(roots: &[Root], fallback: &str) -> Self {
+        let fallback = fallback.trim_start_matches('/').to_string();
+        let root = roots.iter().find(|root| match root {
+            Root::Disk(root) => root.join(&fallback).is_file(),
+            Root::Archive(archive) => archive.is_file(&fallback),
+        });
+        let root = root.unwrap_or_else(|| {
+            log::warn!("spa fallback {fallback} does not exist");
+            &roots[0]
+        });
+        Self {
+            root: root.clone(),
+            fallback,
+        }
+    }
+}
+
+/// Whether this looks like a browser navigating to an app route, as opposed to
+/// a request for an asset or data.
+fn is_navigation(conn: &Conn) -> bool {
+    matches!(conn.method(), Method::Get | Method::Head)
+        && conn
+            .request_headers()
+            .get_str(Accept)
+            .is_some_and(|accept| accept.contains("text/html"))
+        && conn
+            .path()
+            .rsplit('/')
+            .next()
+            .is_none_or(|segment| !segment.contains('.'))
+}
+
+impl Handler for Spa {
+    async fn run(&self, conn: Conn) -> Conn {
+        if !is_navigation(&conn) {
+            return conn;
+        }
+        match &self.root {
+            Root::Disk(root) => conn.send_path(root.join(&self.fallback)).await,
+            Root::Archive(archive) => archive.send(conn, &self.fallback).await,
+        }
+    }
+}
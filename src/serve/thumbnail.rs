@@ -0,0 +1,179 @@
+//! `?thumbnail`: small JPEG previews of images, for the listing's grid view.
+//!
+//! A `GET /photos/cat.jpg?thumbnail` answers with the image scaled to fit
+//! [`SIZE`]×[`SIZE`], turned upright per its EXIF orientation. Decoding and
+//! scaling a photo is slow, so each thumbnail is written once to a directory
+//! under the user's cache dir that only they can read, keyed by the image's
+//! path, length and mtime — an edited image gets a fresh thumbnail, and a
+//! restart keeps the old ones. An image that can't be decoded falls through to
+//! the file handler, so the browser gets the original instead.
+
+use blocking::unblock;
+use image::{DynamicImage, ImageDecoder, ImageReader, codecs::jpeg::JpegEncoder};
+use percent_encoding::percent_decode_str;
+use sha1::{Digest, Sha1};
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::UNIX_EPOCH,
+};
+use trillium::{Conn, Handler, Method};
+use trillium_static::StaticConnExt;
+
+/// The longest side of a thumbnail, in pixels. Twice the grid's cell width, so
+/// thumbnails stay sharp on high-density screens.
+const SIZE: u32 = 320;
+
+/// Whether a file with this (lower-cased) extension gets a generated thumbnail:
+/// the raster formats the `image` crate is built to decode.
+pub fn has_thumbnail(ext: &str) -> bool {
+    matches!(ext, "jpg" | "jpeg" | "png" | "gif" | "webp")
+}
+
+/// Serves `?thumbnail` requests for images beneath the root from the cache,
+/// generating them on a miss; passes everything else through.
+#[derive(Debug, Clone)]
+pub struct Thumbnails {
+    root: PathBuf,
+    /// `None` without a cache dir to keep thumbnails in, when images are
+    /// served as they are.
+    cache: Option<PathBuf>,
+}
+
+impl Thumbnails {
+    pub fn new(root: PathBuf) -> Self {
+        let root = root.canonicalize().unwrap_or(root);
+        let cache = dirs::cache_dir().map(|dir| dir.join("trillium").join("thumbnails"));
+        if cache.is_none() {
+            log::warn!("no cache directory to keep thumbnails in; serving full images");
+        }
+        Self { root, cache }
+    }
+
+    /// The image a request path names, if it is a file beneath the root once
+    /// symlinks are followed, with an extension we can thumbnail.
+    fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode_str(url_path).decode_utf8().ok()?;
+        let mut path = self.root.clone();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                segment => path.push(segment),
+            }
+        }
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        let path = path.canonicalize().ok()?;
+        (has_thumbnail(&ext) && path.starts_with(&self.root) && path.is_file()).then_some(path)
+    }
+
+    /// Where `image`'s thumbnail is cached: a digest of its path, length and
+    /// mtime, so a changed file misses.
+    fn cache_path(cache: &Path, image: &Path) -> io::Result<PathBuf> {
+        let metadata = fs::metadata(image)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let digest = Sha1::new()
+            .chain_update(image.as_os_str().as_encoded_bytes())
+            .chain_update(metadata.len().to_le_bytes())
+            .chain_update(modified.as_nanos().to_le_bytes())
+            .chain_update(SIZE.to_le_bytes())
+            .finalize();
+        let mut name = String::with_capacity(44);
+        for byte in digest {
+            let _ = write!(name, "{byte:02x}");
+        }
+        name.push_str(".jpg");
+        Ok(cache.join(name))
+    }
+}
+
+impl Handler for Thumbnails {
+    async fn run(&self, conn: Conn) -> Conn {
+        if conn.method() != Method::Get
+            || !conn
+                .querystring()
+                .split('&')
+                .any(|pair| pair.split('=').next() == Some("thumbnail"))
+        {
+            return conn;
+        }
+        let (Some(image), Some(cache)) = (self.resolve(conn.path()), self.cache.clone()) else {
+            return conn;
+        };
+
+        let source = image.clone();
+        let cached = unblock(move || {
+            let cached = Self::cache_path(&cache, &source)?;
+            if !cached.is_file() {
+                create_private_dir(&cache)?;
+                generate(&source, &cached)?;
+            }
+            io::Result::Ok(cached)
+        })
+        .await;
+
+        match cached {
+            Ok(cached) => conn.send_path(cached).await.halt(),
+            Err(error) => {
+                log::info!("no thumbnail for {}: {error}", image.display());
+                conn
+            }
+        }
+    }
+}
+
+/// Create `dir`, and any missing parents, readable only by its owner.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Distinguishes concurrent generations' temporary files.
+static GENERATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Decode `image`, scale it down to fit [`SIZE`], right its orientation, and
+/// write it to `cached` as a JPEG — via a temporary file renamed into place, so
+/// a concurrent request never serves a half-written thumbnail.
+fn generate(image: &Path, cached: &Path) -> io::Result<()> {
+    let mut decoder = ImageReader::open(image)?
+        .with_guessed_format()?
+        .into_decoder()
+        .map_err(io::Error::other)?;
+    let orientation = decoder.orientation().ok();
+    let mut thumbnail = DynamicImage::from_decoder(decoder)
+        .map_err(io::Error::other)?
+        .thumbnail(SIZE, SIZE);
+    if let Some(orientation) = orientation {
+        thumbnail.apply_orientation(orientation);
+    }
+    // JPEG has no alpha channel; flatten transparent PNGs and GIFs.
+    let thumbnail = DynamicImage::ImageRgb8(thumbnail.to_rgb8());
+
+    let partial = cached.with_extension(format!(
+        "{}-{}.partial",
+        std::process::id(),
+        GENERATIONS.fetch_add(1, Ordering::Relaxed)
+    ));
+    // A file already there isn't ours to write through.
+    let result = File::create_new(&partial).and_then(|file| {
+        thumbnail
+            .write_with_encoder(JpegEncoder::new_with_quality(BufWriter::new(file), 80))
+            .map_err(io::Error::other)
+    });
+    match result.and_then(|()| fs::rename(&partial, cached)) {
+        Ok(()) => Ok(()),
+        Err(error) => {
+            let _ = fs::remove_file(&partial);
+            Err(error)
+        }
+    }
+}
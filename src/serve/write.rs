@@ -0,0 +1,685 @@
+//! Write access for `serve`: uploads, deletes, and a WebDAV subset.
+//!
+//! All of it is off unless asked for. `--allow-upload` accepts a `PUT` of a
+//! file path and a `multipart/form-data` `POST` to a directory (what the
+//! directory listing's upload form sends); `--allow-delete` accepts `DELETE`;
+//! `--webdav` answers `OPTIONS` and `PROPFIND` so an OS file manager can mount
+//! the root, and `MKCOL`/`MOVE` when the matching write flags are on too.
+//!
+//! Every path is resolved beneath the served root: a `..` segment is refused
+//! outright, and the parent directory is canonicalized and checked against the
+//! root so a symlink can't carry a write outside it. Uploads stream to a
+//! temporary file beside the target and are renamed into place once complete,
+//! so a reader never sees a half-written file and a failed upload leaves the
+//! old one intact. Bodies over `--max-upload-size` are rejected with 413.
+
+use blocking::{Unblock, unblock};
+use clap::Parser;
+use futures_lite::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, io};
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use trillium::{
+    Conn, Handler,
+    KnownHeaderName::{Allow, ContentType, Location},
+    Method, Status,
+};
+
+/// The `--allow-upload`/`--allow-delete`/`--webdav` flags, flattened into
+/// `serve`'s args.
+#[derive(Parser, Debug, Clone, Copy)]
+pub struct WriteAccess {
+    /// accept uploads: `PUT` to a file path, or the listing's upload form
+    ///
+    /// Uploads stream to a temporary file and are renamed into place when
+    /// complete. Writes are confined to the served root.
+    #[arg(long, env, help_heading = "Writes")]
+    allow_upload: bool,
+
+    /// accept `DELETE` of files and directories (directories recursively)
+    #[arg(long, env, help_heading = "Writes")]
+    allow_delete: bool,
+
+    /// answer WebDAV `PROPFIND` and `OPTIONS` so file managers can mount the
+    /// root
+    ///
+    /// `MKCOL` (create a directory) additionally needs --allow-upload, and
+    /// `MOVE` needs both --allow-upload and --allow-delete.
+    #[arg(long, env, help_heading = "Writes")]
+    webdav: bool,
+
+    /// largest accepted upload, e.g. 100MiB or 2GB
+    #[arg(
+        long,
+        env,
+        value_name = "SIZE",
+        default_value = "1GiB",
+        value_parser = parse_size,
+        help_heading = "Writes"
+    )]
+    max_upload_size: u64,
+}
+
+impl WriteAccess {
+    /// Whether the directory listing should offer an upload form.
+    pub fn allows_upload(self) -> bool {
+        self.allow_upload
+    }
+
+    /// Whether any write flag is on.
+    pub fn is_enabled(self) -> bool {
+        self.allow_upload || self.allow_delete || self.webdav
+    }
+
+    /// The write handler for `root`, or `None` when every write flag is off.
+    pub fn handler(self, root: PathBuf) -> Option<Writes> {
+        self.is_enabled().then_some(Writes { root, access: self })
+    }
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let size = size::Size::from_str(s).map_err(|e| format!("invalid size {s:?}: {e}"))?;
+    u64::try_from(size.bytes()).map_err(|_| format!("invalid size {s:?}"))
+}
+
+/// Handles the write (and WebDAV) methods the flags enable; every other
+/// request passes through to the file handler untouched. Goes ahead of
+/// `--forward`, so writes land locally rather than going upstream.
+#[derive(Debug, Clone)]
+pub struct Writes {
+    root: PathBuf,
+    access: WriteAccess,
+}
+
+impl Handler for Writes {
+    async fn run(&self, conn: Conn) -> Conn {
+        let WriteAccess {
+            allow_upload,
+            allow_delete,
+            webdav,
+            ..
+        } = self.access;
+        let conn = match conn.method() {
+            Method::Put if allow_upload => self.put(conn).await,
+            Method::Post if allow_upload && is_multipart(&conn) => self.post(conn).await,
+            Method::Delete if allow_delete => self.delete(conn).await,
+            Method::Options if webdav => self.options(conn),
+            Method::PropFind if webdav => self.propfind(conn).await,
+            Method::MkCol if webdav && allow_upload => self.mkcol(conn).await,
+            Method::Move if webdav && allow_upload && allow_delete => self.r#move(conn).await,
+            _ => return conn,
+        };
+        conn.halt()
+    }
+}
+
+impl Writes {
+    /// Resolve a request path beneath the root, refusing `..`, backslashes and
+    /// NULs, and any path whose parent (once symlinks are followed) lies
+    /// outside the root.
+    fn resolve(&self, url_path: &str) -> Result<PathBuf, Status> {
+        let decoded = percent_decode_str(url_path)
+            .decode_utf8()
+            .map_err(|_| Status::BadRequest)?;
+        let mut path = self.root.clone();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return Err(Status::Forbidden),
+                segment if segment.contains(['\\', '\0']) => return Err(Status::Forbidden),
+                segment => path.push(segment),
+            }
+        }
+        if path != self.root {
+            let parent = path.parent().ok_or(Status::Forbidden)?;
+            match parent.canonicalize() {
+                Ok(parent) if parent.starts_with(&self.root) => {}
+                Ok(_) => return Err(Status::Forbidden),
+                Err(_) => return Err(Status::Conflict),
+            }
+        }
+        Ok(path)
+    }
+
+    fn max_len(&self) -> u64 {
+        self.access.max_upload_size
+    }
+
+    /// `PUT /path/file`: stream the body into place. 201 for a new file, 204
+    /// for a replaced one.
+    async fn put(&self, mut conn: Conn) -> Conn {
+        let target = match self.resolve(conn.path()) {
+            Ok(target) if target != self.root && !conn.path().ends_with('/') => target,
+            Ok(_) => return conn.with_status(Status::MethodNotAllowed),
+            Err(status) => return conn.with_status(status),
+        };
+        if target.is_dir() {
+            return conn.with_status(Status::MethodNotAllowed);
+        }
+        if conn
+            .request_body()
+            .content_length()
+            .is_some_and(|len| len > self.max_len())
+        {
+            return conn.with_status(Status::PayloadTooLarge);
+        }
+
+        let max_len = self.max_len();
+        let result = async {
+            let mut upload = Upload::create(&target).await?;
+            let written =
+                io::copy(conn.request_body().with_max_len(max_len), &mut upload.file).await;
+            upload.finish(written).await
+        }
+        .await;
+        match result {
+            Ok(true) => conn.with_status(Status::NoContent),
+            Ok(false) => conn.with_status(Status::Created),
+            Err(error) => error_status(conn, &target, error),
+        }
+    }
+
+    /// `POST /dir/` with `multipart/form-data`: save every file part into the
+    /// directory under its own (final path component of its) file name, then
+    /// send the browser back to the listing.
+    async fn post(&self, mut conn: Conn) -> Conn {
+        let dir = match self.resolve(conn.path()) {
+            Ok(dir) if dir.is_dir() => dir,
+            Ok(_) => return conn.with_status(Status::NotFound),
+            Err(status) => return conn.with_status(status),
+        };
+        let Some(boundary) = conn
+            .request_headers()
+            .get_str(ContentType)
+            .and_then(boundary)
+        else {
+            return conn.with_status(Status::BadRequest);
+        };
+        if conn
+            .request_body()
+            .content_length()
+            .is_some_and(|len| len > self.max_len())
+        {
+            return conn.with_status(Status::PayloadTooLarge);
+        }
+
+        let max_len = self.max_len();
+        let mut multipart = Multipart::new(conn.request_body().with_max_len(max_len), &boundary);
+        let result = async {
+            multipart.skip_preamble().await?;
+            let mut saved = 0;
+            while let Some(file_name) = multipart.next_part().await? {
+                match file_name.as_deref().and_then(safe_file_name) {
+                    Some(name) => {
+                        let mut upload = Upload::create(&dir.join(name)).await?;
+                        let written = multipart.copy_part(&mut upload.file).await;
+                        upload.finish(written).await?;
+                        saved += 1;
+                    }
+                    None => {
+                        multipart.copy_part(&mut io::sink()).await?;
+                    }
+                }
+            }
+            io::Result::Ok(saved)
+        }
+        .await;
+
+        match result {
+            Ok(saved) => {
+                log::info!("saved {saved} uploaded file(s) into {}", dir.display());
+                let location = conn.path().to_string();
+                conn.with_response_header(Location, location)
+                    .with_status(Status::SeeOther)
+            }
+            Err(error) => error_status(conn, &dir, error),
+        }
+    }
+
+    /// `DELETE`: remove a file, or a directory and everything in it. A symlink
+    /// is removed itself, never followed. The root can't be deleted.
+    async fn delete(&self, conn: Conn) -> Conn {
+        let target = match self.resolve(conn.path()) {
+            Ok(target) if target == self.root => return conn.with_status(Status::Forbidden),
+            Ok(target) => target,
+            Err(status) => return conn.with_status(status),
+        };
+        let removal = target.clone();
+        let result = unblock(move || {
+            let meta = fs::symlink_metadata(&removal)?;
+            if meta.is_dir() {
+                fs::remove_dir_all(&removal)
+            } else {
+                fs::remove_file(&removal)
+            }
+        })
+        .await;
+        match result {
+            Ok(()) => conn.with_status(Status::NoContent),
+            Err(error) => error_status(conn, &target, error),
+        }
+    }
+
+    /// `MKCOL /dir/`: create one directory; its parent must exist.
+    async fn mkcol(&self, conn: Conn) -> Conn {
+        let target = match self.resolve(conn.path()) {
+            Ok(target) => target,
+            Err(status) => return conn.with_status(status),
+        };
+        if target.exists() {
+            return conn.with_status(Status::MethodNotAllowed);
+        }
+        let creation = target.clone();
+        match unblock(move || fs::create_dir(creation)).await {
+            Ok(()) => conn.with_status(Status::Created),
+            Err(error) => error_status(conn, &target, error),
+        }
+    }
+
+    /// `MOVE` with a `Destination` header, honoring `Overwrite: F`.
+    async fn r#move(&self, conn: Conn) -> Conn {
+        let source = match self.resolve(conn.path()) {
+            Ok(source) if source == self.root => return conn.with_status(Status::Forbidden),
+            Ok(source) => source,
+            Err(status) => return conn.with_status(status),
+        };
+        let Some(destination) = conn
+            .request_headers()
+            .get_str("Destination")
+            .map(destination_path)
+        else {
+            return conn.with_status(Status::BadRequest);
+        };
+        let destination = match self.resolve(&destination) {
+            Ok(destination) if destination == self.root => {
+                return conn.with_status(Status::Forbidden);
+            }
+            Ok(destination) => destination,
+            Err(status) => return conn.with_status(status),
+        };
+        if destination.starts_with(&source) {
+            return conn.with_status(Status::Forbidden);
+        }
+        let overwrite = conn
+            .request_headers()
+            .get_str("Overwrite")
+            .is_none_or(|overwrite| !overwrite.eq_ignore_ascii_case("f"));
+
+        let (from, to) = (source.clone(), destination.clone());
+        let result = unblock(move || {
+            fs::symlink_metadata(&from)?;
+            let existed = fs::symlink_metadata(&to).is_ok();
+            if existed && !overwrite {
+                return Ok(None);
+            }
+            if existed && fs::symlink_metadata(&to)?.is_dir() {
+                fs::remove_dir_all(&to)?;
+            }
+            fs::rename(&from, &to)?;
+            Ok(Some(existed))
+        })
+        .await;
+        match result {
+            Ok(Some(true)) => conn.with_status(Status::NoContent),
+            Ok(Some(false)) => conn.with_status(Status::Created),
+            Ok(None) => conn.with_status(Status::PreconditionFailed),
+            Err(error) => error_status(conn, &source, error),
+        }
+    }
+
+    /// `OPTIONS`: advertise DAV class 1 and the methods enabled.
+    fn options(&self, conn: Conn) -> Conn {
+        let WriteAccess {
+            allow_upload,
+            allow_delete,
+            ..
+        } = self.access;
+        let mut allow = vec!["OPTIONS", "GET", "HEAD", "PROPFIND"];
+        if allow_upload {
+            allow.extend(["PUT", "POST", "MKCOL"]);
+        }
+        if allow_delete {
+            allow.push("DELETE");
+        }
+        if allow_upload && allow_delete {
+            allow.push("MOVE");
+        }
+        conn.with_response_header(Allow, allow.join(", "))
+            .with_response_header("DAV", "1")
+            .with_response_header("MS-Author-Via", "DAV")
+            .with_status(Status::Ok)
+    }
+
+    /// `PROPFIND`: a `207 Multi-Status` describing the resource and, unless
+    /// `Depth: 0`, its children. `Depth: infinity` is answered as depth 1.
+    async fn propfind(&self, conn: Conn) -> Conn {
+        let target = match self.resolve(conn.path()) {
+            Ok(target) => target,
+            Err(status) => return conn.with_status(status),
+        };
+        let depth_zero = conn.request_headers().get_str("Depth") == Some("0");
+        let href = conn.path().to_string();
+
+        let result = unblock(move || {
+            let meta = fs::metadata(&target)?;
+            let mut resources = vec![(href.clone(), meta.clone())];
+            if meta.is_dir() && !depth_zero {
+                let base = if href.ends_with('/') {
+                    href
+                } else {
+                    format!("{href}/")
+                };
+                for entry in fs::read_dir(&target)? {
+                    let entry = entry?;
+                    let Ok(meta) = entry.metadata() else { continue };
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    let slash = if meta.is_dir() { "/" } else { "" };
+                    let href = format!("{base}{}{slash}", utf8_percent_encode(&name, SEGMENT));
+                    resources.push((href, meta));
+                }
+            }
+            io::Result::Ok(multistatus(&resources))
+        })
+        .await;
+
+        match result {
+            Ok(body) => conn
+                .with_response_header(ContentType, "application/xml; charset=utf-8")
+                .with_status(Status::MultiStatus)
+                .with_body(body),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                conn.with_status(Status::NotFound)
+            }
+            Err(error) => {
+                log::warn!("PROPFIND {}: {error}", conn.path());
+                conn.with_status(Status::InternalServerError)
+            }
+        }
+    }
+}
+
+/// Map a write failure to a response, logging anything unexpected.
+fn error_status(conn: Conn, path: &Path, error: io::Error) -> Conn {
+    let status = match error.kind() {
+        io::ErrorKind::NotFound => Status::NotFound,
+        io::ErrorKind::PermissionDenied => Status::Forbidden,
+        io::ErrorKind::Unsupported => Status::PayloadTooLarge,
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Status::BadRequest,
+        _ => {
+            log::warn!("write to {} failed: {error}", path.display());
+            Status::InternalServerError
+        }
+    };
+    conn.with_status(status)
+}
+
+/// Distinguishes concurrent uploads' temporary files.
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// An upload in progress: a temporary file beside `target`, renamed over it
+/// by [`Upload::finish`] only if everything was written.
+struct Upload {
+    file: Unblock<fs::File>,
+    temp: PathBuf,
+    target: PathBuf,
+}
+
+impl Upload {
+    async fn create(target: &Path) -> io::Result<Self> {
+        let name = target
+            .file_name()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?
+            .to_string_lossy();
+        let temp = target.with_file_name(format!(
+            ".{name}.{}-{}.upload",
+            std::process::id(),
+            UPLOADS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = {
+            let temp = temp.clone();
+            unblock(move || fs::File::options().write(true).create_new(true).open(temp)).await?
+        };
+        Ok(Self {
+            file: Unblock::new(file),
+            temp,
+            target: target.to_path_buf(),
+        })
+    }
+
+    /// Commit the upload if `written` succeeded, or remove the temporary file
+    /// if it (or the commit) failed. Returns whether `target` already existed.
+    async fn finish(mut self, written: io::Result<u64>) -> io::Result<bool> {
+        let written = match written {
+            Ok(_) => self.file.flush().await,
+            Err(error) => Err(error),
+        };
+        let Self { file, temp, target } = self;
+        drop(file);
+        unblock(move || {
+            let result = written.and_then(|()| {
+                let existed = target.exists();
+                fs::rename(&temp, &target)?;
+                Ok(existed)
+            });
+            if result.is_err() {
+                let _ = fs::remove_file(&temp);
+            }
+            result
+        })
+        .await
+    }
+}
+
+/// The final component of an uploaded file's name — browsers send bare names,
+/// but some old ones send the client's full path — or `None` if nothing
+/// usable is left.
+fn safe_file_name(name: &str) -> Option<&str> {
+    let name = name.rsplit(['/', '\\']).next()?;
+    (!matches!(name, "" | "." | "..") && !name.contains('\0')).then_some(name)
+}
+
+fn is_multipart(conn: &Conn) -> bool {
+    conn.request_headers()
+        .get_str(ContentType)
+        .is_some_and(|content_type| content_type.starts_with("multipart/form-data"))
+}
+
+/// The `boundary` parameter of a `multipart/form-data` content type.
+fn boundary(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.trim().split_once('=')?;
+        key.eq_ignore_ascii_case("boundary")
+            .then(|| value.trim_matches('"').to_string())
+            .filter(|boundary| !boundary.is_empty())
+    })
+}
+
+/// The path of a `Destination` header, which may be an absolute URL.
+fn destination_path(destination: &str) -> String {
+    match destination.split_once("://") {
+        Some((_, rest)) => rest
+            .find('/')
+            .map_or("/", |slash| &rest[slash..])
+            .to_string(),
+        None => destination.to_string(),
+    }
+}
+
+/// Characters to percent-encode in a `PROPFIND` href segment.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'\'')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`');
+
+/// The `207 Multi-Status` body for `(href, metadata)` pairs.
+fn multistatus(resources: &[(String, fs::Metadata)]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    for (href, meta) in resources {
+        let name = href
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let name = percent_decode_str(name).decode_utf8_lossy();
+        let _ = write!(
+            xml,
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>",
+            escape(href),
+            escape(&name),
+        );
+        if meta.is_dir() {
+            xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            let _ = write!(
+                xml,
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>",
+                meta.len()
+            );
+        }
+        if let Ok(modified) = meta.modified() {
+            let _ = write!(
+                xml,
+                "<D:getlastmodified>{}</D:getlastmodified>",
+                httpdate::fmt_http_date(modified)
+            );
+        }
+        xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// How much of the body to read at a time while looking for boundaries.
+const CHUNK: usize = 64 * 1024;
+
+/// The longest part header block accepted.
+const MAX_PART_HEADERS: usize = 16 * 1024;
+
+/// A streaming `multipart/form-data` reader: parts are copied out as they
+/// arrive, holding back only enough bytes to recognize a delimiter split
+/// across reads.
+struct Multipart<R> {
+    reader: R,
+    /// `\r\n--boundary`. The leading CRLF belongs to the delimiter, not the
+    /// part before it.
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> Multipart<R> {
+    fn new(reader: R, boundary: &str) -> Self {
+        Self {
+            reader,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            // Lets the first delimiter, at the very start of the body, match
+            // like the rest.
+            buffer: b"\r\n".to_vec(),
+        }
+    }
+
+    /// Read more of the body into the buffer; `false` at the end of it.
+    async fn fill(&mut self) -> io::Result<bool> {
+        let start = self.buffer.len();
+        self.buffer.resize(start + CHUNK, 0);
+        let read = self.reader.read(&mut self.buffer[start..]).await;
+        self.buffer.truncate(start + *read.as_ref().unwrap_or(&0));
+        read.map(|read| read > 0)
+    }
+
+    async fn fill_or_eof(&mut self) -> io::Result<()> {
+        if self.fill().await? {
+            Ok(())
+        } else {
+            Err(io::ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    /// Discard everything up to and including the first delimiter.
+    async fn skip_preamble(&mut self) -> io::Result<()> {
+        self.copy_part(&mut io::sink()).await.map(drop)
+    }
+
+    /// Having just passed a delimiter, either start the next part — reading its
+    /// headers and returning its file name, if it has one — or recognize the
+    /// closing delimiter and return `None`.
+    async fn next_part(&mut self) -> io::Result<Option<Option<String>>> {
+        while self.buffer.len() < 2 {
+            self.fill_or_eof().await?;
+        }
+        if self.buffer.starts_with(b"--") {
+            return Ok(None);
+        }
+
+        let end = loop {
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buffer.len() > MAX_PART_HEADERS {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "multipart headers too long",
+                ));
+            }
+            self.fill_or_eof().await?;
+        };
+        let headers = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+        self.buffer.drain(..end + 4);
+
+        let file_name = headers
+            .split("\r\n")
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-disposition"))
+            .and_then(|(_, value)| {
+                value.split(';').find_map(|param| {
+                    let (key, value) = param.trim().split_once('=')?;
+                    (key == "filename").then(|| value.trim_matches('"').to_string())
+                })
+            });
+        Ok(Some(file_name))
+    }
+
+    /// Copy the current part's content to `writer`, consuming the delimiter
+    /// that ends it.
+    async fn copy_part(&mut self, writer: &mut (impl AsyncWrite + Unpin)) -> io::Result<u64> {
+        let mut copied = 0;
+        loop {
+            if let Some(at) = find(&self.buffer, &self.delimiter) {
+                writer.write_all(&self.buffer[..at]).await?;
+                self.buffer.drain(..at + self.delimiter.len());
+                return Ok(copied + at as u64);
+            }
+            // Everything but a possible delimiter prefix at the end is content.
+            let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            writer.write_all(&self.buffer[..safe]).await?;
+            self.buffer.drain(..safe);
+            copied += safe as u64;
+            self.fill_or_eof().await?;
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -1,4 +1,11 @@
 use crate::tls::Tls;
+#[cfg(any(
+    feature = "native-tls",
+    feature = "openssl",
+    feature = "rustls",
+    feature = "h3"
+))]
+mod self_signed;
 use clap::Args;
 #[cfg(all(unix, any(feature = "serve", feature = "proxy")))]
 use std::os::fd::AsFd;
@@ -24,7 +31,7 @@ pub struct ServerTls {
     /// both cert and key enables tls.
     ///
     /// Example: `--cert ./cert.pem --key ./key.pem`
-    /// For development, try using mkcert or rcgen
+    /// For development, try --tls-self-signed
     #[cfg(any(
         feature = "native-tls",
         feature = "openssl",
@@ -40,7 +47,7 @@ pub struct ServerTls {
     /// both cert and key enables tls.
     ///
     /// Example: `--cert ./cert.pem --key ./key.pem`
-    /// For development, try using mkcert or rcgen
+    /// For development, try --tls-self-signed
     #[cfg(any(
         feature = "native-tls",
         feature = "openssl",
@@ -50,6 +57,47 @@ pub struct ServerTls {
     #[arg(long, env, requires = "cert")]
     key: Option<PathBuf>,
 
+    /// Serve https with a generated certificate for localhost, this machine's
+    /// names and network addresses, and the bind host
+    ///
+    /// Cached in the config directory (e.g. ~/.config/trillium/tls) and
+    /// reused while it still covers them, so a browser told to trust it once
+    /// keeps trusting it.
+    #[cfg(any(
+        feature = "native-tls",
+        feature = "openssl",
+        feature = "rustls",
+        feature = "h3"
+    ))]
+    #[arg(long, env, conflicts_with_all = ["cert", "key"])]
+    tls_self_signed: bool,
+
+    /// Sign the --tls-self-signed certificate with a local certificate
+    /// authority, created on first use
+    ///
+    /// Install the authority's certificate (ca.pem beside the cached
+    /// certificate, or see --tls-export-ca) in a browser or OS trust store
+    /// once, and every certificate it signs is trusted.
+    #[cfg(any(
+        feature = "native-tls",
+        feature = "openssl",
+        feature = "rustls",
+        feature = "h3"
+    ))]
+    #[arg(long, env, requires = "tls_self_signed")]
+    tls_local_ca: bool,
+
+    /// Copy the local certificate authority's certificate to PATH, for
+    /// installing in a trust store
+    #[cfg(any(
+        feature = "native-tls",
+        feature = "openssl",
+        feature = "rustls",
+        feature = "h3"
+    ))]
+    #[arg(long, env, value_name = "PATH", requires = "tls_local_ca")]
+    tls_export_ca: Option<PathBuf>,
+
     #[arg(long, env, value_enum, default_value_t)]
     tls: Tls,
 }
@@ -78,6 +126,123 @@ impl ServerTls {
         ))
     }
 
+    /// Under `--tls-self-signed`, load or generate the certificate for `host`
+    /// and serve it as though it had been passed with `--cert` and `--key`.
+    /// Exits if it can't.
+    pub(crate) fn prepare_self_signed(&mut self, host: &str) {
+        #[cfg(any(
+            feature = "native-tls",
+            feature = "openssl",
+            feature = "rustls",
+            feature = "h3"
+        ))]
+        if self.tls_self_signed && !matches!(self.tls, Tls::None) {
+            let self_signed = match self_signed::load_or_generate(host, self.tls_local_ca) {
+                Ok(self_signed) => self_signed,
+                Err(e) => {
+                    eprintln!("could not set up a self-signed certificate: {e}");
+                    std::process::exit(1);
+                }
+            };
+            let dir = self_signed
+                .cert
+                .parent()
+                .unwrap_or(&self_signed.cert)
+                .display()
+                .to_string();
+            if self_signed.generated {
+                println!(
+                    "generated a tls certificate for {} in {dir}",
+                    self_signed.names.join(", ")
+                );
+            } else {
+                println!("using the cached tls certificate in {dir}");
+            }
+            if !self_signed.uncovered.is_empty() {
+                println!(
+                    "it doesn't cover {}, so browsers will refuse those names; delete {} to \
+                     generate one that does",
+                    self_signed.uncovered.join(", "),
+                    self_signed.cert.display()
+                );
+            }
+            match (&self_signed.ca, &self.tls_export_ca) {
+                (Some(ca), Some(export)) => match fs::copy(ca, export) {
+                    Ok(_) => println!(
+                        "exported the local certificate authority to {}; trust it once to \
+                         have browsers accept the certificate",
+                        export.display()
+                    ),
+                    Err(e) => {
+                        eprintln!("could not export the local CA to {}: {e}", export.display());
+                        std::process::exit(1);
+                    }
+                },
+                (Some(ca), None) => println!(
+                    "trust the local certificate authority {} once to have browsers accept \
+                     the certificate",
+                    ca.display()
+                ),
+                (None, _) => {
+                    println!("browsers will ask before trusting the self-signed certificate")
+                }
+            }
+            self.cert = Some(self_signed.cert);
+            self.key = Some(self_signed.key);
+        }
+        #[cfg(not(any(
+            feature = "native-tls",
+            feature = "openssl",
+            feature = "rustls",
+            feature = "h3"
+        )))]
+        let _ = host;
+    }
+
+    /// Whether [`run_with_tls`](Self::run_with_tls) will serve https: a cert
+    /// and key were given and `--tls` isn't `none`.
+    #[cfg(feature = "serve")]
+    pub(crate) fn is_tls(&self) -> bool {
+        #[cfg(any(
+            feature = "native-tls",
+            feature = "openssl",
+            feature = "rustls",
+            feature = "h3"
+        ))]
+        let has_cert_and_key = self.cert.is_some() && self.key.is_some();
+        #[cfg(not(any(
+            feature = "native-tls",
+            feature = "openssl",
+            feature = "rustls",
+            feature = "h3"
+        )))]
+        let has_cert_and_key = false;
+        has_cert_and_key && !matches!(self.tls, Tls::None)
+    }
+
+    /// The certificate file's contents when serving https, for `--lan` to
+    /// check its names against the addresses it prints.
+    #[cfg(feature = "serve-lan")]
+    pub(crate) fn cert(&self) -> Option<Vec<u8>> {
+        if !self.is_tls() {
+            return None;
+        }
+        #[cfg(any(
+            feature = "native-tls",
+            feature = "openssl",
+            feature = "rustls",
+            feature = "h3"
+        ))]
+        return fs::read(self.cert.as_deref()?).ok();
+        #[cfg(not(any(
+            feature = "native-tls",
+            feature = "openssl",
+            feature = "rustls",
+            feature = "h3"
+        )))]
+        None
+    }
+
     #[cfg(any(feature = "serve", feature = "proxy"))]
     pub(crate) fn run_with_tls<S: trillium_server_common::Server>(
         &self,
@@ -0,0 +1,301 @@
+//! `--tls-self-signed`: a development certificate generated at startup.
+//!
+//! The certificate names `localhost`, the loopback addresses, this machine's
+//! hostname (and its `.local` mDNS name), the bind host and every network
+//! address, so phones on the LAN and `curl https://localhost` alike can
+//! connect. It's cached in the user's config directory and reused for as long
+//! as it still covers `localhost` and the bind host and isn't about to expire:
+//! a browser told to trust it once keeps trusting it across restarts. Network
+//! addresses come and go with the network, so one the cached certificate
+//! doesn't name gets a warning rather than a new certificate to trust.
+//!
+//! With `--tls-local-ca`, the certificate is instead signed by a local
+//! certificate authority created on first use. Installing the authority in a
+//! trust store once makes every certificate it signs trusted, so a new one is
+//! issued whenever the addresses change, and whenever the authority itself is
+//! replaced.
+
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair,
+    KeyUsagePurpose,
+};
+use std::{
+    collections::HashSet,
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+use time::{Duration, OffsetDateTime};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, pem::Pem};
+
+/// How long a generated server certificate is valid. Apple platforms refuse
+/// server certificates valid for longer, even from a locally trusted authority.
+const CERT_DAYS: i64 = 825;
+
+/// How long the local certificate authority is valid.
+const CA_DAYS: i64 = 3650;
+
+/// A cached certificate this close to expiry is replaced.
+const RENEW_DAYS: i64 = 30;
+
+/// A certificate and key ready to serve, and where they came from.
+#[derive(Debug)]
+pub(super) struct SelfSigned {
+    pub(super) cert: PathBuf,
+    pub(super) key: PathBuf,
+    /// The local authority's certificate, under `--tls-local-ca`.
+    pub(super) ca: Option<PathBuf>,
+    pub(super) names: Vec<String>,
+    /// Whether the certificate was made just now rather than read from the
+    /// cache.
+    pub(super) generated: bool,
+    /// The network addresses a cached certificate doesn't name.
+    pub(super) uncovered: Vec<String>,
+}
+
+/// The directory certificates are cached in.
+fn cache_dir() -> Result<PathBuf, String> {
+    dirs::config_dir()
+        .map(|dir| dir.join("trillium").join("tls"))
+        .ok_or_else(|| "could not find a config directory to keep the certificate in".into())
+}
+
+/// Load the cached certificate for `host`, or generate (and cache) one.
+pub(super) fn load_or_generate(host: &str, local_ca: bool) -> Result<SelfSigned, String> {
+    let dir = cache_dir()?;
+    fs::create_dir_all(&dir).map_err(|e| format!("could not create {}: {e}", dir.display()))?;
+    let names = names(host);
+    // Replacing a certificate from the local CA costs nothing, but replacing a
+    // self-signed one means trusting it all over again.
+    let required = if local_ca {
+        names.clone()
+    } else {
+        local_names(host)
+    };
+
+    let (stem, ca) = if local_ca {
+        ("ca-signed", Some(load_or_create_ca(&dir)?))
+    } else {
+        ("self-signed", None)
+    };
+    let cert = dir.join(format!("{stem}.pem"));
+    let key = dir.join(format!("{stem}-key.pem"));
+
+    let cached = fs::read(&cert)
+        .ok()
+        .filter(|_| key.exists())
+        .and_then(|pem| {
+            with_certificate(&pem, |cert| {
+                // Signed by this CA's key, not just by one with its name: a
+                // replaced CA leaves certificates from its predecessor behind.
+                let issued_by_ca = match &ca {
+                    Some(ca) => with_certificate(ca.cert.as_bytes(), |ca| {
+                        cert.verify_signature(Some(ca.public_key())).is_ok()
+                    }),
+                    None => Some(true),
+                };
+                (issued_by_ca == Some(true)
+                    && is_current(cert)
+                    && uncovered(cert, &required).is_empty())
+                .then(|| uncovered(cert, &names))
+            })
+            .flatten()
+        });
+
+    if cached.is_none() {
+        let key_pair = KeyPair::generate().map_err(|e| e.to_string())?;
+        let params = server_params(&names).map_err(|e| e.to_string())?;
+        let certificate = match &ca {
+            Some(ca) => {
+                let ca_key = KeyPair::from_pem(&ca.key).map_err(|e| e.to_string())?;
+                let issuer =
+                    Issuer::from_ca_cert_pem(&ca.cert, ca_key).map_err(|e| e.to_string())?;
+                params.signed_by(&key_pair, &issuer)
+            }
+            None => params.self_signed(&key_pair),
+        }
+        .map_err(|e| e.to_string())?;
+        write_private(&key, &key_pair.serialize_pem())?;
+        fs::write(&cert, certificate.pem())
+            .map_err(|e| format!("could not write {}: {e}", cert.display()))?;
+    }
+
+    Ok(SelfSigned {
+        cert,
+        key,
+        ca: ca.map(|ca| ca.path),
+        names,
+        generated: cached.is_none(),
+        uncovered: cached.unwrap_or_default(),
+    })
+}
+
+/// The names a cached self-signed certificate must cover to be reused:
+/// `localhost`, the loopback addresses and the bind host.
+fn local_names(host: &str) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".into(), "::1".into()];
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) if ip.is_unspecified() => {}
+        Ok(ip) => names.push(ip.to_string()),
+        Err(_) if !host.is_empty() => names.push(host.to_ascii_lowercase()),
+        Err(_) => {}
+    }
+    dedup(names)
+}
+
+/// Every name the certificate should cover, deduplicated in order: the
+/// [`local_names`], this machine's hostname and its network addresses.
+fn names(host: &str) -> Vec<String> {
+    let mut names = local_names(host);
+
+    if let Some(hostname) = hostname::get()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+        .filter(|name| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        })
+    {
+        let short = hostname.split('.').next().unwrap_or(&hostname).to_string();
+        names.push(format!("{short}.local"));
+        names.push(short);
+        names.push(hostname);
+    }
+
+    for interface in if_addrs::get_if_addrs().unwrap_or_default() {
+        if !interface.is_loopback() && !interface.is_link_local() {
+            names.push(interface.ip().to_string());
+        }
+    }
+
+    dedup(names)
+}
+
+/// `names` without repeats, ignoring case, in order.
+fn dedup(mut names: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    names.retain(|name| seen.insert(name.to_ascii_lowercase()));
+    names
+}
+
+/// The parameters for a server certificate covering `names`.
+fn server_params(names: &[String]) -> Result<CertificateParams, rcgen::Error> {
+    let mut params = CertificateParams::new(names.to_vec())?;
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "trillium development certificate");
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, "trillium");
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(CERT_DAYS);
+    Ok(params)
+}
+
+/// The local certificate authority, as PEM.
+struct LocalCa {
+    path: PathBuf,
+    cert: String,
+    key: String,
+}
+
+fn load_or_create_ca(dir: &Path) -> Result<LocalCa, String> {
+    let path = dir.join("ca.pem");
+    let key_path = dir.join("ca-key.pem");
+
+    if let (Ok(cert), Ok(key)) = (fs::read_to_string(&path), fs::read_to_string(&key_path))
+        && with_certificate(cert.as_bytes(), is_current) == Some(true)
+    {
+        return Ok(LocalCa { path, cert, key });
+    }
+
+    let key_pair = KeyPair::generate().map_err(|e| e.to_string())?;
+    let mut params = CertificateParams::default();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "trillium local development CA");
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, "trillium");
+    // May sign server certificates, but no further authorities.
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(CA_DAYS);
+    let cert = params
+        .self_signed(&key_pair)
+        .map_err(|e| e.to_string())?
+        .pem();
+    let key = key_pair.serialize_pem();
+
+    write_private(&key_path, &key)?;
+    fs::write(&path, &cert).map_err(|e| format!("could not write {}: {e}", path.display()))?;
+    Ok(LocalCa { path, cert, key })
+}
+
+/// Parse the first certificate in `pem` and hand it to `f`; `None` if it
+/// doesn't parse.
+fn with_certificate<T>(pem: &[u8], f: impl FnOnce(&X509Certificate<'_>) -> T) -> Option<T> {
+    let pem = Pem::iter_from_buffer(pem).next()?.ok()?;
+    let cert = pem.parse_x509().ok()?;
+    Some(f(&cert))
+}
+
+/// Whether the certificate is valid now and for at least [`RENEW_DAYS`] more.
+fn is_current(cert: &X509Certificate<'_>) -> bool {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let validity = cert.validity();
+    validity.not_before.timestamp() <= now
+        && validity.not_after.timestamp() > now + Duration::days(RENEW_DAYS).whole_seconds()
+}
+
+/// Those of `names` the certificate's subject alternative names leave out.
+fn uncovered(cert: &X509Certificate<'_>, names: &[String]) -> Vec<String> {
+    let Ok(Some(sans)) = cert.subject_alternative_name() else {
+        return names.to_vec();
+    };
+    let covered = sans
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match *name {
+            GeneralName::DNSName(name) => Some(name.to_ascii_lowercase()),
+            GeneralName::IPAddress(&[a, b, c, d]) => Some(IpAddr::from([a, b, c, d]).to_string()),
+            GeneralName::IPAddress(octets) => <[u8; 16]>::try_from(octets)
+                .ok()
+                .map(|octets| IpAddr::from(octets).to_string()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    names
+        .iter()
+        .filter(|name| !covered.contains(&name.to_ascii_lowercase()))
+        .cloned()
+        .collect()
+}
+
+/// Write a private key readable only by its owner.
+fn write_private(path: &Path, pem: &str) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(
+        &mut options
+            .open(path)
+            .map_err(|e| format!("could not write {}: {e}", path.display()))?,
+        pem.as_bytes(),
+    )
+    .map_err(|e| format!("could not write {}: {e}", path.display()))
+}